@@ -1,5 +1,7 @@
 use async_openai::error::OpenAIError;
 use llm_chain::prompt::StringTemplateError;
+use llm_chain::tokens::TokenizerError;
+use llm_chain::traits::ExecutorError;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -9,4 +11,72 @@ pub enum OpenAIInnerError {
     OpenAIError(#[from] OpenAIError),
     #[error(transparent)]
     StringTemplateError(#[from] StringTemplateError),
+    #[error("The option {0} can't be honored by the OpenAI API")]
+    InvalidOption(String),
+    #[error(transparent)]
+    TokenizerError(#[from] TokenizerError),
+}
+
+impl From<OpenAIInnerError> for ExecutorError {
+    fn from(e: OpenAIInnerError) -> Self {
+        match e {
+            OpenAIInnerError::OpenAIError(e) => classify_openai_error(e),
+            OpenAIInnerError::InvalidOption(_) => ExecutorError::InvalidOptions,
+            e => ExecutorError::InnerError(e.into()),
+        }
+    }
+}
+
+/// Sorts an error from the OpenAI client into the retryable classes of `ExecutorError`.
+fn classify_openai_error(e: OpenAIError) -> ExecutorError {
+    match &e {
+        OpenAIError::Reqwest(err) => match err.status() {
+            Some(status) if status.as_u16() == 429 => ExecutorError::RateLimited(e.into()),
+            Some(status) if status.is_server_error() => ExecutorError::Server(e.into()),
+            Some(_) => ExecutorError::InnerError(e.into()),
+            None if err.is_timeout() || err.is_connect() || err.is_request() => {
+                ExecutorError::Network(e.into())
+            }
+            None => ExecutorError::InnerError(e.into()),
+        },
+        OpenAIError::ApiError(err) => {
+            let code = err.code.as_ref().and_then(|code| code.as_str());
+            match (err.r#type.as_deref(), code) {
+                (_, Some("rate_limit_exceeded")) | (Some("requests" | "tokens"), _) => {
+                    ExecutorError::RateLimited(e.into())
+                }
+                (Some("server_error"), _) => ExecutorError::Server(e.into()),
+                _ => ExecutorError::InnerError(e.into()),
+            }
+        }
+        OpenAIError::StreamError(_) => ExecutorError::Network(e.into()),
+        _ => ExecutorError::InnerError(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::error::ApiError;
+
+    fn api_error(r#type: &str, code: Option<&str>) -> OpenAIInnerError {
+        OpenAIInnerError::OpenAIError(OpenAIError::ApiError(ApiError {
+            message: "error".to_string(),
+            r#type: Some(r#type.to_string()),
+            param: None,
+            code: code.map(|c| c.into()),
+        }))
+    }
+
+    #[test]
+    fn test_classifies_api_errors() {
+        let err: ExecutorError = api_error("requests", Some("rate_limit_exceeded")).into();
+        assert!(matches!(err, ExecutorError::RateLimited(_)));
+        let err: ExecutorError = api_error("server_error", None).into();
+        assert!(matches!(err, ExecutorError::Server(_)));
+        let err: ExecutorError = api_error("insufficient_quota", None).into();
+        assert!(!err.is_retryable());
+        let err: ExecutorError = OpenAIInnerError::InvalidOption("TopP".to_string()).into();
+        assert!(matches!(err, ExecutorError::InvalidOptions));
+    }
 }
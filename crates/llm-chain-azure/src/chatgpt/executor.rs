@@ -1,10 +1,12 @@
 use super::error::OpenAIInnerError;
 use super::prompt::completion_to_output;
+use super::prompt::create_completion_with_logprobs;
 use super::prompt::stream_to_output;
 use async_openai::config::AzureConfig;
 use async_openai::types::ChatCompletionRequestMessage;
 
 use async_openai::types::ChatCompletionRequestUserMessageContent;
+use llm_chain::options::Capabilities;
 use llm_chain::options::Opt;
 use llm_chain::options::Options;
 use llm_chain::options::OptionsCascade;
@@ -33,6 +35,8 @@ use std::sync::Arc;
 pub struct Executor {
     /// The client used to communicate with the OpenAI API.
     client: Arc<async_openai::Client<AzureConfig>>,
+    /// The HTTP client used for requests `async_openai` can't express, such as log-probabilities.
+    http_client: reqwest::Client,
     /// The per-invocation options for this executor.
     options: Options,
 }
@@ -56,7 +60,11 @@ impl Default for Executor {
 
         let client = Arc::new( async_openai::Client::with_config(config));
 
-        Self { client, options }
+        Self {
+            client,
+            http_client: reqwest::Client::new(),
+            options,
+        }
     }
 }
 
@@ -118,24 +126,37 @@ impl traits::Executor for Executor {
         // dbg!(cfg.clone());
 
         let client = Arc::new(async_openai::Client::with_config(cfg));
-        Ok(Self { client, options })
+        Ok(Self {
+            client,
+            http_client: reqwest::Client::new(),
+            options,
+        })
     }
 
     async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
         let opts = self.cascade(Some(options));
         let client: Arc<async_openai::Client<AzureConfig>> = self.client.clone();
         let model = self.get_model_from_invocation_options(&opts);
-        let input = create_chat_completion_request(model, prompt, opts.is_streaming()).unwrap();
+        let tokenizer = OpenAITokenizer::new(opts.clone());
+        let input = create_chat_completion_request(model, prompt, &opts, &tokenizer)?;
         // dbg!(client.clone());
         if opts.is_streaming() {
             let res = async move { client.chat().create_stream(input).await }
                 .await
-                .map_err(|e| ExecutorError::InnerError(e.into()))?;
+                .map_err(OpenAIInnerError::from)?;
             Ok(stream_to_output(res))
+        } else if let Some(top_logprobs) = opts.requested_logprobs() {
+            Ok(create_completion_with_logprobs(
+                &self.http_client,
+                self.client.config(),
+                input,
+                top_logprobs,
+            )
+            .await?)
         } else {
             let res = async move { client.chat().create(input).await }
                 .await
-                .map_err(|e| ExecutorError::InnerError(e.into()))?;
+                .map_err(OpenAIInnerError::from)?;
             Ok(completion_to_output(res))
         }
     }
@@ -175,6 +196,10 @@ impl traits::Executor for Executor {
     fn get_tokenizer(&self, options: &Options) -> Result<OpenAITokenizer, TokenizerError> {
         Ok(OpenAITokenizer::new(self.cascade(Some(options))))
     }
+
+    fn capabilities(&self) -> Capabilities {
+        super::prompt::capabilities()
+    }
 }
 
 fn num_tokens_from_messages(
@@ -1,16 +1,29 @@
+use async_openai::config::Config;
+use async_openai::error::{ApiError, OpenAIError};
 use async_openai::types::{
+    ChatCompletionFunctionsArgs, ChatCompletionMessageToolCall,
     ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestFunctionMessageArgs,
     ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
     ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs,
-    ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
-    CreateChatCompletionResponse, Role,
+    ChatCompletionResponseStream, ChatCompletionTool, ChatCompletionToolArgs,
+    ChatCompletionToolType, CompletionUsage, CreateChatCompletionRequest,
+    CreateChatCompletionRequestArgs, CreateChatCompletionResponse, FinishReason, FunctionCall,
+    Role, Stop,
 };
 use futures::StreamExt;
-use llm_chain::prompt::{self, Prompt};
+use llm_chain::options::{Capabilities, Opt, OptDiscriminants, OptionsCascade, TokenBias};
+use llm_chain::prompt::{self, Prompt, ToolCall};
+use llm_chain::tokens::Tokenizer;
+use llm_chain::tools::ToolDescription;
+use llm_chain::traits::ExecutorError;
 use llm_chain::{
-    output::{Output, StreamSegment},
+    output::{
+        self, Candidate, Output, ResponseMetadata, StreamSegment, TokenLogprob, ToolCallDelta,
+    },
     prompt::{ChatMessage, ChatMessageCollection},
 };
+use serde::Deserialize;
+use std::collections::HashMap;
 
 use super::error::OpenAIInnerError;
 
@@ -19,6 +32,7 @@ fn convert_role(role: &prompt::ChatRole) -> Role {
         prompt::ChatRole::User => Role::User,
         prompt::ChatRole::Assistant => Role::Assistant,
         prompt::ChatRole::System => Role::System,
+        prompt::ChatRole::Tool => Role::Tool,
         prompt::ChatRole::Other(_s) => Role::User, // other roles are not supported by OpenAI
     }
 }
@@ -28,7 +42,7 @@ fn convert_openai_role(role: &Role) -> prompt::ChatRole {
         Role::User => prompt::ChatRole::User,
         Role::Assistant => prompt::ChatRole::Assistant,
         Role::System => prompt::ChatRole::System,
-        Role::Tool => prompt::ChatRole::Other("Tool".to_string()),
+        Role::Tool => prompt::ChatRole::Tool,
         Role::Function => prompt::ChatRole::Other("Function".to_string()),
     }
 }
@@ -39,6 +53,26 @@ fn format_chat_message(
     let role = convert_role(message.role());
     let content = message.body().to_string();
     let msg = match role {
+        Role::Assistant if !message.tool_calls().is_empty() => {
+            let tool_calls = message
+                .tool_calls()
+                .iter()
+                .map(|call| ChatCompletionMessageToolCall {
+                    id: call.id.clone(),
+                    r#type: ChatCompletionToolType::Function,
+                    function: FunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    },
+                })
+                .collect::<Vec<_>>();
+            let mut args = ChatCompletionRequestAssistantMessageArgs::default();
+            args.tool_calls(tool_calls);
+            if !content.is_empty() {
+                args.content(content);
+            }
+            ChatCompletionRequestMessage::Assistant(args.build()?)
+        }
         Role::Assistant => ChatCompletionRequestMessage::Assistant(
             ChatCompletionRequestAssistantMessageArgs::default()
                 .content(content)
@@ -57,6 +91,7 @@ fn format_chat_message(
         Role::Tool => ChatCompletionRequestMessage::Tool(
             ChatCompletionRequestToolMessageArgs::default()
                 .content(content)
+                .tool_call_id(message.tool_call_id().unwrap_or_default())
                 .build()?,
         ),
         Role::Function => ChatCompletionRequestMessage::Function(
@@ -74,35 +109,335 @@ pub fn format_chat_messages(
     messages.iter().map(format_chat_message).collect()
 }
 
-pub fn create_chat_completion_request(
+/// Returns the options `create_chat_completion_request` and the executors use, with the values the
+/// API accepts.
+pub fn capabilities() -> Capabilities {
+    Capabilities::new()
+        .supports(OptDiscriminants::Model)
+        .supports(OptDiscriminants::ApiKey)
+        .supports(OptDiscriminants::Stream)
+        .supports_between(OptDiscriminants::NChoices, 1.0, 128.0)
+        .supports(OptDiscriminants::Logprobs)
+        .supports_between(OptDiscriminants::TopLogprobs, 0.0, 20.0)
+        .supports_between(OptDiscriminants::MaxTokens, 0.0, u16::MAX as f64)
+        .supports_between(OptDiscriminants::Temperature, 0.0, 2.0)
+        .supports_between(OptDiscriminants::TopP, 0.0, 1.0)
+        .supports_at_most(OptDiscriminants::StopSequence, 4)
+        .supports_between(OptDiscriminants::FrequencyPenalty, -2.0, 2.0)
+        .supports_between(OptDiscriminants::PresencePenalty, -2.0, 2.0)
+        .supports(OptDiscriminants::TokenBias)
+        .supports(OptDiscriminants::TextBias)
+        .supports(OptDiscriminants::User)
+        .supports(OptDiscriminants::Tools)
+        .supports(OptDiscriminants::AzureDeployment)
+        .supports(OptDiscriminants::AzureBaseUrl)
+        .supports(OptDiscriminants::AzureApiVersion)
+}
+
+/// Creates the request for `prompt`. `tokenizer` resolves the `TextBias` option into tokens.
+pub fn create_chat_completion_request<T: Tokenizer>(
     model: String,
     prompt: &Prompt,
-    is_streaming: bool,
+    opts: &OptionsCascade,
+    tokenizer: &T,
 ) -> Result<CreateChatCompletionRequest, OpenAIInnerError> {
+    // Options the API has no parameter for would otherwise be dropped silently.
+    if let Some(issue) = capabilities().validate(opts).into_iter().next() {
+        return Err(OpenAIInnerError::InvalidOption(format!(
+            "{:?}",
+            issue.option
+        )));
+    }
     let messages = format_chat_messages(prompt.to_chat())?;
-    Ok(CreateChatCompletionRequestArgs::default()
+    let mut request = CreateChatCompletionRequestArgs::default();
+    request
         .model(model)
-        .stream(is_streaming)
-        .messages(messages)
+        .stream(opts.is_streaming())
+        .messages(messages);
+
+    if let Some(Opt::NChoices(n)) = opts.get(OptDiscriminants::NChoices) {
+        // Only the first candidate is read from streamed responses.
+        let n = u8::try_from(*n)
+            .ok()
+            .filter(|n| (1..=128).contains(n) && (*n == 1 || !opts.is_streaming()))
+            .ok_or_else(|| OpenAIInnerError::InvalidOption("NChoices".to_string()))?;
+        request.n(n);
+    }
+    // Log-probabilities are requested through `create_completion_with_logprobs`, as the request
+    // type has no field for them. They are not available when streaming.
+    if let Some(top_logprobs) = opts.requested_logprobs() {
+        if opts.is_streaming() || top_logprobs > 20 {
+            return Err(OpenAIInnerError::InvalidOption("Logprobs".to_string()));
+        }
+    }
+    if let Some(Opt::MaxTokens(max_tokens)) = opts.get(OptDiscriminants::MaxTokens) {
+        let max_tokens = u16::try_from(*max_tokens)
+            .map_err(|_| OpenAIInnerError::InvalidOption("MaxTokens".to_string()))?;
+        request.max_tokens(max_tokens);
+    }
+    if let Some(Opt::Temperature(temperature)) = opts.get(OptDiscriminants::Temperature) {
+        request.temperature(in_range("Temperature", *temperature, 0.0, 2.0)?);
+    }
+    if let Some(Opt::TopP(top_p)) = opts.get(OptDiscriminants::TopP) {
+        request.top_p(in_range("TopP", *top_p, 0.0, 1.0)?);
+    }
+    if let Some(Opt::StopSequence(stop)) = opts.get(OptDiscriminants::StopSequence) {
+        // OpenAI accepts between one and four stop sequences.
+        if stop.len() > 4 {
+            return Err(OpenAIInnerError::InvalidOption("StopSequence".to_string()));
+        }
+        if !stop.is_empty() {
+            request.stop(Stop::StringArray(stop.clone()));
+        }
+    }
+    if let Some(Opt::FrequencyPenalty(penalty)) = opts.get(OptDiscriminants::FrequencyPenalty) {
+        request.frequency_penalty(in_range("FrequencyPenalty", *penalty, -2.0, 2.0)?);
+    }
+    if let Some(Opt::PresencePenalty(penalty)) = opts.get(OptDiscriminants::PresencePenalty) {
+        request.presence_penalty(in_range("PresencePenalty", *penalty, -2.0, 2.0)?);
+    }
+    if let Some(bias) = opts.token_bias(tokenizer)? {
+        request.logit_bias(token_bias_to_logit_bias(&bias)?);
+    }
+    if let Some(Opt::User(user)) = opts.get(OptDiscriminants::User) {
+        request.user(user.clone());
+    }
+    if let Some(Opt::Tools(tools)) = opts.get(OptDiscriminants::Tools) {
+        if !tools.is_empty() {
+            request.tools(
+                tools
+                    .iter()
+                    .map(format_tool)
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+        }
+    }
+    Ok(request.build()?)
+}
+
+/// Declares a tool as an OpenAI function the model may call.
+fn format_tool(tool: &ToolDescription) -> Result<ChatCompletionTool, OpenAIInnerError> {
+    let description = if tool.description_context.is_empty() {
+        tool.description.clone()
+    } else {
+        format!("{}\n{}", tool.description, tool.description_context)
+    };
+    Ok(ChatCompletionToolArgs::default()
+        .function(
+            ChatCompletionFunctionsArgs::default()
+                .name(tool.name.clone())
+                .description(description)
+                .parameters(tool.input_json_schema())
+                .build()?,
+        )
         .build()?)
 }
 
+/// Checks that `value` lies within the range accepted by the API for the option `name`.
+fn in_range(name: &str, value: f32, min: f32, max: f32) -> Result<f32, OpenAIInnerError> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(OpenAIInnerError::InvalidOption(name.to_string()))
+    }
+}
+
+/// Converts a `TokenBias` into the `logit_bias` map expected by OpenAI, which is keyed by the
+/// token id as a string and accepts biases between -100 and 100.
+fn token_bias_to_logit_bias(
+    bias: &TokenBias,
+) -> Result<HashMap<String, serde_json::Value>, OpenAIInnerError> {
+    bias.iter()
+        .map(|(token, value)| {
+            let token = token
+                .to_usize()
+                .or_else(|| token.to_i32().and_then(|t| usize::try_from(t).ok()))
+                .ok_or_else(|| OpenAIInnerError::InvalidOption("TokenBias".to_string()))?;
+            let value = in_range("TokenBias", *value, -100.0, 100.0)?;
+            Ok((token.to_string(), value.into()))
+        })
+        .collect()
+}
+
+fn convert_finish_reason(reason: &FinishReason) -> output::FinishReason {
+    match reason {
+        FinishReason::Stop => output::FinishReason::Stop,
+        FinishReason::Length => output::FinishReason::Length,
+        FinishReason::ToolCalls | FinishReason::FunctionCall => output::FinishReason::ToolCalls,
+        FinishReason::ContentFilter => output::FinishReason::ContentFilter,
+    }
+}
+
+fn convert_usage(usage: &CompletionUsage) -> output::TokenUsage {
+    output::TokenUsage {
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+        total_tokens: usage.total_tokens,
+    }
+}
+
 pub fn completion_to_output(resp: CreateChatCompletionResponse) -> Output {
-    let msg = resp.choices.first().unwrap().message.clone();
-    let mut col = ChatMessageCollection::new();
-    col.add_message(ChatMessage::new(
-        convert_openai_role(&msg.role),
-        msg.content.unwrap_or_default(), // "" for missing
-    ));
-    Output::new_immediate(col.into())
+    response_to_output(resp, Vec::new())
+}
+
+/// Converts a response into an output with a candidate per choice, attaching `logprobs` to the
+/// choices in order.
+fn response_to_output(
+    resp: CreateChatCompletionResponse,
+    mut logprobs: Vec<Option<Vec<TokenLogprob>>>,
+) -> Output {
+    let choice = resp.choices.first().unwrap();
+    let metadata = ResponseMetadata {
+        id: Some(resp.id.clone()),
+        model: Some(resp.model.clone()),
+        finish_reason: choice.finish_reason.as_ref().map(convert_finish_reason),
+        usage: resp.usage.as_ref().map(convert_usage),
+    };
+    logprobs.resize(resp.choices.len(), None);
+    let candidates = resp
+        .choices
+        .into_iter()
+        .zip(logprobs)
+        .map(|(choice, logprobs)| {
+            let msg = choice.message;
+            let tool_calls = msg
+                .tool_calls
+                .unwrap_or_default()
+                .into_iter()
+                .map(|call| ToolCall::new(call.id, call.function.name, call.function.arguments))
+                .collect();
+            let mut col = ChatMessageCollection::new();
+            col.add_message(
+                ChatMessage::new(
+                    convert_openai_role(&msg.role),
+                    msg.content.unwrap_or_default(), // "" for missing
+                )
+                .with_tool_calls(tool_calls),
+            );
+            Candidate {
+                content: col.into(),
+                finish_reason: choice.finish_reason.as_ref().map(convert_finish_reason),
+                logprobs,
+            }
+        })
+        .collect();
+    Output::new_immediate_with_candidates(candidates, metadata)
+}
+
+/// The parts of a chat completion response that carry log-probabilities.
+#[derive(Deserialize)]
+struct LogprobsResponse {
+    choices: Vec<LogprobsChoice>,
+}
+
+#[derive(Deserialize)]
+struct LogprobsChoice {
+    logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Deserialize)]
+struct ChoiceLogprobs {
+    content: Option<Vec<ChatTokenLogprob>>,
+}
+
+#[derive(Deserialize)]
+struct ChatTokenLogprob {
+    token: String,
+    logprob: f32,
+    #[serde(default)]
+    top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Deserialize)]
+struct TopLogprob {
+    token: String,
+    logprob: f32,
+}
+
+#[derive(Deserialize)]
+struct WrappedError {
+    error: ApiError,
+}
+
+/// Converts the JSON body of a chat completion response, including log-probabilities, into an
+/// output.
+fn completion_with_logprobs_to_output(body: &[u8]) -> Result<Output, OpenAIInnerError> {
+    let resp: CreateChatCompletionResponse =
+        serde_json::from_slice(body).map_err(OpenAIError::JSONDeserialize)?;
+    let with_logprobs: LogprobsResponse =
+        serde_json::from_slice(body).map_err(OpenAIError::JSONDeserialize)?;
+    let logprobs = with_logprobs
+        .choices
+        .into_iter()
+        .map(|choice| {
+            let content = choice.logprobs?.content?;
+            Some(
+                content
+                    .into_iter()
+                    .map(|t| TokenLogprob {
+                        token: t.token,
+                        logprob: t.logprob,
+                        top_logprobs: t
+                            .top_logprobs
+                            .into_iter()
+                            .map(|top| (top.token, top.logprob))
+                            .collect(),
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+    Ok(response_to_output(resp, logprobs))
+}
+
+/// Sends `request` with log-probabilities enabled and `top_logprobs` alternatives per token.
+///
+/// The request type of `async_openai` has no fields for log-probabilities, so the request is sent
+/// directly over HTTP, using the endpoint and credentials of `config`.
+pub async fn create_completion_with_logprobs<C: Config>(
+    http_client: &reqwest::Client,
+    config: &C,
+    request: CreateChatCompletionRequest,
+    top_logprobs: usize,
+) -> Result<Output, OpenAIInnerError> {
+    let mut body = serde_json::to_value(request).map_err(OpenAIError::JSONDeserialize)?;
+    body["logprobs"] = true.into();
+    if top_logprobs > 0 {
+        body["top_logprobs"] = top_logprobs.into();
+    }
+    let response = http_client
+        .post(config.url("/chat/completions"))
+        .query(&config.query())
+        .headers(config.headers())
+        .json(&body)
+        .send()
+        .await
+        .map_err(OpenAIError::Reqwest)?;
+    let status_error = response.error_for_status_ref().err();
+    let bytes = response.bytes().await.map_err(OpenAIError::Reqwest)?;
+    if let Some(status_error) = status_error {
+        // Prefer the error object of the API, it tells rate limits apart from other failures.
+        let err = match serde_json::from_slice::<WrappedError>(&bytes) {
+            Ok(wrapped) => OpenAIError::ApiError(wrapped.error),
+            Err(_) => OpenAIError::Reqwest(status_error),
+        };
+        return Err(err.into());
+    }
+    completion_with_logprobs_to_output(&bytes)
 }
 
 pub fn stream_to_output(resp: ChatCompletionResponseStream) -> Output {
     let stream = resp.flat_map(|x| {
-        // Can't unwrap here!!
-        let resp = x.unwrap();
+        let resp = match x {
+            Ok(resp) => resp,
+            Err(e) => {
+                let err = ExecutorError::from(OpenAIInnerError::from(e));
+                return futures::stream::iter(vec![StreamSegment::Err(err)]);
+            }
+        };
 
-        let delta = resp.choices.first().unwrap().delta.clone();
+        let choice = resp.choices.first().unwrap();
+        let delta = choice.delta.clone();
 
         let mut v = vec![];
 
@@ -112,7 +447,276 @@ pub fn stream_to_output(resp: ChatCompletionResponseStream) -> Output {
         if let Some(content) = delta.content {
             v.push(StreamSegment::Content(content))
         }
+        for chunk in delta.tool_calls.unwrap_or_default() {
+            let (name, arguments) = chunk
+                .function
+                .map(|f| (f.name, f.arguments.unwrap_or_default()))
+                .unwrap_or_default();
+            v.push(StreamSegment::ToolCall(ToolCallDelta {
+                index: chunk.index as usize,
+                id: chunk.id,
+                name,
+                arguments,
+            }))
+        }
+        // The API doesn't report usage when streaming, the last chunk only carries the reason.
+        if let Some(reason) = &choice.finish_reason {
+            v.push(StreamSegment::Metadata(ResponseMetadata {
+                id: Some(resp.id.clone()),
+                model: Some(resp.model.clone()),
+                finish_reason: Some(convert_finish_reason(reason)),
+                usage: None,
+            }))
+        }
         futures::stream::iter(v)
     });
     Output::from_stream(stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatgpt::executor::OpenAITokenizer;
+    use llm_chain::options;
+    use llm_chain::options::{Options, TextBias};
+
+    fn tokenizer() -> OpenAITokenizer {
+        OpenAITokenizer::for_model_name("gpt-3.5-turbo")
+    }
+
+    #[test]
+    fn test_create_request_maps_options() {
+        let options = options!(
+            MaxTokens: 100_usize,
+            Temperature: 0.5,
+            StopSequence: vec!["\n".to_string()],
+            User: "alice"
+        );
+        let opts = OptionsCascade::new().with_options(&options);
+        let prompt = Prompt::text("Hello".to_string());
+        let request = create_chat_completion_request(
+            "gpt-3.5-turbo".to_string(),
+            &prompt,
+            &opts,
+            &tokenizer(),
+        )
+        .unwrap();
+        assert_eq!(request.max_tokens, Some(100));
+        assert_eq!(request.temperature, Some(0.5));
+        assert_eq!(
+            request.stop,
+            Some(Stop::StringArray(vec!["\n".to_string()]))
+        );
+        assert_eq!(request.user, Some("alice".to_string()));
+        assert_eq!(request.top_p, None);
+    }
+
+    #[test]
+    fn test_create_request_rejects_unrepresentable_options() {
+        let options = options!(MaxTokens: 100_000_usize);
+        let opts = OptionsCascade::new().with_options(&options);
+        let prompt = Prompt::text("Hello".to_string());
+        let res = create_chat_completion_request(
+            "gpt-3.5-turbo".to_string(),
+            &prompt,
+            &opts,
+            &tokenizer(),
+        );
+        assert!(matches!(res, Err(OpenAIInnerError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_create_request_rejects_unsupported_options() {
+        let options = options!(TopK: 40);
+        let opts = OptionsCascade::new().with_options(&options);
+        let prompt = Prompt::text("Hello".to_string());
+        let res = create_chat_completion_request(
+            "gpt-3.5-turbo".to_string(),
+            &prompt,
+            &opts,
+            &tokenizer(),
+        );
+        assert!(matches!(res, Err(OpenAIInnerError::InvalidOption(name)) if name == "TopK"));
+    }
+
+    #[test]
+    fn test_create_request_with_tools() {
+        use llm_chain::tools::Format;
+
+        let tool = ToolDescription::new(
+            "BashTool",
+            "Runs a bash command",
+            "",
+            Format::new(vec![("cmd", "The command to run").into()]),
+            Format::new(vec![]),
+        );
+        let options = options!(Tools: vec![tool]);
+        let opts = OptionsCascade::new().with_options(&options);
+        let chat = ChatMessageCollection::new()
+            .with_user("List the files".to_string())
+            .with_assistant(String::new());
+        let mut chat = chat.map(|msg| {
+            if msg.role() == &prompt::ChatRole::Assistant {
+                msg.clone().with_tool_calls(vec![ToolCall::new(
+                    "call_1",
+                    "BashTool",
+                    r#"{"cmd":"ls"}"#,
+                )])
+            } else {
+                msg.clone()
+            }
+        });
+        chat.add_message(ChatMessage::tool_result(
+            "call_1",
+            "stdout: a.txt".to_string(),
+        ));
+
+        let request = create_chat_completion_request(
+            "gpt-4".to_string(),
+            &Prompt::Chat(chat),
+            &opts,
+            &tokenizer(),
+        )
+        .unwrap();
+        let tools = request.tools.unwrap();
+        assert_eq!(tools[0].function.name, "BashTool");
+        assert_eq!(
+            tools[0].function.parameters["required"],
+            serde_json::json!(["cmd"])
+        );
+        match &request.messages[1] {
+            ChatCompletionRequestMessage::Assistant(msg) => {
+                let calls = msg.tool_calls.as_ref().unwrap();
+                assert_eq!(calls[0].function.arguments, r#"{"cmd":"ls"}"#);
+                assert_eq!(msg.content, None);
+            }
+            _ => panic!("expected an assistant message"),
+        }
+        match &request.messages[2] {
+            ChatCompletionRequestMessage::Tool(msg) => assert_eq!(msg.tool_call_id, "call_1"),
+            _ => panic!("expected a tool message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_completion_to_output_keeps_metadata() {
+        let resp: CreateChatCompletionResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1677652288,
+            "model": "gpt-3.5-turbo-0613",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hello there"},
+                "finish_reason": "length"
+            }],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 12, "total_tokens": 21}
+        }))
+        .unwrap();
+        let immediate = completion_to_output(resp).to_immediate().await.unwrap();
+        let metadata = immediate.metadata();
+        assert_eq!(metadata.id.as_deref(), Some("chatcmpl-123"));
+        assert_eq!(metadata.model.as_deref(), Some("gpt-3.5-turbo-0613"));
+        assert_eq!(metadata.finish_reason, Some(output::FinishReason::Length));
+        assert_eq!(metadata.usage, Some(output::TokenUsage::new(9, 12)));
+    }
+
+    #[test]
+    fn test_candidates_and_logprobs_are_read_per_choice() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1677652288,
+            "model": "gpt-3.5-turbo-0613",
+            "choices": [
+                {
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Yes"},
+                    "finish_reason": "stop",
+                    "logprobs": {"content": [{
+                        "token": "Yes",
+                        "logprob": -0.1,
+                        "bytes": [89, 101, 115],
+                        "top_logprobs": [{"token": "Yes", "logprob": -0.1, "bytes": null}]
+                    }]}
+                },
+                {
+                    "index": 1,
+                    "message": {"role": "assistant", "content": "No"},
+                    "finish_reason": "stop",
+                    "logprobs": {"content": [{"token": "No", "logprob": -2.5, "top_logprobs": []}]}
+                }
+            ]
+        });
+        let output = completion_with_logprobs_to_output(body.to_string().as_bytes()).unwrap();
+        let Output::Immediate(immediate) = output else {
+            panic!("expected an immediate output");
+        };
+        let candidates = immediate.candidates();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[1].content.to_string(), "Assistant: No\n");
+        assert_eq!(candidates[1].total_logprob(), Some(-2.5));
+        let logprobs = immediate.logprobs().unwrap();
+        assert_eq!(logprobs[0].top_logprobs, vec![("Yes".to_string(), -0.1)]);
+    }
+
+    #[test]
+    fn test_create_request_resolves_text_bias() {
+        let mut builder = Options::builder();
+        builder.add_option(Opt::TextBias(TextBias::new(vec![(
+            " Paris".to_string(),
+            -100.0,
+        )])));
+        let options = builder.build();
+        let opts = OptionsCascade::new().with_options(&options);
+        let prompt = Prompt::text("What is the capital of France?".to_string());
+        let request = create_chat_completion_request(
+            "gpt-3.5-turbo".to_string(),
+            &prompt,
+            &opts,
+            &tokenizer(),
+        )
+        .unwrap();
+        let tokens = tokenizer()
+            .tokenize_str(" Paris")
+            .unwrap()
+            .as_usize()
+            .unwrap();
+        let logit_bias = request.logit_bias.unwrap();
+        assert_eq!(logit_bias.len(), tokens.len());
+        for token in tokens {
+            assert_eq!(logit_bias[&token.to_string()], serde_json::json!(-100.0));
+        }
+
+        let mut builder = Options::builder();
+        builder.add_option(Opt::TextBias(TextBias::new(vec![(
+            " Paris".to_string(),
+            200.0,
+        )])));
+        let options = builder.build();
+        let opts = OptionsCascade::new().with_options(&options);
+        let res = create_chat_completion_request(
+            "gpt-3.5-turbo".to_string(),
+            &prompt,
+            &opts,
+            &tokenizer(),
+        );
+        assert!(matches!(res, Err(OpenAIInnerError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_create_request_rejects_candidates_when_streaming() {
+        let options = options!(NChoices: 3_usize);
+        let opts = OptionsCascade::new().with_options(&options);
+        let prompt = Prompt::text("Hello".to_string());
+        let request =
+            create_chat_completion_request("gpt-4".to_string(), &prompt, &opts, &tokenizer())
+                .unwrap();
+        assert_eq!(request.n, Some(3));
+
+        let streaming = options!(Stream: true);
+        let opts = opts.with_options(&streaming);
+        let res = create_chat_completion_request("gpt-4".to_string(), &prompt, &opts, &tokenizer());
+        assert!(matches!(res, Err(OpenAIInnerError::InvalidOption(_))));
+    }
+}
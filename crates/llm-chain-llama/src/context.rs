@@ -1,9 +1,12 @@
 use std::{
     ffi::{CStr, CString},
     ptr::null_mut,
+    sync::OnceLock,
 };
 
+use crate::grammar::{token_texts, GrammarState};
 use crate::options::LlamaInvocation;
+use crate::tokenizer::llama_token_eos;
 use anyhow::Result;
 use llm_chain_llama_sys::{
     llama_context, llama_context_default_params, llama_context_params, llama_eval, llama_free,
@@ -90,6 +93,8 @@ impl From<llama_context_params> for ContextParams {
 // Represents the LLamaContext which wraps FFI calls to the llama.cpp library.
 pub(crate) struct LLamaContext {
     ctx: *mut llama_context,
+    // The text of every token, built the first time a grammar needs it.
+    vocabulary: OnceLock<Vec<Option<String>>>,
 }
 
 impl LLamaContext {
@@ -104,7 +109,10 @@ impl LLamaContext {
         if ctx.is_null() {
             return Err("Initializing llama context returned nullptr".into());
         }
-        Ok(Self { ctx })
+        Ok(Self {
+            ctx,
+            vocabulary: OnceLock::new(),
+        })
     }
 
     // Token logits obtained from the last call to llama_eval()
@@ -120,6 +128,13 @@ impl LLamaContext {
         unsafe { llama_n_vocab(self.ctx) }
     }
 
+    // The text of every token, indexed by token id, as used to mask logits with a grammar.
+    pub fn vocabulary(&self) -> &[Option<String>] {
+        self.vocabulary.get_or_init(|| {
+            token_texts((0..self.llama_n_vocab()).map(|tok| self.llama_token_to_bytes(&tok)))
+        })
+    }
+
     // Executes the LLama sampling process with the specified configuration.
     pub fn llama_sample(
         &self,
@@ -127,6 +142,7 @@ impl LLamaContext {
         last_n_tokens_data: &[i32],
         last_n_tokens_size: i32,
         input: &LlamaInvocation,
+        grammar: Option<&GrammarState>,
     ) -> i32 {
         let top_k = if input.top_k <= 0 {
             self.llama_n_vocab()
@@ -147,6 +163,9 @@ impl LLamaContext {
             .logit_bias
             .iter()
             .for_each(|(k, v)| logits[*k as usize] += v);
+        if let Some(state) = grammar {
+            state.mask_logits(&mut logits, self.vocabulary(), llama_token_eos());
+        }
         let mut candidates: Vec<llama_token_data> = Vec::with_capacity(n_vocab);
         (0..n_vocab).for_each(|i| {
             candidates.push(llama_token_data {
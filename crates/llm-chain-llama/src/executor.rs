@@ -2,14 +2,18 @@ use std::marker::PhantomData;
 use std::sync::Arc;
 
 use crate::context::{ContextParams, LLamaContext};
-use crate::options::{get_executor_initial_opts, LlamaInvocation, DEFAULT_OPTIONS};
+use crate::grammar::GrammarState;
+use crate::options::{capabilities, get_executor_initial_opts, LlamaInvocation, DEFAULT_OPTIONS};
+use crate::stop::StopMatcher;
 use crate::tokenizer::{embedding_to_output, llama_token_eos, tokenize, tokens_to_string};
 
 use async_trait::async_trait;
 
-use llm_chain::options::{options_from_env, Options, OptionsCascade};
-use llm_chain::output::{Output, StreamSegment};
-use llm_chain::prompt::{ChatRole, Prompt};
+use llm_chain::options::{options_from_env, Capabilities, Options, OptionsCascade};
+use llm_chain::output::{
+    FinishReason, Output, ResponseMetadata, StreamSegment, TokenLogprob, TokenUsage,
+};
+use llm_chain::prompt::{Data, Prompt};
 
 use llm_chain::tokens::{PromptTokensError, TokenCollection, TokenCount};
 use llm_chain::tokens::{Tokenizer, TokenizerError};
@@ -21,17 +25,18 @@ macro_rules! bail {
         match $val {
             Ok(value) => value,
             Err(err) => {
-                must_send!($sender, StreamSegment::Err(err.into()));
+                send_or_return!($sender, StreamSegment::Err(err.into()));
                 return;
             }
         }
     };
 }
 
-macro_rules! must_send {
+// Stops generating if the output stream was dropped or cancelled.
+macro_rules! send_or_return {
     ($sender:expr, $val:expr) => {
         if $sender.send($val).is_err() {
-            panic!("unable to send message");
+            return;
         }
     };
 }
@@ -53,33 +58,21 @@ impl Executor {
     // Executes the model with the provided input and context parameters.
     async fn run_model(&self, input: LlamaInvocation) -> Output {
         let (sender, output) = Output::new_stream();
-        // Tokenize the stop sequence and input prompt.
         let context = self.context.clone();
         let context_params = self.context_params.clone();
         let context_size = context_params.n_ctx as usize;
-        let answer_prefix = self.answer_prefix(&input.prompt);
         tokio::task::spawn_blocking(move || {
             let context_size = context_size;
             let context = context.blocking_lock();
-            let tokenized_stop_prompt = tokenize(
-                &context,
-                input
-                    .stop_sequence
-                    .first() // XXX: Handle multiple stop seqs
-                    .map(|x| x.as_str())
-                    .unwrap_or("\n\n"),
-                false,
-            );
-
-            if tokenized_stop_prompt.len() > context_size {
-                must_send!(sender, StreamSegment::Err(ExecutorError::ContextTooSmall));
-                return;
-            }
+            let mut stop_matcher = if input.stop_sequence.is_empty() {
+                StopMatcher::new(&["\n\n".to_string()])
+            } else {
+                StopMatcher::new(&input.stop_sequence)
+            };
 
-            let prompt_text = input.prompt.to_text();
-            let tokenized_input = tokenize(&context, prompt_text.as_str(), true);
+            let tokenized_input = tokenize(&context, input.prompt_text.as_str(), true);
             if tokenized_input.len() > context_size {
-                must_send!(sender, StreamSegment::Err(ExecutorError::ContextTooSmall));
+                send_or_return!(sender, StreamSegment::Err(ExecutorError::ContextTooSmall));
                 return;
             }
 
@@ -101,14 +94,14 @@ impl Executor {
 
             let mut n_remaining = context_size - tokenized_input.len();
             let mut n_used = tokenized_input.len() - 1;
-            if let Some(prefix) = answer_prefix {
+            if let Some(prefix) = &input.answer_prefix {
                 let tokenized_answer_prefix = tokenize(&context, prefix.as_str(), false);
                 if tokenized_answer_prefix.len() > context_size {
-                    must_send!(sender, StreamSegment::Err(ExecutorError::ContextTooSmall));
+                    send_or_return!(sender, StreamSegment::Err(ExecutorError::ContextTooSmall));
                     return;
                 }
 
-                // Evaluate the answer prefix (the role -- should be Assistant: )
+                // Evaluate the answer prefix of the chat format, such as `Assistant:`
                 bail!(
                     context
                         .llama_eval(
@@ -126,20 +119,34 @@ impl Executor {
             }
             embd.resize(context_size, 0);
             let token_eos = llama_token_eos();
-            let mut stop_sequence_i = 0;
+            let mut n_sampled = 0;
+            // Running out of context or hitting the token limit both count as a length stop.
+            let mut finish_reason = FinishReason::Length;
+            let mut grammar = input.grammar.clone().map(GrammarState::new);
             // Generate remaining tokens.
             let mut leftover_bytes: Vec<u8> = vec![];
             while n_remaining > 0 {
+                if sender.is_closed() {
+                    return;
+                }
                 let tok = context.llama_sample(
                     context_size as i32,
                     embd.as_slice(),
                     n_used as i32,
                     &input,
+                    grammar.as_ref(),
                 );
+                if let Some(state) = grammar.as_mut() {
+                    if let Some(Some(text)) = context.vocabulary().get(tok as usize) {
+                        state.accept(text);
+                    }
+                }
                 n_used += 1;
                 n_remaining -= 1;
+                n_sampled += 1;
                 embd[n_used] = tok;
                 if tok == token_eos {
+                    finish_reason = FinishReason::Stop;
                     break;
                 }
                 if input.n_tok_predict != 0
@@ -147,17 +154,24 @@ impl Executor {
                 {
                     break;
                 }
-                if tok == tokenized_stop_prompt[stop_sequence_i] {
-                    stop_sequence_i += 1;
-                    if stop_sequence_i >= tokenized_stop_prompt.len() {
-                        break;
-                    }
-                } else {
-                    let str_output =
-                        tokens_to_string(&context, &embd[n_used - stop_sequence_i..n_used]);
+                if let Some(top_n) = input.logprobs {
+                    send_or_return!(
+                        sender,
+                        StreamSegment::Logprob(token_logprob(&context, tok, top_n))
+                    );
+                }
+                let bytes_output: Vec<u8> =
+                    [leftover_bytes, context.llama_token_to_bytes(&tok)].concat();
+                let (str_output, leftover) = decode_up_to_valid_utf8(&bytes_output);
+                leftover_bytes = leftover;
+                let (str_output, stopped) = stop_matcher.push(&str_output);
+                if !str_output.is_empty() {
                     // XXX: make into chat if chat
-                    must_send!(sender, StreamSegment::Content(str_output));
-                    stop_sequence_i = 0;
+                    send_or_return!(sender, StreamSegment::Content(str_output));
+                }
+                if stopped {
+                    finish_reason = FinishReason::Stop;
+                    break;
                 }
                 bail!(
                     context
@@ -165,30 +179,29 @@ impl Executor {
                         .map_err(|e| ExecutorError::InnerError(e.into())),
                     sender
                 );
-
-                if n_used >= tokenized_input.len() && stop_sequence_i == 0 {
-                    let bytes_output: Vec<u8> =
-                        [leftover_bytes, context.llama_token_to_bytes(&embd[n_used])].concat();
-
-                    let (str_output, leftover) = decode_up_to_valid_utf8(&bytes_output);
-                    leftover_bytes = leftover;
-                    // XXX: make into chat if chat
-                    if sender.send(StreamSegment::Content(str_output)).is_err() {
-                        panic!("Failed to send");
-                    }
-                }
             }
-            if sender
-                .send(StreamSegment::Content(
+            // Text held back as the possible start of a stop sequence is part of the answer.
+            let held = stop_matcher.finish();
+            if !held.is_empty() {
+                send_or_return!(sender, StreamSegment::Content(held));
+            }
+            send_or_return!(
+                sender,
+                StreamSegment::Content(
                     std::char::REPLACEMENT_CHARACTER
                         .to_string()
                         .repeat(leftover_bytes.len()),
-                ))
-                .is_err()
-            {
-                panic!("Failed to send");
-            }
-        }); //JoinHandle is dropped? not sure how this works
+                )
+            );
+            send_or_return!(
+                sender,
+                StreamSegment::Metadata(ResponseMetadata {
+                    finish_reason: Some(finish_reason),
+                    usage: Some(TokenUsage::new(tokenized_input.len() as u32, n_sampled)),
+                    ..Default::default()
+                })
+            );
+        }); // The task runs detached and stops once the output stream is dropped.
 
         output
     }
@@ -218,7 +231,10 @@ impl ExecutorTrait for Executor {
     }
     // Executes the model asynchronously and returns the output.
     async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
-        let invocation = LlamaInvocation::new(self.get_cascade(options), prompt)
+        let tokenizer = self
+            .get_tokenizer(options)
+            .map_err(|_| ExecutorError::InvalidOptions)?;
+        let invocation = LlamaInvocation::new(self.get_cascade(options), prompt, &tokenizer)
             .map_err(|_| ExecutorError::InvalidOptions);
         Ok(self.run_model(invocation?).await)
     }
@@ -229,36 +245,26 @@ impl ExecutorTrait for Executor {
         prompt: &Prompt,
     ) -> Result<TokenCount, PromptTokensError> {
         let tokenizer = self.get_tokenizer(options)?;
-        let input = prompt.to_text();
-        let mut tokens_used = tokenizer
+        let chat_format = self.get_cascade(options).chat_format();
+        let input = chat_format
+            .format_prompt(prompt)
+            .map_err(|_e| PromptTokensError::UnableToCompute)?;
+        // includes the answer prefix
+        let tokens_used = tokenizer
             .tokenize_str(&input)
             .map_err(|_e| PromptTokensError::UnableToCompute)?
             .len() as i32;
-        // includes answer_prefix
-        let answer_prefix = self.answer_prefix(prompt);
-        if let Some(prefix) = answer_prefix {
-            let answer_used = tokenizer
-                .tokenize_str(&prefix)
-                .map_err(|_e| PromptTokensError::UnableToCompute)?
-                .len() as i32;
-            tokens_used += answer_used
-        }
         let max_tokens = self.max_tokens_allowed(options);
         Ok(TokenCount::new(max_tokens, tokens_used))
     }
 
     fn answer_prefix(&self, prompt: &Prompt) -> Option<String> {
-        if let llm_chain::prompt::Data::Chat(_) = prompt {
-            // Tokenize answer prefix
-            // XXX: Make the format dynamic
-            let prefix = if prompt.to_text().ends_with('\n') {
-                ""
-            } else {
-                "\n"
-            };
-            Some(format!("{}{}:", prefix, ChatRole::Assistant))
-        } else {
-            None
+        match prompt {
+            Data::Chat(_) => self
+                .get_cascade(Options::empty())
+                .chat_format()
+                .answer_prefix(),
+            Data::Text(_) => None,
         }
     }
 
@@ -269,6 +275,10 @@ impl ExecutorTrait for Executor {
     fn get_tokenizer(&self, _step: &Options) -> Result<LLamaTokenizer, TokenizerError> {
         Ok(LLamaTokenizer::new(self))
     }
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities()
+    }
 }
 
 pub struct LLamaTokenizer<'a> {
@@ -304,6 +314,32 @@ impl Tokenizer for LLamaTokenizer<'_> {
     }
 }
 
+/// Computes the log-probability of `token` and of the `top_n` most likely tokens from the logits
+/// of the last evaluation, that is before penalties and temperature are applied.
+fn token_logprob(context: &LLamaContext, token: i32, top_n: usize) -> TokenLogprob {
+    let n_vocab = context.llama_n_vocab() as usize;
+    let logits = context.llama_get_logits_as_slice(1, n_vocab);
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|l| (l - max).exp()).sum::<f32>().ln() + max;
+
+    let mut top: Vec<(usize, f32)> = logits.iter().copied().enumerate().collect();
+    let by_logit_desc = |a: &(usize, f32), b: &(usize, f32)| b.1.total_cmp(&a.1);
+    if top_n < top.len() {
+        top.select_nth_unstable_by(top_n, by_logit_desc);
+        top.truncate(top_n);
+    }
+    top.sort_unstable_by(by_logit_desc);
+
+    TokenLogprob {
+        token: tokens_to_string(context, &[token]),
+        logprob: logits[token as usize] - log_sum,
+        top_logprobs: top
+            .into_iter()
+            .map(|(id, logit)| (tokens_to_string(context, &[id as i32]), logit - log_sum))
+            .collect(),
+    }
+}
+
 fn decode_up_to_valid_utf8(bytes: &[u8]) -> (String, Vec<u8>) {
     let (str_output, leftover): (String, Vec<u8>) = match std::str::from_utf8(bytes) {
         Ok(s) => (s.to_owned(), Vec::new()),
@@ -0,0 +1,747 @@
+//! Grammar-constrained sampling.
+//!
+//! A [`Grammar`] restricts the text the model may generate. Before every sampling step, the
+//! logits of the tokens that can't continue a match of the grammar are set to negative infinity,
+//! and the end of stream token is only allowed once the text generated so far is a complete
+//! match. This makes it possible to get reliable JSON, or one of a fixed set of answers, out of
+//! small local models.
+//!
+//! Grammars are written in a subset of the GBNF format used by llama.cpp:
+//!
+//! ```text
+//! # Comments start with a hash.
+//! root   ::= answer ws
+//! answer ::= "yes" | "no" | "maybe: " [a-z]+
+//! ws     ::= [ \t\n]*
+//! ```
+//!
+//! A rule is a name, `::=`, and alternatives separated by `|`. Alternatives are sequences of
+//! string literals, character classes such as `[a-z_]` or `[^"]`, references to other rules and
+//! parenthesized groups, each optionally followed by `*`, `+` or `?`. Literals and classes accept
+//! the escapes `\n`, `\t`, `\r`, `\\`, `\"`, `\]` and `\xHH`. Generation starts from the rule named
+//! `root`. Left recursive rules aren't supported.
+//!
+//! [`json_schema_to_gbnf`] compiles a JSON Schema into such a grammar. Pass the grammar to the
+//! executor with `Opt::Grammar`. Tokens holding only part of a multi-byte character are never
+//! allowed, not even by negated classes such as `[^"]`, as their text isn't known until the rest of
+//! the character is generated. See [`token_texts`].
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde_json::Value;
+use thiserror::Error;
+
+/// The deepest a stack of rules may get while matching, which stops left recursion.
+const MAX_STACK_DEPTH: usize = 1024;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum GrammarError {
+    #[error("grammar syntax error at byte {0}: {1}")]
+    Syntax(usize, String),
+    #[error("the grammar uses the rule `{0}`, which isn't defined")]
+    UndefinedRule(String),
+    #[error("the grammar has no `root` rule")]
+    MissingRoot,
+    #[error("the JSON Schema can't be compiled to a grammar: {0}")]
+    UnsupportedSchema(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Element {
+    /// A character in one of the ranges, or, if negated, in none of them.
+    Chars {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    /// A reference to a rule.
+    Rule(usize),
+}
+
+impl Element {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Element::Chars { ranges, negated } => {
+                ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi) != *negated
+            }
+            Element::Rule(_) => false,
+        }
+    }
+}
+
+type Alternative = Vec<Element>;
+
+/// A parsed grammar.
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    /// The alternatives of every rule, indexed by rule id.
+    rules: Vec<Vec<Alternative>>,
+    root: usize,
+}
+
+impl Grammar {
+    /// Parses a grammar in GBNF. See the module documentation for the supported syntax.
+    pub fn parse(gbnf: &str) -> Result<Self, GrammarError> {
+        Parser::new(gbnf).parse()
+    }
+
+    /// Compiles a JSON Schema into a grammar. See `json_schema_to_gbnf`.
+    pub fn from_json_schema(schema: &Value) -> Result<Self, GrammarError> {
+        Self::parse(&json_schema_to_gbnf(schema)?)
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    ids: HashMap<String, usize>,
+    names: Vec<String>,
+    rules: Vec<Option<Vec<Alternative>>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            pos: 0,
+            ids: HashMap::new(),
+            names: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, GrammarError> {
+        Err(GrammarError::Syntax(self.pos, message.to_string()))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skips whitespace, including newlines, and comments.
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.next_char();
+                }
+            } else if c.is_whitespace() {
+                self.next_char();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn name_len(&self) -> usize {
+        self.rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(self.rest().len())
+    }
+
+    /// Returns true if a rule definition, `name ::=`, starts here.
+    fn at_definition(&self) -> bool {
+        let len = self.name_len();
+        len > 0 && self.rest()[len..].trim_start().starts_with("::=")
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.rules.len();
+        self.ids.insert(name.to_string(), id);
+        self.names.push(name.to_string());
+        self.rules.push(None);
+        id
+    }
+
+    /// Adds an anonymous rule, used for groups and repetitions.
+    fn anonymous_rule(&mut self, alternatives: Vec<Alternative>) -> usize {
+        let id = self.rules.len();
+        self.names.push(format!("<anonymous {}>", id));
+        self.rules.push(Some(alternatives));
+        id
+    }
+
+    fn parse(mut self) -> Result<Grammar, GrammarError> {
+        self.skip_space();
+        while self.pos < self.src.len() {
+            let len = self.name_len();
+            if len == 0 {
+                return self.error("expected a rule name");
+            }
+            let name = &self.src[self.pos..self.pos + len];
+            self.pos += len;
+            self.skip_space();
+            if !self.rest().starts_with("::=") {
+                return self.error("expected `::=`");
+            }
+            self.pos += 3;
+            let id = self.rule_id(name);
+            let alternatives = self.parse_alternatives()?;
+            if self.rules[id].is_some() {
+                return self.error(&format!("the rule `{}` is defined twice", name));
+            }
+            self.rules[id] = Some(alternatives);
+        }
+        let root = *self.ids.get("root").ok_or(GrammarError::MissingRoot)?;
+        let rules = self
+            .rules
+            .into_iter()
+            .zip(self.names)
+            .map(|(rule, name)| rule.ok_or(GrammarError::UndefinedRule(name)))
+            .collect::<Result<_, _>>()?;
+        Ok(Grammar { rules, root })
+    }
+
+    fn parse_alternatives(&mut self) -> Result<Vec<Alternative>, GrammarError> {
+        let mut alternatives = vec![self.parse_sequence()?];
+        while self.peek() == Some('|') {
+            self.next_char();
+            alternatives.push(self.parse_sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self) -> Result<Alternative, GrammarError> {
+        let mut sequence = Vec::new();
+        loop {
+            self.skip_space();
+            let element = match self.peek() {
+                None | Some('|') | Some(')') => break,
+                Some(_) if self.at_definition() => break,
+                Some('"') => {
+                    self.next_char();
+                    let mut chars = self.parse_literal()?;
+                    let repeated = matches!(self.peek(), Some('*' | '+' | '?'));
+                    if chars.len() == 1 || !repeated {
+                        sequence.append(&mut chars);
+                        self.parse_repetition(&mut sequence);
+                        continue;
+                    }
+                    // Repetitions apply to the whole literal.
+                    Element::Rule(self.anonymous_rule(vec![chars]))
+                }
+                Some('[') => {
+                    self.next_char();
+                    self.parse_class()?
+                }
+                Some('(') => {
+                    self.next_char();
+                    let alternatives = self.parse_alternatives()?;
+                    if self.next_char() != Some(')') {
+                        return self.error("expected `)`");
+                    }
+                    Element::Rule(self.anonymous_rule(alternatives))
+                }
+                Some(_) => {
+                    let len = self.name_len();
+                    if len == 0 {
+                        return self.error("unexpected character");
+                    }
+                    let name = &self.src[self.pos..self.pos + len];
+                    self.pos += len;
+                    Element::Rule(self.rule_id(name))
+                }
+            };
+            sequence.push(element);
+            self.parse_repetition(&mut sequence);
+        }
+        Ok(sequence)
+    }
+
+    /// Applies a `*`, `+` or `?` following the last element of `sequence`.
+    fn parse_repetition(&mut self, sequence: &mut Alternative) {
+        let op = match self.peek() {
+            Some(op @ ('*' | '+' | '?')) => op,
+            _ => return,
+        };
+        self.next_char();
+        let element = sequence.pop().expect("repetitions follow an element");
+        match op {
+            '?' => {
+                let id = self.anonymous_rule(vec![vec![element], vec![]]);
+                sequence.push(Element::Rule(id));
+            }
+            _ => {
+                // x* ::= x x* | ε, and x+ is x x*.
+                let id = self.anonymous_rule(Vec::new());
+                self.rules[id] = Some(vec![vec![element.clone(), Element::Rule(id)], vec![]]);
+                if op == '+' {
+                    sequence.push(element);
+                }
+                sequence.push(Element::Rule(id));
+            }
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<char, GrammarError> {
+        match self.next_char() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('x') => {
+                let hex = self.rest().get(..2).unwrap_or_default();
+                let code =
+                    u32::from_str_radix(hex, 16).or_else(|_| self.error("bad \\x escape"))?;
+                self.pos += 2;
+                char::from_u32(code).map_or_else(|| self.error("bad \\x escape"), Ok)
+            }
+            Some(c @ ('\\' | '"' | '[' | ']' | '-' | '^')) => Ok(c),
+            _ => self.error("unknown escape"),
+        }
+    }
+
+    /// Parses the rest of a string literal, into one element per character.
+    fn parse_literal(&mut self) -> Result<Alternative, GrammarError> {
+        let mut sequence = Vec::new();
+        loop {
+            let c = match self.next_char() {
+                None => return self.error("unterminated string literal"),
+                Some('"') => return Ok(sequence),
+                Some('\\') => self.parse_escape()?,
+                Some(c) => c,
+            };
+            sequence.push(Element::Chars {
+                ranges: vec![(c, c)],
+                negated: false,
+            });
+        }
+    }
+
+    /// Parses the rest of a character class.
+    fn parse_class(&mut self) -> Result<Element, GrammarError> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.next_char();
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let lo = match self.next_char() {
+                None => return self.error("unterminated character class"),
+                Some(']') => return Ok(Element::Chars { ranges, negated }),
+                Some('\\') => self.parse_escape()?,
+                Some(c) => c,
+            };
+            let hi = if self.peek() == Some('-') && !self.rest()[1..].starts_with(']') {
+                self.next_char();
+                match self.next_char() {
+                    Some('\\') => self.parse_escape()?,
+                    Some(c) => c,
+                    None => return self.error("unterminated character class"),
+                }
+            } else {
+                lo
+            };
+            ranges.push((lo, hi));
+        }
+    }
+}
+
+/// A position in the grammar: the next element of an alternative of a rule.
+type Position = (usize, usize, usize);
+type Stack = Vec<Position>;
+
+/// The progress of matching generated text against a grammar.
+///
+/// Like in llama.cpp, the state is the set of stacks of rule positions the text so far can be in.
+/// Every stack either ends with a character element, the next character it expects, or is empty,
+/// meaning the text so far is a complete match.
+#[derive(Debug, Clone)]
+pub struct GrammarState {
+    grammar: Arc<Grammar>,
+    stacks: Vec<Stack>,
+}
+
+impl GrammarState {
+    /// Starts matching `grammar` from its root rule.
+    pub fn new(grammar: Arc<Grammar>) -> Self {
+        let mut stacks = Vec::new();
+        for alt in 0..grammar.rules[grammar.root].len() {
+            advance(&grammar, vec![(grammar.root, alt, 0)], &mut stacks);
+        }
+        stacks.sort();
+        stacks.dedup();
+        Self { grammar, stacks }
+    }
+
+    /// Returns true if the text accepted so far is a complete match of the grammar.
+    pub fn is_complete(&self) -> bool {
+        self.stacks.iter().any(Vec::is_empty)
+    }
+
+    /// Returns true if no text can be accepted anymore.
+    pub fn is_finished(&self) -> bool {
+        self.stacks.iter().all(Vec::is_empty)
+    }
+
+    fn stacks_after(&self, text: &str) -> Vec<Stack> {
+        let mut stacks = self.stacks.clone();
+        for c in text.chars() {
+            let mut next = Vec::new();
+            for mut stack in stacks {
+                let Some(&(rule, alt, i)) = stack.last() else {
+                    continue;
+                };
+                if self.grammar.rules[rule][alt][i].matches(c) {
+                    *stack.last_mut().unwrap() = (rule, alt, i + 1);
+                    advance(&self.grammar, stack, &mut next);
+                }
+            }
+            if next.is_empty() {
+                return next;
+            }
+            next.sort();
+            next.dedup();
+            stacks = next;
+        }
+        stacks
+    }
+
+    /// Returns true if `text` can follow the text accepted so far.
+    pub fn allows(&self, text: &str) -> bool {
+        !text.is_empty() && !self.stacks_after(text).is_empty()
+    }
+
+    /// Accepts the generated `text`. Returns false, and leaves the state unchanged, if the grammar
+    /// doesn't allow it.
+    pub fn accept(&mut self, text: &str) -> bool {
+        let stacks = self.stacks_after(text);
+        if stacks.is_empty() {
+            return false;
+        }
+        self.stacks = stacks;
+        true
+    }
+
+    /// Sets the logit of every token the grammar doesn't allow next to negative infinity.
+    ///
+    /// `vocabulary` holds the text of every token, indexed by token id, as built by
+    /// `token_texts`. Tokens without text are never allowed. The end of stream token, `eos`, is
+    /// allowed once the match is complete.
+    pub fn mask_logits(&self, logits: &mut [f32], vocabulary: &[Option<String>], eos: i32) {
+        for (token, logit) in logits.iter_mut().enumerate() {
+            let allowed = if token as i32 == eos {
+                self.is_complete()
+            } else {
+                vocabulary
+                    .get(token)
+                    .and_then(Option::as_deref)
+                    .is_some_and(|text| self.allows(text))
+            };
+            if !allowed {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+    }
+}
+
+/// Returns the text of every token, given their bytes in token id order, for `mask_logits`.
+///
+/// Tokens whose bytes aren't valid UTF-8 on their own, such as those holding part of a multi-byte
+/// character, have no text. Replacing their bytes with `U+FFFD` instead would let negated classes
+/// match them.
+pub fn token_texts<I>(tokens: I) -> Vec<Option<String>>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    tokens
+        .into_iter()
+        .map(|bytes| String::from_utf8(bytes).ok())
+        .collect()
+}
+
+/// Expands `stack` until it expects a character or is empty, adding the results to `out`.
+fn advance(grammar: &Grammar, mut stack: Stack, out: &mut Vec<Stack>) {
+    if stack.len() > MAX_STACK_DEPTH {
+        return;
+    }
+    let Some(&(rule, alt, i)) = stack.last() else {
+        out.push(stack);
+        return;
+    };
+    let sequence = &grammar.rules[rule][alt];
+    match sequence.get(i) {
+        None => {
+            stack.pop();
+            advance(grammar, stack, out);
+        }
+        Some(Element::Chars { .. }) => out.push(stack),
+        Some(Element::Rule(sub)) => {
+            *stack.last_mut().unwrap() = (rule, alt, i + 1);
+            for sub_alt in 0..grammar.rules[*sub].len() {
+                let mut next = stack.clone();
+                next.push((*sub, sub_alt, 0));
+                advance(grammar, next, out);
+            }
+        }
+    }
+}
+
+/// Shared rules for the primitive JSON types, appended to compiled schemas.
+const JSON_PRIMITIVES: &str = r#"ws ::= [ \t\n]*
+string ::= "\"" ( [^"\\] | "\\" ["\\/bfnrt] | "\\u" [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] )* "\"" ws
+number ::= "-"? [0-9]+ ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )? ws
+integer ::= "-"? [0-9]+ ws
+boolean ::= ( "true" | "false" ) ws
+null ::= "null" ws
+"#;
+
+/// A grammar matching any JSON value.
+pub const JSON: &str = r#"root ::= value
+value ::= object | array | string | number | boolean | null
+object ::= "{" ws ( string ":" ws value ( "," ws string ":" ws value )* )? "}" ws
+array ::= "[" ws ( value ( "," ws value )* )? "]" ws
+ws ::= [ \t\n]*
+string ::= "\"" ( [^"\\] | "\\" ["\\/bfnrt] | "\\u" [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] )* "\"" ws
+number ::= "-"? [0-9]+ ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )? ws
+boolean ::= ( "true" | "false" ) ws
+null ::= "null" ws
+"#;
+
+/// Returns `text` as a GBNF string literal.
+fn literal(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Returns the GBNF literal matching the JSON serialization of `value`.
+fn json_literal(value: &Value) -> String {
+    literal(&value.to_string())
+}
+
+struct SchemaCompiler {
+    rules: Vec<String>,
+    names: HashSet<String>,
+}
+
+impl SchemaCompiler {
+    /// Compiles `schema` into a rule named after `name`, returning the name to reference it by.
+    ///
+    /// Names are derived from property names, so different paths may give the same one, e.g. the
+    /// properties `a-b` and `a_b`. Later rules get a numeric suffix to keep them apart.
+    fn compile(&mut self, schema: &Value, name: &str) -> Result<String, GrammarError> {
+        let mut unique = name.to_string();
+        let mut suffix = 1;
+        while !self.names.insert(unique.clone()) {
+            suffix += 1;
+            unique = format!("{}-{}", name, suffix);
+        }
+        let body = self.body(schema, &unique)?;
+        self.rules.push(format!("{} ::= {}", unique, body));
+        Ok(unique)
+    }
+
+    fn body(&mut self, schema: &Value, name: &str) -> Result<String, GrammarError> {
+        let unsupported = |what: &str| Err(GrammarError::UnsupportedSchema(what.to_string()));
+        if let Some(value) = schema.get("const") {
+            return Ok(format!("{} ws", json_literal(value)));
+        }
+        if let Some(values) = schema.get("enum") {
+            let Some(values) = values.as_array().filter(|v| !v.is_empty()) else {
+                return unsupported("`enum` must be a non-empty array");
+            };
+            let alternatives: Vec<String> = values.iter().map(json_literal).collect();
+            return Ok(format!("( {} ) ws", alternatives.join(" | ")));
+        }
+        let Some(kind) = schema.get("type").and_then(Value::as_str) else {
+            return unsupported("every schema needs a `type`, `enum` or `const`");
+        };
+        match kind {
+            "string" | "number" | "integer" | "boolean" | "null" => Ok(kind.to_string()),
+            "array" => {
+                let item = match schema.get("items") {
+                    Some(items) => self.compile(items, &format!("{}-item", name))?,
+                    None => return unsupported("arrays need `items`"),
+                };
+                Ok(format!(
+                    r#""[" ws ( {item} ( "," ws {item} )* )? "]" ws"#,
+                    item = item
+                ))
+            }
+            "object" => {
+                let properties = match schema.get("properties").and_then(Value::as_object) {
+                    Some(properties) if !properties.is_empty() => properties,
+                    _ => return unsupported("objects need `properties`"),
+                };
+                let mut members = Vec::new();
+                for (key, property) in properties {
+                    let rule_name: String = key
+                        .chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                        .collect();
+                    let value = self.compile(property, &format!("{}-{}", name, rule_name))?;
+                    members.push(format!(
+                        r#"{} ws ":" ws {}"#,
+                        json_literal(&Value::String(key.clone())),
+                        value
+                    ));
+                }
+                Ok(format!(
+                    r#""{{" ws {} "}}" ws"#,
+                    members.join(r#" "," ws "#)
+                ))
+            }
+            other => unsupported(&format!("the type `{}`", other)),
+        }
+    }
+}
+
+/// Compiles a JSON Schema into a GBNF grammar matching JSON documents valid under the schema.
+///
+/// The supported subset is `type` (`object`, `array`, `string`, `number`, `integer`, `boolean`
+/// and `null`), `properties`, `items`, `enum` and `const`. Every property of an object is
+/// required, and properties are generated in alphabetical order.
+pub fn json_schema_to_gbnf(schema: &Value) -> Result<String, GrammarError> {
+    let mut compiler = SchemaCompiler {
+        rules: Vec::new(),
+        names: HashSet::new(),
+    };
+    compiler.compile(schema, "root")?;
+    compiler.rules.reverse();
+    Ok(format!(
+        "{}\n{}",
+        compiler.rules.join("\n"),
+        JSON_PRIMITIVES
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn state(gbnf: &str) -> GrammarState {
+        GrammarState::new(Arc::new(Grammar::parse(gbnf).unwrap()))
+    }
+
+    #[test]
+    fn test_matches_alternatives_and_repetitions() {
+        let mut state = state(
+            r#"
+            # an answer
+            root ::= ( "yes" | "no" ) "!"? [0-9]*
+            "#,
+        );
+        assert!(state.allows("ye"));
+        assert!(!state.allows("maybe"));
+        assert!(state.accept("no"));
+        assert!(state.is_complete());
+        assert!(state.accept("!12"));
+        assert!(state.is_complete());
+        assert!(!state.accept("!"));
+    }
+
+    #[test]
+    fn test_masks_synthetic_logits() {
+        let state = state(r#"root ::= "{" [a-z]+ "}""#);
+        let vocabulary: Vec<Option<String>> = ["{", "ab", "}", "x}", "", "{a"]
+            .iter()
+            .map(|s| Some(s.to_string()))
+            .collect();
+        let eos = 4;
+        let mut logits = vec![1.0; vocabulary.len()];
+        state.mask_logits(&mut logits, &vocabulary, eos);
+        let allowed: Vec<bool> = logits.iter().map(|l| l.is_finite()).collect();
+        assert_eq!(allowed, vec![true, false, false, false, false, true]);
+
+        let mut state = state;
+        assert!(state.accept("{ab}"));
+        let mut logits = vec![1.0; vocabulary.len()];
+        state.mask_logits(&mut logits, &vocabulary, eos);
+        assert!(logits[eos as usize].is_finite());
+        assert!(state.is_finished());
+    }
+
+    #[test]
+    fn test_never_allows_partial_characters() {
+        let state = state(r#"root ::= "\"" [^"]* "\"""#);
+        // "é" is two bytes, which a tokenizer may split across tokens.
+        let bytes = "é".as_bytes();
+        let vocabulary = token_texts(vec![
+            b"\"".to_vec(),
+            bytes[..1].to_vec(),
+            bytes[1..].to_vec(),
+            "é".as_bytes().to_vec(),
+        ]);
+        assert_eq!(vocabulary[1], None);
+        let mut state = state;
+        assert!(state.accept("\""));
+        let mut logits = vec![1.0; vocabulary.len()];
+        state.mask_logits(&mut logits, &vocabulary, -1);
+        let allowed: Vec<bool> = logits.iter().map(|l| l.is_finite()).collect();
+        assert_eq!(allowed, vec![true, false, false, true]);
+    }
+
+    #[test]
+    fn test_reports_errors() {
+        assert_eq!(
+            Grammar::parse("root ::= missing").unwrap_err(),
+            GrammarError::UndefinedRule("missing".to_string())
+        );
+        assert_eq!(
+            Grammar::parse("answer ::= \"a\"").unwrap_err(),
+            GrammarError::MissingRoot
+        );
+        assert!(matches!(
+            Grammar::parse("root ::= \"a"),
+            Err(GrammarError::Syntax(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_compiles_json_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "label": {"enum": ["positive", "negative"]},
+                "scores": {"type": "array", "items": {"type": "number"}},
+            }
+        });
+        let mut state = GrammarState::new(Arc::new(Grammar::from_json_schema(&schema).unwrap()));
+        assert!(!state.allows(r#"{"label": "neutral""#));
+        assert!(state.accept(r#"{"label": "positive", "scores": [0.5, -1e3]}"#));
+        assert!(state.is_complete());
+
+        // `a-b` and `a_b` both give the rule name `root-a-b`, and `x` has items named `root-x-item`.
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "a-b": {"type": "integer"},
+                "a_b": {"type": "string"},
+                "x": {"type": "array", "items": {"type": "boolean"}},
+                "x-item": {"const": 1},
+            }
+        });
+        let mut state = GrammarState::new(Arc::new(Grammar::from_json_schema(&schema).unwrap()));
+        assert!(!state.allows(r#"{"a-b": 1, "a_b": 2"#));
+        assert!(state.accept(r#"{"a-b": 1, "a_b": "s", "x": [true], "x-item": 1}"#));
+        assert!(state.is_complete());
+
+        let mut state = GrammarState::new(Arc::new(Grammar::parse(JSON).unwrap()));
+        assert!(state.accept(r#"{"a": [1, true, null, {"b": "c\n"}]}"#));
+        assert!(state.is_complete());
+    }
+}
@@ -23,11 +23,14 @@
 
 mod context;
 mod executor;
+pub mod grammar;
 mod options;
+mod stop;
 mod tokenizer;
 
 pub use context::ContextParams;
 pub use executor::Executor;
+pub use grammar::{json_schema_to_gbnf, Grammar, GrammarError, GrammarState};
 
 #[deprecated(note = "Use llm_chain::step::Step instead", since = "0.7.0")]
 pub use llm_chain::step::Step;
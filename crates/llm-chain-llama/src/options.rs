@@ -1,14 +1,18 @@
 use lazy_static::lazy_static;
 use llm_chain::{
     options,
-    options::{Opt, OptDiscriminants, Options, OptionsCascade},
-    prompt::Prompt,
+    options::{Capabilities, Opt, OptDiscriminants, Options, OptionsCascade},
+    prompt::{Data, Prompt},
+    tokens::{Tokenizer, TokenizerError},
     traits::ExecutorCreationError,
 };
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::context::ContextParams;
+use crate::grammar::Grammar;
+use crate::tokenizer::llama_token_bos;
 
 /// Represents a concrete call to the LLM model, with all the parameters specified, and no implicit behavior.
 pub struct LlamaInvocation {
@@ -29,7 +33,12 @@ pub struct LlamaInvocation {
     pub(crate) mirostat_eta: f32,
     pub(crate) penalize_nl: bool,
     pub(crate) stop_sequence: Vec<String>,
-    pub(crate) prompt: Prompt,
+    pub(crate) logprobs: Option<usize>,
+    pub(crate) grammar: Option<Arc<Grammar>>,
+    /// The prompt, with chat prompts rendered in the `ChatFormat` option.
+    pub(crate) prompt_text: String,
+    /// The text evaluated after the prompt to start the answer, for chat prompts.
+    pub(crate) answer_prefix: Option<String>,
 }
 
 macro_rules! opt_extract {
@@ -45,9 +54,13 @@ macro_rules! opt_extract {
 }
 
 impl LlamaInvocation {
-    pub(crate) fn new(
+    /// Creates the invocation for `prompt`. `tokenizer` resolves the `TextBias` option into tokens.
+    /// Chat prompts are written in the `ChatFormat` option, whose stop sequences are used after
+    /// those of the `StopSequence` option.
+    pub(crate) fn new<T: Tokenizer>(
         opt: OptionsCascade,
         prompt: &Prompt,
+        tokenizer: &T,
     ) -> Result<LlamaInvocation, ExecutorCreationError> {
         let n_threads = opt_extract!(opt, n_threads, NThreads)?;
         let n_tok_predict = opt_extract!(opt, n_tok_predict, MaxTokens)?;
@@ -64,10 +77,39 @@ impl LlamaInvocation {
         let mirostat_tau = opt_extract!(opt, mirostat_tau, MirostatTau)?;
         let mirostat_eta = opt_extract!(opt, mirostat_eta, MirostatEta)?;
         let penalize_nl = opt_extract!(opt, penalize_nl, PenalizeNl)?;
-        let stop_sequence = opt_extract!(opt, stop_sequence, StopSequence)?;
+        let mut stop_sequence = opt_extract!(opt, stop_sequence, StopSequence)?.clone();
+        let chat_format = opt.chat_format();
+        let (prompt_text, answer_prefix) = match prompt {
+            Data::Chat(chat) => {
+                stop_sequence.extend(chat_format.stop_sequences());
+                let text = chat_format
+                    .render(chat)
+                    .map_err(|e| ExecutorCreationError::InnerError(e.into()))?;
+                (text, chat_format.answer_prefix())
+            }
+            Data::Text(text) => (text.clone(), None),
+        };
+        let logprobs = opt.requested_logprobs();
+        let grammar = match opt.get(OptDiscriminants::Grammar) {
+            Some(Opt::Grammar(gbnf)) => Some(Arc::new(
+                Grammar::parse(gbnf).map_err(|e| ExecutorCreationError::InnerError(e.into()))?,
+            )),
+            _ => None,
+        };
 
-        // Skip TokenBias for now
-        let logit_bias = HashMap::<i32, f32>::new(); // token_bias.as_i32_f32_hashmap()?;
+        let mut logit_bias = match opt.token_bias(tokenizer) {
+            Ok(Some(bias)) => bias
+                .as_i32_f32_hashmap()
+                .ok_or(TokenizerError::TokenCollectionTypeMismatch),
+            Ok(None) => Ok(HashMap::new()),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| ExecutorCreationError::InnerError(e.into()))?;
+        // The tokenizer starts every text with the beginning-of-sequence token, which is never
+        // generated anyway.
+        if matches!(opt.get(OptDiscriminants::TextBias), Some(Opt::TextBias(_))) {
+            logit_bias.remove(&llama_token_bos());
+        }
 
         Ok(LlamaInvocation {
             n_threads: *n_threads as i32,
@@ -86,8 +128,11 @@ impl LlamaInvocation {
             mirostat_tau: *mirostat_tau,
             mirostat_eta: *mirostat_eta,
             penalize_nl: *penalize_nl,
-            stop_sequence: stop_sequence.clone(),
-            prompt: prompt.clone(),
+            stop_sequence,
+            logprobs,
+            grammar,
+            prompt_text,
+            answer_prefix,
         })
     }
 }
@@ -115,6 +160,35 @@ lazy_static! {
     );
 }
 
+/// Returns the options used by the executor.
+pub(crate) fn capabilities() -> Capabilities {
+    Capabilities::new()
+        .supports(OptDiscriminants::Model)
+        .supports(OptDiscriminants::MaxContextSize)
+        .supports(OptDiscriminants::NThreads)
+        .supports(OptDiscriminants::MaxTokens)
+        .supports(OptDiscriminants::TopK)
+        .supports_between(OptDiscriminants::TopP, 0.0, 1.0)
+        .supports(OptDiscriminants::TfsZ)
+        .supports(OptDiscriminants::TypicalP)
+        .supports_between(OptDiscriminants::Temperature, 0.0, f64::MAX)
+        .supports(OptDiscriminants::RepeatPenalty)
+        .supports(OptDiscriminants::RepeatPenaltyLastN)
+        .supports(OptDiscriminants::FrequencyPenalty)
+        .supports(OptDiscriminants::PresencePenalty)
+        .supports_between(OptDiscriminants::Mirostat, 0.0, 2.0)
+        .supports(OptDiscriminants::MirostatTau)
+        .supports(OptDiscriminants::MirostatEta)
+        .supports(OptDiscriminants::PenalizeNl)
+        .supports(OptDiscriminants::TokenBias)
+        .supports(OptDiscriminants::TextBias)
+        .supports(OptDiscriminants::StopSequence)
+        .supports(OptDiscriminants::Logprobs)
+        .supports(OptDiscriminants::TopLogprobs)
+        .supports(OptDiscriminants::Grammar)
+        .supports(OptDiscriminants::ChatFormat)
+}
+
 pub(crate) fn get_executor_initial_opts(
     opt: &OptionsCascade,
 ) -> Result<(String, ContextParams), ExecutorCreationError> {
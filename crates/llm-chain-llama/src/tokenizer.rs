@@ -4,7 +4,8 @@ use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 
 use llm_chain_llama_sys::{
-    llama_token, llama_token_eos as inner_eos, llama_token_to_str, llama_tokenize,
+    llama_token, llama_token_bos as inner_bos, llama_token_eos as inner_eos, llama_token_to_str,
+    llama_tokenize,
 };
 
 use crate::context::LLamaContext;
@@ -36,6 +37,10 @@ pub fn llama_token_eos() -> i32 {
     unsafe { inner_eos() }
 }
 
+pub fn llama_token_bos() -> i32 {
+    unsafe { inner_bos() }
+}
+
 /// Helper function to tokenize text using the provided LLamaContext and add_bos option.
 ///
 /// # Arguments
@@ -1,20 +1,24 @@
+use crate::stop::StopMatcher;
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use llm::{
     load_progress_callback_stdout, InferenceError, InferenceParameters, InferenceRequest, Model,
-    ModelArchitecture, ModelParameters, TokenUtf8Buffer,
+    ModelArchitecture, ModelParameters, TokenBias, TokenUtf8Buffer,
 };
 use llm_chain::options;
-use llm_chain::options::{options_from_env, Opt, OptDiscriminants, Options, OptionsCascade};
-use llm_chain::output::Output;
-use llm_chain::prompt::Prompt;
+use llm_chain::options::{
+    options_from_env, Capabilities, Opt, OptDiscriminants, Options, OptionsCascade,
+};
+use llm_chain::output::{FinishReason, Output, ResponseMetadata, StreamSegment, TokenUsage};
+use llm_chain::prompt::{Data, Prompt};
 use llm_chain::tokens::{
     PromptTokensError, TokenCollection, TokenCount, Tokenizer, TokenizerError,
 };
 use llm_chain::traits::{ExecutorCreationError, ExecutorError};
-use std::convert::Infallible;
 use std::path::Path;
+use std::sync::Arc;
 use thiserror::Error;
+use tokio::sync::mpsc::error::SendError;
 
 lazy_static! {
     static ref DEFAULT_OPTIONS: Options = options!(
@@ -29,7 +33,7 @@ lazy_static! {
 }
 /// Executor is responsible for running the LLM and managing its context.
 pub struct Executor {
-    llm: Box<dyn Model>,
+    llm: Arc<dyn Model>,
     options: Options,
 }
 
@@ -48,6 +52,15 @@ pub enum Error {
     EndOfText,
 }
 
+/// Why the token callback stopped inference.
+#[derive(Debug, Error)]
+enum Interrupt {
+    #[error("the output stream was dropped")]
+    StreamDropped,
+    #[error("a stop sequence was generated")]
+    StopSequence,
+}
+
 #[async_trait]
 impl llm_chain::traits::Executor for Executor {
     type StepTokenizer<'a> = LocalLlmTokenizer<'a>;
@@ -91,7 +104,10 @@ impl llm_chain::traits::Executor for Executor {
         )
         .map_err(|e| ExecutorCreationError::InnerError(Box::new(e)))?;
 
-        Ok(Executor { llm, options })
+        Ok(Executor {
+            llm: llm.into(),
+            options,
+        })
     }
 
     async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
@@ -99,40 +115,91 @@ impl llm_chain::traits::Executor for Executor {
             .with_options(&DEFAULT_OPTIONS)
             .with_options(&self.options)
             .with_options(options);
-        let session = &mut self.llm.start_session(Default::default());
-        let mut output = String::new();
-        session
-            .infer::<Infallible>(
-                self.llm.as_ref(),
-                &mut rand::thread_rng(),
-                &InferenceRequest {
-                    prompt: prompt.to_text().as_str(),
-                    parameters: Some(
-                        &inference_params_from_options(opts)
-                            .map_err(|_| ExecutorError::InvalidOptions)?,
-                    ),
-                    // playback_previous_tokens
-                    // maximum_token_count
-                    ..Default::default()
-                },
+        let tokenizer = self
+            .get_tokenizer(options)
+            .map_err(|_| ExecutorError::InvalidOptions)?;
+        let mut stop_matcher = StopMatcher::new(&stop_sequences(prompt, &opts));
+        // The answer prefix of the chat format ends the prompt, as the model takes a single text.
+        let prompt = opts
+            .chat_format()
+            .format_prompt(prompt)
+            .map_err(|e| ExecutorError::InnerError(e.into()))?;
+        let bias_tokens = bias_tokens_from_options(&opts, &tokenizer)
+            .map_err(|_| ExecutorError::InvalidOptions)?;
+        let parameters = InferenceParameters {
+            bias_tokens,
+            ..inference_params_from_options(opts).map_err(|_| ExecutorError::InvalidOptions)?
+        };
+        let llm = self.llm.clone();
+        let (sender, output) = Output::new_stream();
+        tokio::task::spawn_blocking(move || {
+            let session = &mut llm.start_session(Default::default());
+            // The prompt is fed on its own so that it isn't matched against the stop sequences.
+            let res = session.feed_prompt::<SendError<StreamSegment>>(
+                llm.as_ref(),
+                &parameters,
+                &prompt,
                 // OutputRequest
                 &mut Default::default(),
-                |t| {
-                    output.push_str(t);
-
-                    Ok(())
+                TokenUtf8Buffer::adapt_callback(|t| {
+                    sender.send(StreamSegment::Content(t.to_string()))
+                }),
+            );
+            let prompt_tokens = session.n_past;
+            let res = res.and_then(|()| {
+                session.infer::<Interrupt>(
+                    llm.as_ref(),
+                    &mut rand::thread_rng(),
+                    &InferenceRequest {
+                        prompt: "",
+                        parameters: Some(&parameters),
+                        // playback_previous_tokens
+                        // maximum_token_count
+                        ..Default::default()
+                    },
+                    // OutputRequest
+                    &mut Default::default(),
+                    |t| {
+                        let (text, stopped) = stop_matcher.push(t);
+                        // Failing to send means the output stream was dropped, which stops inference.
+                        if !text.is_empty() && sender.send(StreamSegment::Content(text)).is_err() {
+                            return Err(Interrupt::StreamDropped);
+                        }
+                        if stopped {
+                            return Err(Interrupt::StopSequence);
+                        }
+                        Ok(())
+                    },
+                )
+            });
+            let segment = match res {
+                // No token limit is set, so inference only ends successfully on the end of text
+                // token. A stop sequence interrupts it from the callback.
+                Ok(_) => {
+                    // Text held back as the possible start of a stop sequence is part of the answer.
+                    let held = stop_matcher.finish();
+                    if !held.is_empty() && sender.send(StreamSegment::Content(held)).is_err() {
+                        return;
+                    }
+                    stop_segment(prompt_tokens, session.n_past)
+                }
+                Err(InferenceError::UserCallback(e)) => match e.downcast_ref::<Interrupt>() {
+                    Some(Interrupt::StopSequence) => stop_segment(prompt_tokens, session.n_past),
+                    _ => return,
                 },
-            )
-            .map_err(|e| match e {
-                InferenceError::ContextFull => Error::ContextFull,
-                InferenceError::EndOfText => Error::EndOfText,
-                InferenceError::TokenizationFailed => Error::TokenizationFailed,
-                InferenceError::UserCallback(_) => {
-                    panic!("user callback error should not be possible")
+                Err(InferenceError::ContextFull) => {
+                    StreamSegment::Err(ExecutorError::InnerError(Error::ContextFull.into()))
                 }
-            })
-            .map_err(|e| ExecutorError::InnerError(e.into()))?;
-        Ok(Output::new_immediate(Prompt::text(output)))
+                Err(InferenceError::EndOfText) => {
+                    StreamSegment::Err(ExecutorError::InnerError(Error::EndOfText.into()))
+                }
+                Err(InferenceError::TokenizationFailed) => {
+                    StreamSegment::Err(ExecutorError::InnerError(Error::TokenizationFailed.into()))
+                }
+            };
+            let _ = sender.send(segment);
+        });
+        Ok(output)
     }
 
     fn tokens_used(
@@ -141,7 +208,13 @@ impl llm_chain::traits::Executor for Executor {
         prompt: &Prompt,
     ) -> Result<TokenCount, PromptTokensError> {
         let tokenizer = self.get_tokenizer(options)?;
-        let input = prompt.to_text();
+        let input = OptionsCascade::new()
+            .with_options(&DEFAULT_OPTIONS)
+            .with_options(&self.options)
+            .with_options(options)
+            .chat_format()
+            .format_prompt(prompt)
+            .map_err(|_e| PromptTokensError::UnableToCompute)?;
 
         let tokens_used = tokenizer
             .tokenize_str(&input)
@@ -162,6 +235,25 @@ impl llm_chain::traits::Executor for Executor {
     fn get_tokenizer(&self, _: &Options) -> Result<Self::StepTokenizer<'_>, TokenizerError> {
         Ok(LocalLlmTokenizer::new(self))
     }
+
+    /// The options read by `inference_params_from_options`, `bias_tokens_from_options`,
+    /// `stop_sequences` and at creation. A token limit isn't passed to the model yet.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new()
+            .supports(OptDiscriminants::Model)
+            .supports(OptDiscriminants::ModelType)
+            .supports(OptDiscriminants::NThreads)
+            .supports(OptDiscriminants::NBatch)
+            .supports(OptDiscriminants::TopK)
+            .supports_between(OptDiscriminants::TopP, 0.0, 1.0)
+            .supports(OptDiscriminants::RepeatPenalty)
+            .supports(OptDiscriminants::RepeatPenaltyLastN)
+            .supports_between(OptDiscriminants::Temperature, 0.0, f64::MAX)
+            .supports(OptDiscriminants::TokenBias)
+            .supports(OptDiscriminants::TextBias)
+            .supports(OptDiscriminants::ChatFormat)
+            .supports(OptDiscriminants::StopSequence)
+    }
 }
 
 pub struct LocalLlmTokenizer<'a> {
@@ -200,6 +292,32 @@ impl Tokenizer for LocalLlmTokenizer<'_> {
     }
 }
 
+/// Returns the stop sequences of the request: the `StopSequence` option followed, for chat prompts,
+/// by those of the chat format.
+fn stop_sequences(prompt: &Prompt, opts: &OptionsCascade) -> Vec<String> {
+    let mut stop = match opts.get(OptDiscriminants::StopSequence) {
+        Some(Opt::StopSequence(stops)) => stops.clone(),
+        _ => Vec::new(),
+    };
+    if let Data::Chat(_) = prompt {
+        stop.extend(opts.chat_format().stop_sequences());
+    }
+    stop
+}
+
+/// The segment ending a successful inference, which counts the tokens of the session before and
+/// after generating.
+fn stop_segment(prompt_tokens: usize, session_tokens: usize) -> StreamSegment {
+    StreamSegment::Metadata(ResponseMetadata {
+        finish_reason: Some(FinishReason::Stop),
+        usage: Some(TokenUsage::new(
+            prompt_tokens as u32,
+            session_tokens.saturating_sub(prompt_tokens) as u32,
+        )),
+        ..Default::default()
+    })
+}
+
 fn model_params_from_options(opts: OptionsCascade) -> Result<ModelParameters, ()> {
     Ok(ModelParameters {
         prefer_mmap: true,
@@ -208,6 +326,21 @@ fn model_params_from_options(opts: OptionsCascade) -> Result<ModelParameters, ()
     })
 }
 
+/// Resolves the `TokenBias` and `TextBias` options into the bias passed to the model. The model's
+/// tokenizer is needed for the text, so this is done for each call.
+fn bias_tokens_from_options(
+    opts: &OptionsCascade,
+    tokenizer: &LocalLlmTokenizer,
+) -> Result<TokenBias, TokenizerError> {
+    let Some(bias) = opts.token_bias(tokenizer)? else {
+        return Ok(TokenBias::default());
+    };
+    let bias = bias
+        .as_i32_f32_hashmap()
+        .ok_or(TokenizerError::TokenCollectionTypeMismatch)?;
+    Ok(TokenBias::new(bias.into_iter().collect()))
+}
+
 fn inference_params_from_options(opts: OptionsCascade) -> Result<InferenceParameters, ()> {
     let Some(Opt::NThreads(n_threads)) = opts.get(OptDiscriminants::NThreads) else {
         return Err(());
@@ -1,2 +1,3 @@
 mod executor;
+mod stop;
 pub use executor::Executor;
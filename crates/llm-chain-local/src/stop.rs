@@ -0,0 +1,83 @@
+/// Looks for stop sequences in the generated text, holding back the text that may be the start of
+/// one until the next tokens tell whether it is.
+pub(crate) struct StopMatcher {
+    stop_sequences: Vec<String>,
+    pending: String,
+}
+
+impl StopMatcher {
+    /// Creates a matcher stopping at any of `stop_sequences`. Empty sequences are ignored.
+    pub(crate) fn new(stop_sequences: &[String]) -> Self {
+        Self {
+            stop_sequences: stop_sequences
+                .iter()
+                .filter(|stop| !stop.is_empty())
+                .cloned()
+                .collect(),
+            pending: String::new(),
+        }
+    }
+
+    /// Adds generated `text`. Returns the text that can be sent, and true if a stop sequence was
+    /// found, in which case only the text before it is returned.
+    pub(crate) fn push(&mut self, text: &str) -> (String, bool) {
+        self.pending.push_str(text);
+        let stop_at = self
+            .stop_sequences
+            .iter()
+            .filter_map(|stop| self.pending.find(stop.as_str()))
+            .min();
+        if let Some(index) = stop_at {
+            self.pending.truncate(index);
+            return (std::mem::take(&mut self.pending), true);
+        }
+        let held = self.partial_match_len();
+        let rest = self.pending.split_off(self.pending.len() - held);
+        (std::mem::replace(&mut self.pending, rest), false)
+    }
+
+    /// Returns the text held back when generation ends without a stop sequence.
+    pub(crate) fn finish(self) -> String {
+        self.pending
+    }
+
+    /// Returns the length of the longest end of the pending text that starts a stop sequence.
+    fn partial_match_len(&self) -> usize {
+        self.pending
+            .char_indices()
+            .map(|(i, _)| &self.pending[i..])
+            .find(|end| self.stop_sequences.iter().any(|stop| stop.starts_with(end)))
+            .map_or(0, str::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(stops: &[&str]) -> StopMatcher {
+        StopMatcher::new(&stops.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_stops_at_any_sequence() {
+        let mut stop = matcher(&["</s>", "\nUser:"]);
+        assert_eq!(stop.push("Hello"), ("Hello".to_string(), false));
+        // The newline may start `\nUser:` so it is held back.
+        assert_eq!(stop.push(" there\n"), (" there".to_string(), false));
+        assert_eq!(stop.push("Us"), (String::new(), false));
+        assert_eq!(stop.push("er: hi"), (String::new(), true));
+
+        let mut stop = matcher(&["</s>", "\nUser:"]);
+        assert_eq!(stop.push("Done.</"), ("Done.".to_string(), false));
+        assert_eq!(stop.push("s>"), (String::new(), true));
+    }
+
+    #[test]
+    fn test_releases_held_text_that_is_no_stop_sequence() {
+        let mut stop = matcher(&["\n\n", "###"]);
+        assert_eq!(stop.push("a\n"), ("a".to_string(), false));
+        assert_eq!(stop.push("b#"), ("\nb".to_string(), false));
+        assert_eq!(stop.finish(), "#");
+    }
+}
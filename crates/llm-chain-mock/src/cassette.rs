@@ -0,0 +1,448 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use llm_chain::middleware::CachedOutput;
+use llm_chain::options::{Capabilities, Opt, OptDiscriminants, Options, OptionsCascade};
+use llm_chain::output::{Output, OutputStream};
+use llm_chain::prompt::Prompt;
+use llm_chain::tokens::{PromptTokensError, TokenCount, TokenizerError};
+use llm_chain::traits::{Executor, ExecutorCreationError, ExecutorError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// An error loading, saving or replaying a cassette.
+#[derive(thiserror::Error, Debug)]
+pub enum CassetteError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("no recorded interaction matches the prompt {0:?}, re-record the cassette")]
+    NoMatch(String),
+    #[error(
+        "the interaction recorded for the prompt {0:?} has no candidates, re-record the cassette"
+    )]
+    EmptyInteraction(String),
+}
+
+impl From<CassetteError> for ExecutorError {
+    fn from(err: CassetteError) -> Self {
+        ExecutorError::InnerError(Box::new(err))
+    }
+}
+
+/// Whether a `Cassette` records new interactions or replays recorded ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Calls go to the inner executor and are written to the cassette file.
+    Record,
+    /// Calls are answered from the cassette file, the inner executor is never called.
+    Replay,
+}
+
+/// How a call is matched against the recorded interactions.
+///
+/// The API key never takes part in matching, and is never written to the cassette.
+#[derive(Debug, Clone, Default)]
+pub struct Matching {
+    normalize_whitespace: bool,
+    ignored_options: Vec<OptDiscriminants>,
+}
+
+impl Matching {
+    /// Matches calls with exactly the same prompt and options.
+    pub fn exact() -> Self {
+        Self::default()
+    }
+
+    /// Matches prompts that are equal once runs of whitespace are collapsed into a single space
+    /// and leading and trailing whitespace is removed.
+    pub fn normalized_whitespace() -> Self {
+        Self {
+            normalize_whitespace: true,
+            ..Self::default()
+        }
+    }
+
+    /// Leaves the option `opt` out of matching, so recordings survive changing it.
+    pub fn ignoring_option(mut self, opt: OptDiscriminants) -> Self {
+        self.ignored_options.push(opt);
+        self
+    }
+
+    fn key(&self, prompt: &Prompt, options: &Options) -> Result<Value, CassetteError> {
+        let mut prompt = serde_json::to_value(prompt)?;
+        if self.normalize_whitespace {
+            normalize_strings(&mut prompt);
+        }
+        let mut opts = Vec::new();
+        for opt in OptionsCascade::new()
+            .with_options(options)
+            .effective_options()
+        {
+            let discriminant = OptDiscriminants::from(opt);
+            if discriminant != OptDiscriminants::ApiKey
+                && !self.ignored_options.contains(&discriminant)
+            {
+                opts.push((format!("{:?}", discriminant), serde_json::to_value(opt)?));
+            }
+        }
+        opts.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(serde_json::json!([prompt, opts]))
+    }
+}
+
+fn normalize_strings(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = s.split_whitespace().collect::<Vec<_>>().join(" "),
+        Value::Array(values) => values.iter_mut().for_each(normalize_strings),
+        Value::Object(map) => map.values_mut().for_each(normalize_strings),
+        _ => {}
+    }
+}
+
+/// A recorded call and its response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub prompt: Prompt,
+    pub options: Options,
+    pub output: CachedOutput,
+}
+
+#[derive(Default)]
+struct Tape {
+    interactions: Vec<Interaction>,
+    /// Whether each interaction has been replayed already.
+    played: Vec<bool>,
+}
+
+/// An executor that records the responses of the inner executor to a file and replays them, for
+/// deterministic tests that don't need a model or network access.
+///
+/// In record mode every call goes to the inner executor, and the prompt, the per-call options and
+/// the response are appended to the cassette file. Streamed responses are recorded segment by
+/// segment once the stream ends, and replayed as streams. In replay mode calls are answered from
+/// the file, and a call that matches no recorded interaction fails with
+/// `CassetteError::NoMatch`. When several interactions match, they are replayed in the order they
+/// were recorded, and the last one is repeated once all have been played.
+///
+/// The inner executor is still needed in replay mode for token counting and the tokenizer.
+///
+/// # Example
+///
+/// ```ignore
+/// // Record once against the real model...
+/// let exec = Cassette::record(openai_executor, "tests/cassettes/summarize.json");
+/// // ...then replay in CI.
+/// let exec = Cassette::replay(openai_executor, "tests/cassettes/summarize.json")?
+///     .with_matching(Matching::normalized_whitespace().ignoring_option(OptDiscriminants::Model));
+/// ```
+pub struct Cassette<E> {
+    inner: E,
+    path: Arc<PathBuf>,
+    mode: Mode,
+    matching: Matching,
+    tape: Arc<Mutex<Tape>>,
+}
+
+impl<E> Cassette<E> {
+    /// Wraps `inner`, recording its responses to the file at `path`, which is overwritten.
+    pub fn record<P: Into<PathBuf>>(inner: E, path: P) -> Self {
+        Self {
+            inner,
+            path: Arc::new(path.into()),
+            mode: Mode::Record,
+            matching: Matching::default(),
+            tape: Arc::new(Mutex::new(Tape::default())),
+        }
+    }
+
+    /// Wraps `inner`, replaying the responses recorded in the file at `path`.
+    pub fn replay<P: Into<PathBuf>>(inner: E, path: P) -> Result<Self, CassetteError> {
+        let path = path.into();
+        let interactions: Vec<Interaction> = serde_json::from_slice(&std::fs::read(&path)?)?;
+        let tape = Tape {
+            played: vec![false; interactions.len()],
+            interactions,
+        };
+        Ok(Self {
+            inner,
+            path: Arc::new(path),
+            mode: Mode::Replay,
+            matching: Matching::default(),
+            tape: Arc::new(Mutex::new(tape)),
+        })
+    }
+
+    /// Sets how calls are matched against the recorded interactions. Defaults to
+    /// `Matching::exact`.
+    pub fn with_matching(mut self, matching: Matching) -> Self {
+        self.matching = matching;
+        self
+    }
+
+    /// Returns whether the cassette records or replays.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns a reference to the wrapped executor.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Unwraps the cassette, returning the inner executor.
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    fn find(&self, options: &Options, prompt: &Prompt) -> Result<Output, CassetteError> {
+        let key = self.matching.key(prompt, options)?;
+        let mut tape = self.tape.lock().unwrap();
+        let mut matches = Vec::new();
+        for (i, interaction) in tape.interactions.iter().enumerate() {
+            if self
+                .matching
+                .key(&interaction.prompt, &interaction.options)?
+                == key
+            {
+                matches.push(i);
+            }
+        }
+        let Some(&last) = matches.last() else {
+            return Err(CassetteError::NoMatch(prompt.to_string()));
+        };
+        let i = matches
+            .into_iter()
+            .find(|&i| !tape.played[i])
+            .unwrap_or(last);
+        tape.played[i] = true;
+        tape.interactions[i]
+            .output
+            .clone()
+            .replay()
+            .ok_or_else(|| CassetteError::EmptyInteraction(prompt.to_string()))
+    }
+}
+
+/// Appends an interaction to the tape and writes the whole tape to `path`.
+fn save(tape: &Mutex<Tape>, path: &Path, interaction: Interaction) -> Result<(), CassetteError> {
+    let mut tape = tape.lock().unwrap();
+    tape.interactions.push(interaction);
+    tape.played.push(false);
+    std::fs::write(path, serde_json::to_vec_pretty(&tape.interactions)?)?;
+    Ok(())
+}
+
+/// Returns `options` without the API key, so it is never written to disk.
+fn recorded_options(options: &Options) -> Options {
+    let mut builder = Options::builder();
+    for opt in OptionsCascade::new()
+        .with_options(options)
+        .effective_options()
+    {
+        if !matches!(opt, Opt::ApiKey(_)) {
+            builder.add_option(opt.clone());
+        }
+    }
+    builder.build()
+}
+
+impl<E> Cassette<E> {
+    /// Forwards `stream` to a new output while recording it, saving the recording at its end.
+    fn record_stream(&self, prompt: Prompt, options: Options, stream: OutputStream) -> Output {
+        let tape = self.tape.clone();
+        let path = self.path.clone();
+        stream.record(|segments| async move {
+            let interaction = Interaction {
+                prompt,
+                options,
+                output: CachedOutput::Stream(segments),
+            };
+            Ok(save(&tape, &path, interaction)?)
+        })
+    }
+}
+
+#[async_trait]
+impl<E> Executor for Cassette<E>
+where
+    E: Executor + Send + Sync,
+{
+    type StepTokenizer<'a>
+        = E::StepTokenizer<'a>
+    where
+        Self: 'a;
+
+    /// A cassette can't be created from options, use `Cassette::record` or `Cassette::replay`.
+    fn new_with_options(_: Options) -> Result<Self, ExecutorCreationError> {
+        Err(ExecutorCreationError::FieldRequiredError(
+            "cassette path".to_string(),
+        ))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        if self.mode == Mode::Replay {
+            return Ok(self.find(options, prompt)?);
+        }
+        let recorded = recorded_options(options);
+        match self.inner.execute(options, prompt).await? {
+            Output::Immediate(immediate) => {
+                let (candidates, metadata) = immediate.into_parts();
+                let interaction = Interaction {
+                    prompt: prompt.clone(),
+                    options: recorded,
+                    output: CachedOutput::Immediate {
+                        candidates: candidates.clone(),
+                        metadata: metadata.clone(),
+                    },
+                };
+                save(&self.tape, &self.path, interaction)?;
+                Ok(Output::new_immediate_with_candidates(candidates, metadata))
+            }
+            Output::Stream(stream) => Ok(self.record_stream(prompt.clone(), recorded, stream)),
+        }
+    }
+
+    fn tokens_used(
+        &self,
+        options: &Options,
+        prompt: &Prompt,
+    ) -> Result<TokenCount, PromptTokensError> {
+        self.inner.tokens_used(options, prompt)
+    }
+
+    fn max_tokens_allowed(&self, options: &Options) -> i32 {
+        self.inner.max_tokens_allowed(options)
+    }
+
+    fn answer_prefix(&self, prompt: &Prompt) -> Option<String> {
+        self.inner.answer_prefix(prompt)
+    }
+
+    fn get_tokenizer(&self, options: &Options) -> Result<Self::StepTokenizer<'_>, TokenizerError> {
+        self.inner.get_tokenizer(options)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llm_chain::options::ModelRef;
+    use llm_chain::output::StreamSegment;
+    use llm_chain::prompt::Data;
+
+    fn cassette_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "llm-chain-mock-{}-{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    /// Streams the prompt back word by word, and counts tokens like the mock executor.
+    struct StreamingExecutor(crate::Executor);
+
+    #[async_trait]
+    impl Executor for StreamingExecutor {
+        type StepTokenizer<'a> = crate::executor::MockTokenizer;
+
+        fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
+            Ok(Self(crate::Executor::new_with_options(options)?))
+        }
+
+        async fn execute(&self, _: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+            let (sender, output) = Output::new_stream();
+            for word in prompt.to_text().split_inclusive(' ') {
+                sender
+                    .send(StreamSegment::Content(word.to_string()))
+                    .unwrap();
+            }
+            Ok(output)
+        }
+
+        fn tokens_used(
+            &self,
+            options: &Options,
+            prompt: &Prompt,
+        ) -> Result<TokenCount, PromptTokensError> {
+            self.0.tokens_used(options, prompt)
+        }
+
+        fn max_tokens_allowed(&self, options: &Options) -> i32 {
+            self.0.max_tokens_allowed(options)
+        }
+
+        fn answer_prefix(&self, prompt: &Prompt) -> Option<String> {
+            self.0.answer_prefix(prompt)
+        }
+
+        fn get_tokenizer(
+            &self,
+            options: &Options,
+        ) -> Result<Self::StepTokenizer<'_>, TokenizerError> {
+            self.0.get_tokenizer(options)
+        }
+    }
+
+    fn mock() -> crate::Executor {
+        crate::Executor::new_with_options(Options::empty().clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replays_recorded_responses() {
+        let path = cassette_path("immediate");
+        let prompt = Data::text("Tell me  a joke".to_string());
+        let exec = Cassette::record(mock(), path.clone());
+        let recorded = exec.execute(Options::empty(), &prompt).await.unwrap();
+        let recorded = recorded.to_immediate().await.unwrap().to_string();
+
+        let exec = Cassette::replay(mock(), path.clone())
+            .unwrap()
+            .with_matching(Matching::normalized_whitespace());
+        let prompt = Data::text(" Tell me a joke\n".to_string());
+        let replayed = exec.execute(Options::empty(), &prompt).await.unwrap();
+        assert_eq!(replayed.to_immediate().await.unwrap().to_string(), recorded);
+
+        let story = Data::text("Tell me a story".to_string());
+        match exec.execute(Options::empty(), &story).await {
+            Err(err) => assert!(err.to_string().contains("Tell me a story")),
+            Ok(_) => panic!("an unmatched prompt was answered"),
+        }
+
+        let exec = Cassette::replay(mock(), path.clone()).unwrap();
+        assert!(exec.execute(Options::empty(), &prompt).await.is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replays_streams_ignoring_options() {
+        let path = cassette_path("stream");
+        let prompt = Data::text("Hello there".to_string());
+        let mut options = Options::builder();
+        options.add_option(Opt::Model(ModelRef::from_model_name("model-a")));
+        options.add_option(Opt::ApiKey("secret".to_string()));
+        let options = options.build();
+
+        let exec = Cassette::record(StreamingExecutor(mock()), path.clone());
+        let output = exec.execute(&options, &prompt).await.unwrap();
+        let recorded = output.to_immediate().await.unwrap().to_string();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+
+        let mut options = Options::builder();
+        options.add_option(Opt::Model(ModelRef::from_model_name("model-b")));
+        let options = options.build();
+        let exec = Cassette::replay(StreamingExecutor(mock()), path.clone())
+            .unwrap()
+            .with_matching(Matching::exact().ignoring_option(OptDiscriminants::Model));
+        let replayed = exec.execute(&options, &prompt).await.unwrap();
+        assert!(matches!(replayed, Output::Stream(_)));
+        assert_eq!(replayed.to_immediate().await.unwrap().to_string(), recorded);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use async_trait::async_trait;
+use llm_chain::traits::{self, EmbeddingsError};
+use thiserror::Error;
+
+/// An embeddings model that needs no service, for tests and examples.
+///
+/// Texts are embedded by feature hashing: the lowercased words, the word n-grams up to
+/// `ngram_size` and the character trigrams of every word are hashed into a vector of `dimensions`
+/// components, which is then normalized to unit length. The same text always gets the same
+/// vector, on every platform and run, and texts sharing words and word pieces land near each other
+/// under cosine or dot product similarity.
+pub struct Embeddings {
+    dimensions: usize,
+    ngram_size: usize,
+}
+
+#[derive(Debug, Error)]
+pub enum MockEmbeddingsError {}
+
+impl EmbeddingsError for MockEmbeddingsError {}
+
+impl Embeddings {
+    /// Creates an embeddings model producing vectors of `dimensions` components.
+    pub fn new(dimensions: usize) -> Self {
+        Self {
+            dimensions: dimensions.max(1),
+            ngram_size: 2,
+        }
+    }
+
+    /// Sets the length of the longest word n-gram that is hashed. Defaults to 2.
+    pub fn with_ngram_size(mut self, ngram_size: usize) -> Self {
+        self.ngram_size = ngram_size.max(1);
+        self
+    }
+
+    /// Returns the number of components of the vectors.
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Embeds `text`. An empty text, or one without words, gets the zero vector.
+    pub fn embed(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; self.dimensions];
+        let lowercase = text.to_lowercase();
+        let words: Vec<&str> = lowercase
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+        for n in 1..=self.ngram_size {
+            for ngram in words.windows(n) {
+                self.add_feature(&mut vector, &ngram.join(" "));
+            }
+        }
+        for word in &words {
+            let chars: Vec<char> = format!("<{}>", word).chars().collect();
+            for trigram in chars.windows(3) {
+                self.add_feature(&mut vector, &trigram.iter().collect::<String>());
+            }
+        }
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        vector
+    }
+
+    fn add_feature(&self, vector: &mut [f32], feature: &str) {
+        let hash = fnv1a(feature.as_bytes());
+        // The top bit picks the sign, so colliding features tend to cancel out instead of adding
+        // up.
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % self.dimensions as u64) as usize] += sign;
+    }
+}
+
+impl Default for Embeddings {
+    /// Creates an embeddings model producing vectors of 256 components.
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+/// The 64 bit FNV-1a hash, which unlike the standard library hashers is stable across releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[async_trait]
+impl traits::Embeddings for Embeddings {
+    type Error = MockEmbeddingsError;
+
+    async fn embed_texts(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Self::Error> {
+        Ok(texts.iter().map(|text| self.embed(text)).collect())
+    }
+
+    async fn embed_query(&self, query: String) -> Result<Vec<f32>, Self::Error> {
+        Ok(self.embed(&query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn similarity(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    #[test]
+    fn test_similar_texts_are_close() {
+        let embeddings = Embeddings::new(128);
+        let query = embeddings.embed("How do I bake sourdough bread?");
+        let close = embeddings.embed("Baking bread with a sourdough starter");
+        let far = embeddings.embed("The stock market fell sharply on Monday");
+        assert_eq!(query.len(), 128);
+        assert!(similarity(&query, &close) > similarity(&query, &far));
+        assert_eq!(query, embeddings.embed("How do I bake sourdough bread?"));
+        assert!((similarity(&query, &query) - 1.0).abs() < 1e-5);
+        assert!(embeddings.embed("").iter().all(|x| *x == 0.0));
+    }
+}
@@ -1,16 +1,223 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use async_trait::async_trait;
-use llm_chain::options::Options;
-use llm_chain::output::Output;
-use llm_chain::prompt::Prompt;
+use llm_chain::options::{Capabilities, Opt, OptDiscriminants, Options};
+use llm_chain::output::{FinishReason, Output, ResponseMetadata, StreamSegment, TokenUsage};
+use llm_chain::prompt::{Data, Prompt};
 use llm_chain::tokens::{
     PromptTokensError, TokenCollection, TokenCount, Tokenizer, TokenizerError,
 };
 use llm_chain::traits::{ExecutorCreationError, ExecutorError};
+use regex::Regex;
 
 /// Executor is responsible for running the LLM and managing its context.
+///
+/// By default it echoes the prompt and options back, and has an unlimited context. Use
+/// `Executor::builder` to script its answers, errors, context size and streaming behavior.
 pub struct Executor {
-    #[allow(dead_code)]
     options: Options,
+    script: Script,
+    prompts: Mutex<Vec<Prompt>>,
+}
+
+/// How the mock tokenizer splits text into tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TokenizerKind {
+    /// Every byte of the UTF-8 encoding is a token.
+    #[default]
+    Bytes,
+    /// Every character is a token.
+    Chars,
+    /// Every word, together with the whitespace following it, is a token.
+    Words,
+}
+
+/// How answers are split into an `Output::Stream`.
+#[derive(Debug, Clone, Copy)]
+struct Streaming {
+    chunk_size: usize,
+    delay: Duration,
+}
+
+#[derive(Default)]
+struct Script {
+    responses: Mutex<VecDeque<String>>,
+    rules: Vec<(Regex, String)>,
+    errors: Mutex<HashMap<usize, ExecutorError>>,
+    context_size: Option<i32>,
+    tokenizer: TokenizerKind,
+    vocabulary: Arc<Mutex<Vec<String>>>,
+    streaming: Option<Streaming>,
+    capabilities: Option<Capabilities>,
+}
+
+/// A builder for a scripted mock `Executor`.
+///
+/// For every call the executor answers with, in order of precedence: the error injected for that
+/// call, the next queued response, the response of the first rule whose pattern matches the
+/// prompt, or the echo of the prompt.
+///
+/// # Example
+///
+/// ```rust
+/// use llm_chain_mock::{Executor, TokenizerKind};
+///
+/// let exec = Executor::builder()
+///     .respond_with("The first answer")
+///     .respond_when("(?i)summar", "A summary")
+///     .fail_on_call(2, llm_chain::traits::ExecutorError::Timeout)
+///     .context_size(512)
+///     .tokenizer(TokenizerKind::Words)
+///     .streaming(4, std::time::Duration::from_millis(10))
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct ExecutorBuilder {
+    options: Options,
+    script: Script,
+}
+
+impl ExecutorBuilder {
+    /// Sets the options the executor is created with.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Queues `response` to answer the next call that doesn't fail. Queued responses are used in
+    /// the order they were added, and take precedence over rules.
+    pub fn respond_with<S: Into<String>>(self, response: S) -> Self {
+        self.script
+            .responses
+            .lock()
+            .unwrap()
+            .push_back(response.into());
+        self
+    }
+
+    /// Answers with `response` when the prompt text matches the regular expression `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` isn't a valid regular expression.
+    pub fn respond_when<S: Into<String>>(mut self, pattern: &str, response: S) -> Self {
+        let pattern = Regex::new(pattern).expect("invalid regular expression");
+        self.script.rules.push((pattern, response.into()));
+        self
+    }
+
+    /// Makes the `n`th call, counting from 1, fail with `error`.
+    pub fn fail_on_call(self, n: usize, error: ExecutorError) -> Self {
+        self.script.errors.lock().unwrap().insert(n, error);
+        self
+    }
+
+    /// Sets the context size reported by `max_tokens_allowed`. Defaults to the `MaxContextSize`
+    /// option, or `i32::MAX` if that isn't set either.
+    pub fn context_size(mut self, tokens: i32) -> Self {
+        self.script.context_size = Some(tokens);
+        self
+    }
+
+    /// Sets how the tokenizer splits text. Defaults to `TokenizerKind::Bytes`.
+    pub fn tokenizer(mut self, kind: TokenizerKind) -> Self {
+        self.script.tokenizer = kind;
+        self
+    }
+
+    /// Answers with an `Output::Stream` of chunks of `chunk_size` characters, waiting `delay`
+    /// before each one.
+    pub fn streaming(mut self, chunk_size: usize, delay: Duration) -> Self {
+        self.script.streaming = Some(Streaming {
+            chunk_size: chunk_size.max(1),
+            delay,
+        });
+        self
+    }
+
+    /// Sets the capabilities the executor reports, to test option validation. By default every
+    /// option is accepted.
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.script.capabilities = Some(capabilities);
+        self
+    }
+
+    pub fn build(self) -> Executor {
+        Executor {
+            options: self.options,
+            script: self.script,
+            prompts: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Executor {
+    /// Returns a builder for a scripted mock executor.
+    pub fn builder() -> ExecutorBuilder {
+        ExecutorBuilder::default()
+    }
+
+    /// Returns the prompts received so far, in order.
+    pub fn prompts(&self) -> Vec<Prompt> {
+        self.prompts.lock().unwrap().clone()
+    }
+
+    /// Returns the number of calls made so far.
+    pub fn calls(&self) -> usize {
+        self.prompts.lock().unwrap().len()
+    }
+
+    fn answer(&self, options: &Options, prompt: &Prompt) -> String {
+        if let Some(response) = self.script.responses.lock().unwrap().pop_front() {
+            return response;
+        }
+        let text = prompt.to_text();
+        match self.script.rules.iter().find(|(re, _)| re.is_match(&text)) {
+            Some((_, response)) => response.clone(),
+            None => format!("As a mock large language model, I'm here to help you debug. I have received your prompt: \"{prompt}\" with options \"{options:?}\""),
+        }
+    }
+
+    fn usage(&self, options: &Options, prompt: &Prompt, answer: &str) -> Option<TokenUsage> {
+        use llm_chain::traits::Executor as _;
+        let tokenizer = self.get_tokenizer(options).ok()?;
+        let prompt_tokens = self.tokens_used(options, prompt).ok()?.tokens_used();
+        let completion_tokens = tokenizer.tokenize_str(answer).ok()?.len();
+        Some(TokenUsage::new(
+            prompt_tokens as u32,
+            completion_tokens as u32,
+        ))
+    }
+}
+
+/// Sends `answer` in chunks of `streaming.chunk_size` characters, followed by `metadata`.
+fn stream_answer(answer: String, metadata: ResponseMetadata, streaming: Streaming) -> Output {
+    let chars: Vec<char> = answer.chars().collect();
+    let chunks: Vec<String> = chars
+        .chunks(streaming.chunk_size)
+        .map(|chunk| chunk.iter().collect())
+        .collect();
+    let (sender, output) = Output::new_stream();
+    if streaming.delay.is_zero() {
+        for chunk in chunks {
+            // The receiver is still held by `output`, so this can't fail.
+            let _ = sender.send(StreamSegment::Content(chunk));
+        }
+        let _ = sender.send(StreamSegment::Metadata(metadata));
+        return output;
+    }
+    tokio::spawn(async move {
+        for chunk in chunks {
+            tokio::time::sleep(streaming.delay).await;
+            if sender.send(StreamSegment::Content(chunk)).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(StreamSegment::Metadata(metadata));
+    });
+    output
 }
 
 #[async_trait]
@@ -18,12 +225,32 @@ impl llm_chain::traits::Executor for Executor {
     type StepTokenizer<'a> = MockTokenizer;
 
     fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
-        Ok(Executor { options: options })
+        Ok(Executor::builder().options(options).build())
     }
 
     async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
-        let output = format!( "As a mock large language model, I'm here to help you debug. I have received your prompt: \"{prompt}\" with options \"{options:?}\"");
-        Ok(Output::new_immediate(Prompt::text(output)))
+        let call = {
+            let mut prompts = self.prompts.lock().unwrap();
+            prompts.push(prompt.clone());
+            prompts.len()
+        };
+        if let Some(err) = self.script.errors.lock().unwrap().remove(&call) {
+            return Err(err);
+        }
+        let answer = self.answer(options, prompt);
+        let metadata = ResponseMetadata {
+            model: Some("mock".to_string()),
+            finish_reason: Some(FinishReason::Stop),
+            usage: self.usage(options, prompt, &answer),
+            ..Default::default()
+        };
+        match self.script.streaming {
+            Some(streaming) => Ok(stream_answer(answer, metadata, streaming)),
+            None => Ok(Output::new_immediate_with_metadata(
+                Data::text(answer),
+                metadata,
+            )),
+        }
     }
 
     fn tokens_used(
@@ -50,7 +277,13 @@ impl llm_chain::traits::Executor for Executor {
     }
 
     fn max_tokens_allowed(&self, _: &Options) -> i32 {
-        i32::MAX
+        if let Some(context_size) = self.script.context_size {
+            return context_size;
+        }
+        match self.options.get(OptDiscriminants::MaxContextSize) {
+            Some(Opt::MaxContextSize(size)) => i32::try_from(*size).unwrap_or(i32::MAX),
+            _ => i32::MAX,
+        }
     }
 
     fn answer_prefix(&self, _prompt: &Prompt) -> Option<String> {
@@ -60,36 +293,75 @@ impl llm_chain::traits::Executor for Executor {
     fn get_tokenizer(&self, _: &Options) -> Result<Self::StepTokenizer<'_>, TokenizerError> {
         Ok(MockTokenizer::new(self))
     }
+
+    fn capabilities(&self) -> Capabilities {
+        self.script
+            .capabilities
+            .clone()
+            .unwrap_or_else(Capabilities::any)
+    }
 }
 
-pub struct MockTokenizer {}
+pub struct MockTokenizer {
+    kind: TokenizerKind,
+    /// The words seen so far, indexed by their token, when tokenizing words.
+    vocabulary: Arc<Mutex<Vec<String>>>,
+}
 
 impl MockTokenizer {
-    pub fn new(_executor: &Executor) -> Self {
-        MockTokenizer {}
+    pub fn new(executor: &Executor) -> Self {
+        MockTokenizer {
+            kind: executor.script.tokenizer,
+            vocabulary: executor.script.vocabulary.clone(),
+        }
     }
 }
 
 impl Tokenizer for MockTokenizer {
     fn tokenize_str(&self, doc: &str) -> Result<TokenCollection, TokenizerError> {
-        let tokens: Vec<i32> = doc
-            .as_bytes()
-            .to_vec()
-            .into_iter()
-            .map(|c| c as i32)
-            .collect();
+        let tokens: Vec<i32> = match self.kind {
+            TokenizerKind::Bytes => doc.bytes().map(|c| c as i32).collect(),
+            TokenizerKind::Chars => doc.chars().map(|c| c as i32).collect(),
+            TokenizerKind::Words => {
+                let mut vocabulary = self.vocabulary.lock().unwrap();
+                doc.split_inclusive(char::is_whitespace)
+                    .map(|word| match vocabulary.iter().position(|w| w == word) {
+                        Some(token) => token as i32,
+                        None => {
+                            vocabulary.push(word.to_string());
+                            vocabulary.len() as i32 - 1
+                        }
+                    })
+                    .collect()
+            }
+        };
         Ok(tokens.into())
     }
 
     fn to_string(&self, tokens: TokenCollection) -> Result<String, TokenizerError> {
-        let bytes: Vec<u8> = tokens
-            .as_i32()
-            .unwrap()
-            .into_iter()
-            .map(|c| c as u8)
-            .collect();
-        let doc = String::from_utf8(bytes).unwrap();
-        Ok(doc)
+        let tokens = tokens.as_i32()?;
+        match self.kind {
+            TokenizerKind::Bytes => {
+                let bytes: Vec<u8> = tokens.into_iter().map(|c| c as u8).collect();
+                String::from_utf8(bytes).map_err(|_| TokenizerError::ToStringError)
+            }
+            TokenizerKind::Chars => tokens
+                .into_iter()
+                .map(|c| char::from_u32(c as u32).ok_or(TokenizerError::ToStringError))
+                .collect(),
+            TokenizerKind::Words => {
+                let vocabulary = self.vocabulary.lock().unwrap();
+                tokens
+                    .into_iter()
+                    .map(|t| {
+                        vocabulary
+                            .get(t as usize)
+                            .map(String::as_str)
+                            .ok_or(TokenizerError::ToStringError)
+                    })
+                    .collect()
+            }
+        }
     }
 }
 
@@ -112,4 +384,58 @@ mod tests {
             .expect("failed to convert back to string");
         assert_eq!(doc, "Héllo world");
     }
+
+    #[tokio::test]
+    async fn test_scripted_executor() {
+        let executor = crate::Executor::builder()
+            .respond_with("first")
+            .respond_when("(?i)summar", "a summary")
+            .fail_on_call(2, ExecutorError::Timeout)
+            .context_size(10)
+            .tokenizer(TokenizerKind::Words)
+            .build();
+        let run = |text: &str| {
+            let prompt = Data::text(text.to_string());
+            let executor = &executor;
+            async move { executor.execute(Options::empty(), &prompt).await }
+        };
+        assert_eq!(run("hello").await.unwrap().to_string(), "first");
+        assert!(matches!(run("hello").await, Err(ExecutorError::Timeout)));
+        assert_eq!(
+            run("Summarize this").await.unwrap().to_string(),
+            "a summary"
+        );
+        assert!(run("hello").await.unwrap().to_string().contains("hello"));
+        assert_eq!(executor.calls(), 4);
+
+        let count = executor
+            .tokens_used(Options::empty(), &Data::text("one two three".to_string()))
+            .unwrap();
+        assert_eq!(count.tokens_used(), 3);
+        assert_eq!(count.tokens_remaining(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_streams_chunks() {
+        use futures::StreamExt;
+
+        let executor = crate::Executor::builder()
+            .respond_with("Hello world")
+            .streaming(4, Duration::from_millis(1))
+            .build();
+        let output = executor
+            .execute(Options::empty(), &Data::text("hi".to_string()))
+            .await
+            .unwrap();
+        let Output::Stream(mut stream) = output else {
+            panic!("expected a stream");
+        };
+        let mut chunks = Vec::new();
+        while let Some(segment) = stream.next().await {
+            if let StreamSegment::Content(chunk) = segment {
+                chunks.push(chunk);
+            }
+        }
+        assert_eq!(chunks, vec!["Hell", "o wo", "rld"]);
+    }
 }
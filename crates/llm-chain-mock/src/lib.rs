@@ -1,2 +1,6 @@
+mod cassette;
+mod embeddings;
 mod executor;
-pub use executor::Executor;
+pub use cassette::{Cassette, CassetteError, Interaction, Matching, Mode};
+pub use embeddings::{Embeddings, MockEmbeddingsError};
+pub use executor::{Executor, ExecutorBuilder, MockTokenizer, TokenizerKind};
@@ -1,10 +1,12 @@
 use super::error::OpenAIInnerError;
 use super::prompt::completion_to_output;
+use super::prompt::create_completion_with_logprobs;
 use super::prompt::stream_to_output;
 use async_openai::config::OpenAIConfig;
 use async_openai::types::ChatCompletionRequestMessage;
 
 use async_openai::types::ChatCompletionRequestUserMessageContent;
+use llm_chain::options::Capabilities;
 use llm_chain::options::Opt;
 use llm_chain::options::Options;
 use llm_chain::options::OptionsCascade;
@@ -33,6 +35,8 @@ use std::sync::Arc;
 pub struct Executor {
     /// The client used to communicate with the OpenAI API.
     client: Arc<async_openai::Client<OpenAIConfig>>,
+    /// The HTTP client used for requests `async_openai` can't express, such as log-probabilities.
+    http_client: reqwest::Client,
     /// The per-invocation options for this executor.
     options: Options,
 }
@@ -41,7 +45,11 @@ impl Default for Executor {
     fn default() -> Self {
         let options = Options::default();
         let client = Arc::new(async_openai::Client::new());
-        Self { client, options }
+        Self {
+            client,
+            http_client: reqwest::Client::new(),
+            options,
+        }
     }
 }
 
@@ -95,23 +103,36 @@ impl traits::Executor for Executor {
             cfg = cfg.with_org_id(org_id);
         }
         let client = Arc::new(async_openai::Client::with_config(cfg));
-        Ok(Self { client, options })
+        Ok(Self {
+            client,
+            http_client: reqwest::Client::new(),
+            options,
+        })
     }
 
     async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
         let opts = self.cascade(Some(options));
         let client = self.client.clone();
         let model = self.get_model_from_invocation_options(&opts);
-        let input = create_chat_completion_request(model, prompt, opts.is_streaming()).unwrap();
+        let tokenizer = OpenAITokenizer::new(opts.clone());
+        let input = create_chat_completion_request(model, prompt, &opts, &tokenizer)?;
         if opts.is_streaming() {
             let res = async move { client.chat().create_stream(input).await }
                 .await
-                .map_err(|e| ExecutorError::InnerError(e.into()))?;
+                .map_err(OpenAIInnerError::from)?;
             Ok(stream_to_output(res))
+        } else if let Some(top_logprobs) = opts.requested_logprobs() {
+            Ok(create_completion_with_logprobs(
+                &self.http_client,
+                self.client.config(),
+                input,
+                top_logprobs,
+            )
+            .await?)
         } else {
             let res = async move { client.chat().create(input).await }
                 .await
-                .map_err(|e| ExecutorError::InnerError(e.into()))?;
+                .map_err(OpenAIInnerError::from)?;
             Ok(completion_to_output(res))
         }
     }
@@ -151,6 +172,10 @@ impl traits::Executor for Executor {
     fn get_tokenizer(&self, options: &Options) -> Result<OpenAITokenizer, TokenizerError> {
         Ok(OpenAITokenizer::new(self.cascade(Some(options))))
     }
+
+    fn capabilities(&self) -> Capabilities {
+        super::prompt::capabilities()
+    }
 }
 
 fn num_tokens_from_messages(
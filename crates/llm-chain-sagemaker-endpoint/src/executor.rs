@@ -2,7 +2,9 @@ use crate::model::Formatter;
 use crate::model::Model;
 use async_trait::async_trait;
 
+use llm_chain::options::Capabilities;
 use llm_chain::options::Opt;
+use llm_chain::options::OptDiscriminants;
 use llm_chain::options::Options;
 use llm_chain::options::OptionsCascade;
 use llm_chain::output::Output;
@@ -12,6 +14,8 @@ use llm_chain::tokens::{
 };
 use llm_chain::traits::{ExecutorCreationError, ExecutorError};
 
+use aws_sdk_sagemakerruntime::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_sagemakerruntime::operation::invoke_endpoint::InvokeEndpointError;
 use std::str::FromStr;
 
 /// Executor is responsible for running the LLM and managing its context.
@@ -56,7 +60,7 @@ impl llm_chain::traits::Executor for Executor {
         let opts = self.cascade(Some(options));
         let model = self.get_model_from_invocation_options(&opts);
 
-        let body_blob = model.format_request(prompt, &opts);
+        let body_blob = model.format_request(prompt, &opts)?;
 
         let result = self
             .sagemaker_client
@@ -66,7 +70,7 @@ impl llm_chain::traits::Executor for Executor {
             .body(body_blob)
             .send()
             .await;
-        let response = result.map_err(|e| ExecutorError::InnerError(e.into()))?;
+        let response = result.map_err(classify_invoke_error)?;
         let generated_text = model.parse_response(response);
 
         Ok(Output::new_immediate(Prompt::text(generated_text)))
@@ -95,6 +99,39 @@ impl llm_chain::traits::Executor for Executor {
         // Not all models expose this information.
         unimplemented!();
     }
+
+    /// The options passed to the endpoint by the default formatter.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new()
+            .supports(OptDiscriminants::Model)
+            .supports(OptDiscriminants::MaxTokens)
+            .supports(OptDiscriminants::MaxContextSize)
+            .supports(OptDiscriminants::Temperature)
+            .supports(OptDiscriminants::TopK)
+            .supports(OptDiscriminants::TopP)
+            .supports(OptDiscriminants::StopSequence)
+            .supports(OptDiscriminants::ChatFormat)
+    }
+}
+
+/// Sorts an error from invoking the endpoint into the retryable classes of `ExecutorError`.
+fn classify_invoke_error(e: SdkError<InvokeEndpointError>) -> ExecutorError {
+    match &e {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            ExecutorError::Network(e.into())
+        }
+        SdkError::ServiceError(service_err) => match service_err.err() {
+            InvokeEndpointError::InternalFailure(_)
+            | InvokeEndpointError::InternalDependencyException(_)
+            | InvokeEndpointError::ServiceUnavailable(_)
+            | InvokeEndpointError::ModelNotReadyException(_) => ExecutorError::Server(e.into()),
+            err if err.code() == Some("ThrottlingException") => {
+                ExecutorError::RateLimited(e.into())
+            }
+            _ => ExecutorError::InnerError(e.into()),
+        },
+        _ => ExecutorError::InnerError(e.into()),
+    }
 }
 
 pub struct SageMakerEndpointTokenizer {}
@@ -1,7 +1,8 @@
 use aws_sdk_sagemakerruntime::operation::invoke_endpoint::InvokeEndpointOutput;
 use aws_sdk_sagemakerruntime::primitives::Blob;
 use llm_chain::options::{ModelRef, Opt, OptDiscriminants, OptionsCascade};
-use llm_chain::prompt::Prompt;
+use llm_chain::prompt::{Data, Prompt};
+use llm_chain::traits::ExecutorError;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_with::skip_serializing_none;
@@ -41,13 +42,22 @@ pub enum Model {
 }
 
 pub trait Formatter {
-    fn format_request(&self, prompt: &Prompt, options: &OptionsCascade) -> Blob;
+    /// Formats the request body, failing if the prompt can't be written in the `ChatFormat` option.
+    fn format_request(
+        &self,
+        prompt: &Prompt,
+        options: &OptionsCascade,
+    ) -> Result<Blob, ExecutorError>;
     fn request_content_type(&self) -> String;
     fn parse_response(&self, response: InvokeEndpointOutput) -> String;
 }
 
 impl Formatter for Model {
-    fn format_request(&self, prompt: &Prompt, options: &OptionsCascade) -> Blob {
+    fn format_request(
+        &self,
+        prompt: &Prompt,
+        options: &OptionsCascade,
+    ) -> Result<Blob, ExecutorError> {
         match self {
             Model::Falcon7BInstruct | Model::Falcon40BInstruct => {
                 #[skip_serializing_none]
@@ -85,22 +95,20 @@ impl Formatter for Model {
                         Opt::TopP(i) => *i,
                         _ => unreachable!("options.get should restrict the enum variant."),
                     }),
-                    stop: options
-                        .get(OptDiscriminants::StopSequence)
-                        .map(|x| match x {
-                            Opt::StopSequence(i) => i.clone(),
-                            _ => unreachable!("options.get should restrict the enum variant."),
-                        }),
+                    stop: stop_sequences(prompt, options),
                 };
 
                 let body_json = json!({
-                    "inputs": prompt.to_string(),
+                    "inputs": options
+                        .chat_format()
+                        .format_prompt(prompt)
+                        .map_err(|e| ExecutorError::InnerError(e.into()))?,
                     "parameters": parameters
                 });
 
                 let body_string = body_json.to_string();
                 let body_blob = Blob::new(body_string.as_bytes().to_vec());
-                body_blob
+                Ok(body_blob)
             }
             _ => {
                 unimplemented!("This model does not have a default formatter. Please format the request with your own code.");
@@ -132,6 +140,19 @@ impl Formatter for Model {
     }
 }
 
+/// Returns the stop sequences of the request: the `StopSequence` option followed, for chat prompts,
+/// by those of the chat format.
+fn stop_sequences(prompt: &Prompt, options: &OptionsCascade) -> Option<Vec<String>> {
+    let mut stop = match options.get(OptDiscriminants::StopSequence) {
+        Some(Opt::StopSequence(stops)) => stops.clone(),
+        _ => Vec::new(),
+    };
+    if let Data::Chat(_) = prompt {
+        stop.extend(options.chat_format().stop_sequences());
+    }
+    (!stop.is_empty()).then_some(stop)
+}
+
 impl Model {
     /// Convert the model to its SageMaker JumpStart default endpoint name
     pub fn to_jumpstart_endpoint_name(&self) -> String {
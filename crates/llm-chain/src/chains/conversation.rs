@@ -1,14 +1,14 @@
 //! The `Chain` module models a conversation between an entity and an LLM.
 //! It manages the conversation state and provides methods for sending messages and receiving responses.
 //!
-//! It relies on the `traits::Executor` trait to execute prompts and handle LLM interactions.
+//! It relies on the `traits::Executor` trait (or a `dyn DynExecutor`) to execute prompts and handle LLM interactions.
 
 use crate::options::Options;
 use crate::output::Output;
 use crate::prompt::{ChatMessageCollection, Prompt, PromptTemplate, StringTemplateError};
 use crate::step::Step;
 use crate::tokens::{PromptTokensError, TokenizerError};
-use crate::traits::{Executor, ExecutorError};
+use crate::traits::{DynExecutor, ExecutorError};
 use crate::{parameters, Parameters};
 use serde::{Deserialize, Serialize};
 
@@ -53,7 +53,7 @@ impl Chain {
     ///
     /// # Returns
     /// A `Result` containing the LLM's response as `E::Output` on success or an `Error` variant on failure.
-    pub async fn send_message<E: Executor>(
+    pub async fn send_message<E: DynExecutor + ?Sized>(
         &mut self,
         step: Step,
         parameters: &Parameters,
@@ -74,7 +74,7 @@ impl Chain {
     ///
     /// # Returns
     /// A `Result` containing the LLM's response as `E::Output` on success or an `Error` variant on failure.
-    pub async fn send_message_raw<E: Executor>(
+    pub async fn send_message_raw<E: DynExecutor + ?Sized>(
         &mut self,
         options: &Options,
         prompt: &Prompt,
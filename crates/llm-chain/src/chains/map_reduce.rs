@@ -7,10 +7,12 @@
 //! The `Chain` struct is generic over the type of the `Step` and provides a convenient way
 //! to execute map-reduce operations using a provided `Executor`.
 
+use crate::frame::FormatAndExecuteError;
+use crate::step::ValidationError;
 use crate::traits::ExecutorError;
 use crate::{
     frame::Frame, output::Output, prompt::Data, serialization::StorableEntity, step::Step, tokens,
-    tokens::PromptTokensError, traits::Executor, Parameters,
+    tokens::PromptTokensError, traits::DynExecutor, Parameters,
 };
 use futures::future::join_all;
 use futures::future::FutureExt;
@@ -32,16 +34,33 @@ pub enum MapReduceChainError {
     InputEmpty,
     #[error("Error templating: {0}")]
     StringTemplate(#[from] crate::prompt::StringTemplateError),
+    #[error("Invalid chain: {0}")]
+    Validation(#[from] ValidationError),
+}
+
+/// The number of documents the map step processes at the same time by default.
+const DEFAULT_CONCURRENCY: usize = 4;
+
+fn default_concurrency() -> usize {
+    DEFAULT_CONCURRENCY
 }
 
 /// The `Chain` struct represents a map-reduce chain, consisting of a `map` step and a `reduce` step.
 ///
 /// The struct is generic over the type of the `Step` and provides methods for constructing and
 /// executing the chain using a given `Executor`.
+///
+/// The `map` prompt is formatted once per document. Prompts built with
+/// `StringTemplate::registered` are parsed once, when added to the `TemplateRegistry`, rather
+/// than for every document.
 #[derive(Serialize, Deserialize)]
 pub struct Chain {
     map: Step,
     reduce: Step,
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    skip_validation: bool,
 }
 
 impl Chain {
@@ -49,17 +68,55 @@ impl Chain {
     ///
     /// The `new` function takes two instances of `Step` and returns a new `Chain` instance.
     pub fn new(map: Step, reduce: Step) -> Chain {
-        Chain { map, reduce }
+        Chain {
+            map,
+            reduce,
+            concurrency: DEFAULT_CONCURRENCY,
+            skip_validation: false,
+        }
+    }
+
+    /// Sets how many documents the `map` step processes at the same time. Defaults to 4.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Chain {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Makes `run` skip `validate`, for prompts whose parameters can't be checked ahead of time,
+    /// such as those set by custom template functions.
+    pub fn without_validation(mut self) -> Chain {
+        self.skip_validation = true;
+        self
+    }
+
+    /// Checks, without running anything, that the steps get the parameters their prompts need.
+    /// The `map` step, at index 0, gets `base_parameters` combined with each document. The
+    /// `reduce` step, at index 1, gets `base_parameters` and `text`, which holds the documents to
+    /// combine.
+    pub fn validate(
+        &self,
+        documents: &[Parameters],
+        base_parameters: &Parameters,
+    ) -> Result<(), ValidationError> {
+        for document in documents {
+            self.map.validate(0, &base_parameters.combine(document))?;
+        }
+        self.reduce.validate(1, &base_parameters.with_text(""))
     }
 
     /// Executes the map-reduce chain using the provided `Executor`.
     ///
     /// The `run` function takes a vector of input documents, a base set of parameters, and a reference
-    /// to an `Executor`. It processes the input documents using the `map` step and the `reduce` step,
+    /// to an `Executor` or a `dyn DynExecutor`. It processes the input documents using the `map` step and the `reduce` step,
     /// and returns the result as an `Option<E::Output>`.
     ///
+    /// Documents whose `map` step fails are left out of the reduction, so one failure doesn't
+    /// discard the rest. The chain only fails if the `map` step fails for every document, or
+    /// before calling the executor if `validate` fails, unless the chain was built
+    /// `without_validation`.
+    ///
     /// The function is asynchronous and must be awaited.
-    pub async fn run<E: Executor>(
+    pub async fn run<E: DynExecutor + ?Sized>(
         &self,
         documents: Vec<Parameters>,
         base_parameters: Parameters,
@@ -68,6 +125,9 @@ impl Chain {
         if documents.is_empty() {
             return Err(MapReduceChainError::InputEmpty);
         }
+        if !self.skip_validation {
+            self.validate(&documents, &base_parameters)?;
+        }
         let map_frame = Frame::new(executor, &self.map);
         let reduce_frame = Frame::new(executor, &self.reduce);
 
@@ -83,31 +143,30 @@ impl Chain {
             .iter()
             .map(|doc| base_parameters.combine(doc))
             .collect();
-        let mapped_documents: Vec<_> = join_all(
-            chunked_docs_with_base_parameters
-                .iter()
-                .map(|doc| map_frame.format_and_execute(doc))
-                .collect::<Vec<_>>(),
-        )
-        .await;
-        let mapped_documents = mapped_documents
-            .into_iter()
-            .collect::<Result<Vec<Output>, _>>()?;
-        let mapped_documents: Vec<Result<Data<String>, ExecutorError>> = join_all(
-            mapped_documents
-                .into_iter()
-                .map(|x| x.to_immediate().map(|x| x.map(|y| y.as_content())))
-                .collect::<Vec<_>>(),
-        )
-        .await;
-        let mapped_documents: Vec<Data<String>> = mapped_documents
-            .into_iter()
-            .collect::<Result<Vec<Data<String>>, ExecutorError>>()
-            .map_err(|e| {
-                MapReduceChainError::FormatAndExecuteError(
-                    crate::frame::FormatAndExecuteError::Execute(e),
-                )
-            })?;
+        let mapped_documents = map_frame
+            .format_and_execute_batch(&chunked_docs_with_base_parameters, self.concurrency)
+            .await;
+        let mapped_documents: Vec<Result<Data<String>, FormatAndExecuteError>> =
+            join_all(mapped_documents.into_iter().map(|output| async move {
+                let immediate = output?.to_immediate().await?;
+                Ok(immediate.as_content())
+            }))
+            .await;
+        let mut mapped = Vec::new();
+        let mut first_error = None;
+        for result in mapped_documents {
+            match result {
+                Ok(doc) => mapped.push(doc),
+                Err(err) => {
+                    log::warn!("skipping a document whose map step failed: {}", err);
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        if let (true, Some(err)) = (mapped.is_empty(), first_error) {
+            return Err(err.into());
+        }
+        let mapped_documents = mapped;
 
         let mut documents = self
             .combine_documents_up_to(executor, mapped_documents, &base_parameters)
@@ -122,8 +181,9 @@ impl Chain {
                 .iter()
                 .map(|doc| base_parameters.with_text(doc))
                 .collect();
-            let futures = tasks.iter().map(|p| reduce_frame.format_and_execute(p));
-            let new_docs = join_all(futures).await;
+            let new_docs = reduce_frame
+                .format_and_execute_batch(&tasks, self.concurrency)
+                .await;
             let new_docs = new_docs.into_iter().collect::<Result<Vec<_>, _>>()?;
             let new_docs = join_all(
                 new_docs
@@ -149,7 +209,7 @@ impl Chain {
         }
     }
 
-    async fn combine_documents_up_to<E: Executor>(
+    async fn combine_documents_up_to<E: DynExecutor + ?Sized>(
         &self,
         executor: &E,
         mut v: Vec<Data<String>>,
@@ -189,12 +249,12 @@ impl Chain {
         step: &Step,
     ) -> Result<Vec<Parameters>, PromptTokensError>
     where
-        E: Executor + 'a,
+        E: DynExecutor + ?Sized + 'a,
     {
         let data: Result<Vec<_>, _> = v
             .iter()
             .map(|x| {
-                <E as tokens::ExecutorTokenCountExt>::split_to_fit(
+                tokens::ExecutorTokenCountExt::split_to_fit(
                     executor,
                     step,
                     x,
@@ -226,3 +286,64 @@ impl StorableEntity for Chain {
         base
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt;
+    use crate::prompt::StringTemplate;
+    use crate::test_util::{TestExecutor, TestTokenizer};
+
+    #[tokio::test]
+    async fn test_run_fails_before_calling_the_executor() {
+        let chain = Chain::new(
+            Step::for_prompt_template(Data::text(StringTemplate::tera("Summarize {{ text }}"))),
+            Step::for_prompt_template(Data::text(StringTemplate::tera(
+                "Combine {{ text }} for {{ audience }}",
+            ))),
+        );
+        let exec = TestExecutor::default();
+        let documents = vec![Parameters::new_with_text("apples")];
+        let res = chain.run(documents, Parameters::new(), &exec).await;
+        assert!(matches!(
+            res,
+            Err(MapReduceChainError::Validation(
+                ValidationError::MissingParameters { step: 1, .. }
+            ))
+        ));
+        assert_eq!(exec.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_map_reduce_skips_failed_documents() {
+        let chain = Chain::new(
+            Step::for_prompt_template(prompt!("{{text}}")),
+            Step::for_prompt_template(prompt!("{{text}}")),
+        )
+        .with_concurrency(1);
+        let exec = TestExecutor::echoing()
+            .failing_on("fail")
+            .with_tokenizer(TestTokenizer::Chars);
+        let documents = vec![
+            Parameters::new_with_text("apples"),
+            Parameters::new_with_text("fail"),
+            Parameters::new_with_text("pears"),
+        ];
+        let res = chain
+            .run(documents, Parameters::new(), &exec)
+            .await
+            .unwrap()
+            .to_string();
+        assert!(res.contains("apples") && res.contains("pears"));
+        assert!(!res.contains("fail"));
+
+        let res = chain
+            .run(
+                vec![Parameters::new_with_text("fail")],
+                Parameters::new(),
+                &exec,
+            )
+            .await;
+        assert!(res.is_err());
+    }
+}
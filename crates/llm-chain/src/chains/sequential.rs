@@ -30,8 +30,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::frame::FormatAndExecuteError;
 use crate::output::Output;
+use crate::parsing::ExtractionError;
+use crate::step::ValidationError;
 use crate::{
-    frame::Frame, serialization::StorableEntity, step::Step, traits::Executor, Parameters,
+    frame::Frame, serialization::StorableEntity, step::Step, traits::DynExecutor, Parameters,
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -42,12 +44,21 @@ pub enum SequentialChainError {
     FormatAndExecuteError(#[from] FormatAndExecuteError),
     #[error("The vector of steps was empty")]
     NoSteps,
+    #[error("Invalid chain: {0}")]
+    Validation(#[from] ValidationError),
+    #[error("The answer of step {step} couldn't be parsed: {source}")]
+    OutputParsing {
+        step: usize,
+        source: ExtractionError,
+    },
 }
 
 /// A sequential chain is a chain where each step is executed in order, with the output of the previous step being available to the next step.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Chain {
     steps: Vec<Step>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    skip_validation: bool,
 }
 
 impl Chain {
@@ -57,7 +68,10 @@ impl Chain {
     ///
     /// * `steps` - A vector of `Step<E>` objects that define the sequence of steps for the chain.
     pub fn new(steps: Vec<Step>) -> Chain {
-        Chain { steps }
+        Chain {
+            steps,
+            skip_validation: false,
+        }
     }
 
     /// Creates a new `Chain` instance with a single step.
@@ -66,18 +80,42 @@ impl Chain {
     ///
     /// * `step` - A `Step<E>` object that defines the single step for the chain.
     pub fn of_one(step: Step) -> Chain {
-        Chain { steps: vec![step] }
+        Self::new(vec![step])
+    }
+
+    /// Makes `run` skip `validate`, for prompts whose parameters can't be checked ahead of time,
+    /// such as those set by custom template functions.
+    pub fn without_validation(mut self) -> Chain {
+        self.skip_validation = true;
+        self
+    }
+
+    /// Checks, without running anything, that every step gets the parameters its prompt needs:
+    /// those in `parameters`, `text`, which holds the output of the previous step, and the output
+    /// keys of the previous steps.
+    pub fn validate(&self, parameters: &Parameters) -> Result<(), ValidationError> {
+        let mut parameters = parameters.clone();
+        for (index, step) in self.steps.iter().enumerate() {
+            step.validate(index, &parameters)?;
+            parameters = parameters.with_text("");
+            if let Some(key) = step.output_key() {
+                parameters = parameters.with_value(key, serde_json::Value::Null);
+            }
+        }
+        Ok(())
     }
 
     /// Executes the chain with the given parameters and executor.
     ///
     /// This method runs each step in the chain in sequence, passing the output of the previous step to the next step.
-    /// If the chain is empty, an error is returned.
+    /// The output of a step with an output key is also parsed and passed under that key, see `Step::with_output_key`.
+    /// If the chain is empty, or `validate` fails, an error is returned before the executor is called, unless the
+    /// chain was built `without_validation`.
     ///
     /// # Arguments
     ///
     /// * `parameters` - A `Parameters` object containing the input parameters for the chain.
-    /// * `executor` - A reference to an executor that implements the `Executor` trait.
+    /// * `executor` - A reference to an executor that implements the `Executor` trait, or a `dyn DynExecutor`.
     ///
     /// # Returns
     ///
@@ -89,14 +127,17 @@ impl Chain {
         executor: &E,
     ) -> Result<Output, SequentialChainError>
     where
-        E: Executor,
+        E: DynExecutor + ?Sized,
     {
         if self.steps.is_empty() {
             return Err(SequentialChainError::NoSteps);
         }
+        if !self.skip_validation {
+            self.validate(&parameters)?;
+        }
         let mut current_params = parameters;
 
-        for step in &self.steps[..self.steps.len() - 1] {
+        for (index, step) in self.steps[..self.steps.len() - 1].iter().enumerate() {
             let body = Frame::new(executor, step)
                 .format_and_execute(&current_params)
                 .await?
@@ -109,7 +150,12 @@ impl Chain {
                 .extract_last_body()
                 .cloned()
                 .unwrap_or_default();
-            current_params = current_params.with_text(body);
+            current_params = step
+                .output_parameters(&current_params, body)
+                .map_err(|source| SequentialChainError::OutputParsing {
+                    step: index,
+                    source,
+                })?;
         }
         let last_step = self.steps.last().unwrap();
         Ok(Frame::new(executor, last_step)
@@ -127,3 +173,131 @@ impl StorableEntity for Chain {
         base
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::{ChatMessageCollection, Data, StringTemplate};
+    use crate::step::ValidationError;
+    use crate::test_util::TestExecutor;
+
+    fn step(template: &str) -> Step {
+        Step::for_prompt_template(Data::text(StringTemplate::tera(template)))
+    }
+
+    #[test]
+    fn test_validate_checks_every_step() {
+        let chain = Chain::new(vec![
+            step("Summarize {{ text }} in {{ language | default(value=\"English\") }}"),
+            step("{% for word in text | split(pat=\" \") %}{{ word }}{% endfor %} for {{ audience }}"),
+        ]);
+        match chain.validate(&"input".into()) {
+            Err(ValidationError::MissingParameters { step, missing }) => {
+                assert_eq!(step, 1);
+                assert_eq!(missing, vec!["audience"]);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let parameters = Parameters::new().with("audience", "children");
+        assert!(matches!(
+            chain.validate(&parameters),
+            Err(ValidationError::MissingParameters { step: 0, .. })
+        ));
+        chain.validate(&parameters.with_text("input")).unwrap();
+
+        let chat = ChatMessageCollection::new()
+            .with_system(StringTemplate::tera("You are {{ persona }}."))
+            .with_user(StringTemplate::static_string("{{ not_a_variable }}"));
+        let chain = Chain::of_one(Step::for_prompt_template(Data::Chat(chat)));
+        assert!(chain.validate(&"input".into()).is_err());
+        chain
+            .validate(&Parameters::new().with("persona", "a poet"))
+            .unwrap();
+
+        // Variables read inside an `if` testing them are optional.
+        let chain = Chain::new(vec![
+            step("Answer {{ text }}{% if note %} ({{ note }}){% endif %}"),
+            step("{% if note is defined %}{{ note }}{% else %}none{% endif %}{{ text }}"),
+        ]);
+        chain.validate(&"input".into()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_fails_before_calling_the_executor() {
+        let chain = Chain::new(vec![
+            step("Summarize {{ text }}"),
+            step("{{ text }} {{ audience }}"),
+        ]);
+        let exec = TestExecutor::default();
+        let res = chain.run("input".into(), &exec).await;
+        assert!(matches!(
+            res,
+            Err(SequentialChainError::Validation(
+                ValidationError::MissingParameters { step: 1, .. }
+            ))
+        ));
+        assert_eq!(exec.calls(), 0);
+
+        // Without validation, the first step runs before the second fails to format.
+        let res = chain.without_validation().run("input".into(), &exec).await;
+        assert!(matches!(
+            res,
+            Err(SequentialChainError::FormatAndExecuteError(_))
+        ));
+        assert_eq!(exec.calls(), 1);
+    }
+
+    #[test]
+    fn test_output_key_passes_the_parsed_answer() {
+        let first = step("List the people in {{ text }}").with_output_key("people");
+        let second = step("{% for p in people.names %}{{ p }};{% endfor %} {{ people.count + 1 }}");
+        let chain = Chain::new(vec![first.clone(), second.clone()]);
+        chain.validate(&"input".into()).unwrap();
+        assert!(Chain::new(vec![step("{{ text }}"), second.clone()])
+            .validate(&"input".into())
+            .is_err());
+
+        let parameters = first
+            .output_parameters(&"input".into(), "names: [Ann, Bob]\ncount: 2".to_string())
+            .unwrap();
+        assert_eq!(
+            parameters.get_text().unwrap(),
+            "names: [Ann, Bob]\ncount: 2"
+        );
+        assert_eq!(second.format(&parameters).unwrap().to_text(), "Ann;Bob; 3");
+    }
+
+    #[test]
+    fn test_output_key_finds_json_in_prose() {
+        let first = step("List the people in {{ text }}").with_output_key("people");
+        let parameters = first
+            .output_parameters(
+                &"input".into(),
+                r#"Here it is: {"names": ["Ann"], "count": 1}"#.to_string(),
+            )
+            .unwrap();
+        let second = step("{% for p in people.names %}{{ p }};{% endfor %} {{ people.count }}");
+        assert_eq!(second.format(&parameters).unwrap().to_text(), "Ann; 1");
+
+        // A one-word introduction also parses as a YAML key.
+        let parameters = first
+            .output_parameters(
+                &"input".into(),
+                r#"Sure: {"names": ["Bob"], "count": 1}"#.to_string(),
+            )
+            .unwrap();
+        assert_eq!(second.format(&parameters).unwrap().to_text(), "Bob; 1");
+    }
+
+    #[tokio::test]
+    async fn test_output_key_rejects_prose() {
+        let first = step("List the people in {{ text }}").with_output_key("people");
+        let chain = Chain::new(vec![first, step("{{ people }}")]);
+        let exec = TestExecutor::replying("The people are Ann and Bob.", 100);
+        let res = chain.run("input".into(), &exec).await;
+        assert!(matches!(
+            res,
+            Err(SequentialChainError::OutputParsing { step: 0, .. })
+        ));
+    }
+}
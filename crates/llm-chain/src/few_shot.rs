@@ -0,0 +1,357 @@
+//! Few-shot examples, and selectors picking the ones to put in a prompt.
+//!
+//! A pool of examples is often too large to fit in a prompt, and only some of the examples are
+//! relevant to a given input. An [`ExampleSelector`] picks examples for an input: the
+//! [`LengthBasedExampleSelector`] takes as many as fit in a token budget, while the
+//! [`SemanticSimilarityExampleSelector`] and [`VectorStoreExampleSelector`] take those whose input
+//! is the most similar to it. The selected examples are added to a prompt template with
+//! [`with_examples`], either as a conversation or as a block of text.
+//!
+//! ```ignore
+//! let selector = LengthBasedExampleSelector::for_executor(&exec, &options, pool, 500)?;
+//! let examples = selector.select(&question).await?;
+//! let step = Step::for_prompt_template(with_examples(&prompt!("{{ text }}"), &examples));
+//! let output = step.run(&parameters!(question), &exec).await?;
+//! ```
+
+use std::error::Error;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::options::Options;
+use crate::prompt::{
+    ChatMessage, ChatMessageCollection, ChatRole, ConversationTemplate, Data, PromptTemplate,
+    StringTemplate,
+};
+use crate::schema::Document;
+use crate::tokens::{Tokenizer, TokenizerError};
+use crate::traits::{Embeddings, Executor, VectorStore};
+
+/// An example of the task: an input and the expected output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Example {
+    pub input: String,
+    pub output: String,
+}
+
+impl Example {
+    pub fn new<I: Into<String>, O: Into<String>>(input: I, output: O) -> Self {
+        Self {
+            input: input.into(),
+            output: output.into(),
+        }
+    }
+
+    /// Returns the example as it appears in a block of text, see `to_text`.
+    pub fn to_text(&self) -> String {
+        format!("Input: {}\nOutput: {}", self.input, self.output)
+    }
+}
+
+/// Picks the examples to show the model for an input.
+#[async_trait]
+pub trait ExampleSelector {
+    type Error: Debug + Error + Send;
+
+    /// Returns the examples for `input`, in the order they should appear in the prompt.
+    async fn select(&self, input: &str) -> Result<Vec<Example>, Self::Error>;
+}
+
+/// Returns the examples as a conversation, each one a user message answered by the assistant.
+/// The examples are static text, so braces in them aren't read as template syntax.
+pub fn to_conversation(examples: &[Example]) -> ConversationTemplate {
+    let mut conversation = ChatMessageCollection::new();
+    for example in examples {
+        conversation.add_message(ChatMessage::new(
+            ChatRole::User,
+            StringTemplate::static_string(example.input.clone()),
+        ));
+        conversation.add_message(ChatMessage::new(
+            ChatRole::Assistant,
+            StringTemplate::static_string(example.output.clone()),
+        ));
+    }
+    conversation
+}
+
+/// Returns the examples as a block of text, `Input:` and `Output:` pairs separated by blank lines.
+pub fn to_text(examples: &[Example]) -> String {
+    examples
+        .iter()
+        .map(Example::to_text)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Adds the examples to `prompt`. A chat prompt gets them as a conversation after its system
+/// messages, and a text prompt gets them as a block of text before its text.
+///
+/// ```
+/// use llm_chain::few_shot::{with_examples, Example};
+/// use llm_chain::{prompt, Parameters};
+/// let examples = vec![Example::new("2 + 2", "4"), Example::new("3 * 3", "9")];
+/// let template = with_examples(&prompt!("Input: {{ text }}\nOutput:"), &examples);
+/// let parameters: Parameters = "5 - 1".into();
+/// assert_eq!(
+///     template.format(&parameters).unwrap().to_text(),
+///     "Input: 2 + 2\nOutput: 4\n\nInput: 3 * 3\nOutput: 9\n\nInput: 5 - 1\nOutput:"
+/// );
+///
+/// let template = with_examples(&prompt!("You compute.", "{{ text }}"), &examples);
+/// let chat = template.format(&parameters).unwrap().to_chat();
+/// assert_eq!(chat.len(), 6);
+/// assert_eq!(chat.iter().nth(1).unwrap().body(), "2 + 2");
+/// ```
+pub fn with_examples(prompt: &PromptTemplate, examples: &[Example]) -> PromptTemplate {
+    if examples.is_empty() {
+        return prompt.clone();
+    }
+    match prompt {
+        Data::Text(template) => Data::Text(StringTemplate::combine(vec![
+            StringTemplate::static_string(format!("{}\n\n", to_text(examples))),
+            template.clone(),
+        ])),
+        Data::Chat(chat) => {
+            let mut messages = chat.iter().cloned().peekable();
+            let mut conversation = ChatMessageCollection::new();
+            while let Some(message) = messages.next_if(|m| m.role() == &ChatRole::System) {
+                conversation.add_message(message);
+            }
+            for message in to_conversation(examples).iter().cloned().chain(messages) {
+                conversation.add_message(message);
+            }
+            Data::Chat(conversation)
+        }
+    }
+}
+
+/// Selects the examples, in their order, until their tokens and those of the input fill a budget.
+/// Selection stops at the first example that doesn't fit.
+pub struct LengthBasedExampleSelector<T> {
+    examples: Vec<Example>,
+    tokenizer: T,
+    max_tokens: usize,
+}
+
+impl<T: Tokenizer> LengthBasedExampleSelector<T> {
+    /// Creates a selector counting tokens with `tokenizer`, with a budget of `max_tokens`.
+    pub fn new(examples: Vec<Example>, tokenizer: T, max_tokens: usize) -> Self {
+        Self {
+            examples,
+            tokenizer,
+            max_tokens,
+        }
+    }
+}
+
+impl<'a, T: Tokenizer> LengthBasedExampleSelector<T> {
+    /// Creates a selector counting tokens with the tokenizer of `executor` for `options`.
+    pub fn for_executor<E>(
+        executor: &'a E,
+        options: &Options,
+        examples: Vec<Example>,
+        max_tokens: usize,
+    ) -> Result<Self, TokenizerError>
+    where
+        E: Executor<StepTokenizer<'a> = T>,
+    {
+        Ok(Self::new(
+            examples,
+            executor.get_tokenizer(options)?,
+            max_tokens,
+        ))
+    }
+}
+
+#[async_trait]
+impl<T: Tokenizer + Send + Sync> ExampleSelector for LengthBasedExampleSelector<T> {
+    type Error = TokenizerError;
+
+    async fn select(&self, input: &str) -> Result<Vec<Example>, TokenizerError> {
+        let mut remaining = self
+            .max_tokens
+            .saturating_sub(self.tokenizer.tokenize_str(input)?.len());
+        let mut selected = Vec::new();
+        for example in &self.examples {
+            let tokens = self.tokenizer.tokenize_str(&example.to_text())?.len();
+            if tokens > remaining {
+                break;
+            }
+            remaining -= tokens;
+            selected.push(example.clone());
+        }
+        Ok(selected)
+    }
+}
+
+/// Selects the `k` examples whose input is the most similar to the input, by the cosine
+/// similarity of their embeddings. The most similar example comes last, closest to the input.
+pub struct SemanticSimilarityExampleSelector<E> {
+    embeddings: E,
+    examples: Vec<(Example, Vec<f32>)>,
+    k: usize,
+}
+
+impl<E: Embeddings> SemanticSimilarityExampleSelector<E> {
+    /// Creates a selector, embedding the inputs of the examples with `embeddings` once.
+    pub async fn new(embeddings: E, examples: Vec<Example>, k: usize) -> Result<Self, E::Error> {
+        let inputs = examples.iter().map(|e| e.input.clone()).collect();
+        let vectors = embeddings.embed_texts(inputs).await?;
+        Ok(Self {
+            embeddings,
+            examples: examples.into_iter().zip(vectors).collect(),
+            k,
+        })
+    }
+}
+
+#[async_trait]
+impl<E: Embeddings + Send + Sync> ExampleSelector for SemanticSimilarityExampleSelector<E> {
+    type Error = E::Error;
+
+    async fn select(&self, input: &str) -> Result<Vec<Example>, E::Error> {
+        let query = self.embeddings.embed_query(input.to_string()).await?;
+        let mut scored: Vec<(f32, &Example)> = self
+            .examples
+            .iter()
+            .map(|(example, vector)| (cosine_similarity(&query, vector), example))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored
+            .into_iter()
+            .take(self.k)
+            .rev()
+            .map(|(_, example)| example.clone())
+            .collect())
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// Selects the `k` examples a vector store finds the most similar to the input. The examples are
+/// stored as documents of their input, with the example as metadata. The most similar example
+/// comes last, closest to the input.
+pub struct VectorStoreExampleSelector<E, V> {
+    store: V,
+    k: u32,
+    _embeddings: PhantomData<fn() -> E>,
+}
+
+impl<E, V> VectorStoreExampleSelector<E, V>
+where
+    E: Embeddings,
+    V: VectorStore<E, Example>,
+{
+    /// Creates a selector searching `store`, which may already hold examples.
+    pub fn new(store: V, k: u32) -> Self {
+        Self {
+            store,
+            k,
+            _embeddings: PhantomData,
+        }
+    }
+
+    /// Adds examples to the store.
+    pub async fn add_examples(&self, examples: Vec<Example>) -> Result<Vec<String>, V::Error> {
+        let documents = examples
+            .into_iter()
+            .map(|example| Document {
+                page_content: example.input.clone(),
+                metadata: Some(example),
+            })
+            .collect();
+        self.store.add_documents(documents).await
+    }
+}
+
+#[async_trait]
+impl<E, V> ExampleSelector for VectorStoreExampleSelector<E, V>
+where
+    E: Embeddings,
+    V: VectorStore<E, Example> + Send + Sync,
+    V::Error: Send,
+{
+    type Error = V::Error;
+
+    async fn select(&self, input: &str) -> Result<Vec<Example>, V::Error> {
+        let documents = self
+            .store
+            .similarity_search(input.to_string(), self.k)
+            .await?;
+        Ok(documents
+            .into_iter()
+            .rev()
+            .filter_map(|document| document.metadata)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestTokenizer;
+    use crate::traits::EmbeddingsError;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("unreachable")]
+    struct NoError;
+
+    impl EmbeddingsError for NoError {}
+
+    /// Embeds a text as the number of `a`, `b` and `c` it holds.
+    struct LetterEmbeddings;
+
+    #[async_trait]
+    impl Embeddings for LetterEmbeddings {
+        type Error = NoError;
+
+        async fn embed_texts(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, NoError> {
+            let mut vectors = Vec::new();
+            for text in texts {
+                vectors.push(self.embed_query(text).await?);
+            }
+            Ok(vectors)
+        }
+
+        async fn embed_query(&self, query: String) -> Result<Vec<f32>, NoError> {
+            Ok(['a', 'b', 'c']
+                .iter()
+                .map(|l| query.chars().filter(|c| c == l).count() as f32)
+                .collect())
+        }
+    }
+
+    fn pool() -> Vec<Example> {
+        vec![
+            Example::new("aaa", "one"),
+            Example::new("bbb", "two two"),
+            Example::new("ccc", "three three three"),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_selectors() {
+        // The input takes 2 of the 10 tokens, the first example 4 and the second 5.
+        let selector = LengthBasedExampleSelector::new(pool(), TestTokenizer::Words, 10);
+        let selected = selector.select("two words").await.unwrap();
+        assert_eq!(selected, pool()[..1]);
+
+        let selector = SemanticSimilarityExampleSelector::new(LetterEmbeddings, pool(), 2)
+            .await
+            .unwrap();
+        let selected = selector.select("cc b").await.unwrap();
+        assert_eq!(selected, vec![pool()[1].clone(), pool()[2].clone()]);
+    }
+}
@@ -7,19 +7,36 @@
 //! The `Frame` struct is generic over the `Step` and `Executor` types, ensuring that it can work with any
 //! combination of types that implement the required traits.
 
+use crate::options::{OptionIssue, Options, OptionsCascade};
 use crate::output::Output;
 use crate::step::Step;
 use crate::traits;
 use crate::traits::ExecutorError;
 use crate::Parameters;
 
+/// Checks `options` against the capabilities of `executor`. Options the executor ignores are
+/// logged, and the first value it rejects is returned as an error.
+pub(crate) fn check_options<E>(executor: &E, options: &Options) -> Result<(), FormatAndExecuteError>
+where
+    E: traits::DynExecutor + ?Sized,
+{
+    let cascade = OptionsCascade::new().with_options(options);
+    for issue in executor.capabilities().validate(&cascade) {
+        if issue.is_error() {
+            return Err(FormatAndExecuteError::InvalidOption(issue));
+        }
+        log::warn!("{}", issue);
+    }
+    Ok(())
+}
+
 /// The `Frame` struct represents a combination of a `Step` and an `Executor`.
 ///
 /// It is designed to provide a simple interface for working with different chain types and handling common
 /// behavior for formatting and executing steps.
 pub struct Frame<'l, E>
 where
-    E: traits::Executor,
+    E: traits::DynExecutor + ?Sized,
 {
     executor: &'l E,
     step: &'l Step,
@@ -27,7 +44,7 @@ where
 
 impl<'l, E> Frame<'l, E>
 where
-    E: traits::Executor,
+    E: traits::DynExecutor + ?Sized,
 {
     /// Constructs a new `Frame` with the given `Executor` and `Step`.
     ///
@@ -37,17 +54,68 @@ where
         Self { executor, step }
     }
 
+    /// Checks the options of the step against the capabilities of the executor, see
+    /// `check_options`.
+    fn check_options(&self) -> Result<(), FormatAndExecuteError> {
+        check_options(self.executor, self.step.options())
+    }
+
     /// Formats the step with the provided parameters and executes it using the associated executor.
     ///
     /// This function takes a reference to a `Parameters` struct, formats the step with the provided parameters,
     /// and executes it using the associated executor. The result of the execution is returned as `E::Output`.
+    /// The options of the step are checked against the capabilities of the executor first, see
+    /// `Capabilities::validate`.
     pub async fn format_and_execute(
         &self,
         parameters: &Parameters,
     ) -> Result<Output, FormatAndExecuteError> {
+        self.check_options()?;
         let prompt = self.step.format(parameters)?;
         Ok(self.executor.execute(self.step.options(), &prompt).await?)
     }
+
+    /// Formats the step with every set of parameters and executes the prompts as a batch, running
+    /// at most `concurrency` calls at once.
+    ///
+    /// Returns one result per set of parameters, in order. Parameters that fail to format are
+    /// reported without being executed, and don't affect the others. If the options of the step
+    /// are rejected by the executor, every result is that error, and prompts the executor returns
+    /// no result for fail with `ExecutorError::InnerError`.
+    pub async fn format_and_execute_batch(
+        &self,
+        parameters: &[Parameters],
+        concurrency: usize,
+    ) -> Vec<Result<Output, FormatAndExecuteError>> {
+        if let Err(FormatAndExecuteError::InvalidOption(issue)) = self.check_options() {
+            return parameters
+                .iter()
+                .map(|_| Err(FormatAndExecuteError::InvalidOption(issue.clone())))
+                .collect();
+        }
+        let formatted: Vec<_> = parameters.iter().map(|p| self.step.format(p)).collect();
+        let prompts: Vec<_> = formatted
+            .iter()
+            .filter_map(|prompt| prompt.as_ref().ok().cloned())
+            .collect();
+        let mut outputs = self
+            .executor
+            .execute_batch(self.step.options(), &prompts, concurrency)
+            .await
+            .into_iter();
+        formatted
+            .into_iter()
+            .map(|prompt| match prompt {
+                // `execute_batch` can be overridden, and an override may return too few results.
+                Ok(_) => Ok(outputs.next().unwrap_or_else(|| {
+                    Err(ExecutorError::InnerError(
+                        "the executor returned fewer results than prompts".into(),
+                    ))
+                })?),
+                Err(err) => Err(err.into()),
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -57,4 +125,110 @@ pub enum FormatAndExecuteError {
     Format(#[from] crate::prompt::StringTemplateError),
     #[error("Error executing: {0}")]
     Execute(#[from] ExecutorError),
+    #[error("Invalid option: {0}")]
+    InvalidOption(OptionIssue),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::delegate_to_inner;
+    use crate::options;
+    use crate::options::{Capabilities, OptDiscriminants, Options};
+    use crate::prompt::{Data, Prompt, StringTemplate};
+    use crate::test_util::{TestExecutor, TestTokenizer};
+    use crate::traits::{Executor, ExecutorCreationError};
+    use async_trait::async_trait;
+
+    /// Answers only the first prompt of a batch.
+    struct FirstOfBatch {
+        inner: TestExecutor,
+    }
+
+    #[async_trait]
+    impl Executor for FirstOfBatch {
+        type StepTokenizer<'a> = TestTokenizer;
+
+        fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
+            Ok(Self {
+                inner: TestExecutor::new_with_options(options)?,
+            })
+        }
+
+        async fn execute(
+            &self,
+            options: &Options,
+            prompt: &Prompt,
+        ) -> Result<Output, ExecutorError> {
+            self.inner.execute(options, prompt).await
+        }
+
+        async fn execute_batch(
+            &self,
+            options: &Options,
+            prompts: &[Prompt],
+            _: usize,
+        ) -> Vec<Result<Output, ExecutorError>> {
+            match prompts.first() {
+                Some(prompt) => vec![self.inner.execute(options, prompt).await],
+                None => Vec::new(),
+            }
+        }
+
+        delegate_to_inner!();
+    }
+
+    #[tokio::test]
+    async fn test_batch_reports_missing_results() {
+        let exec = FirstOfBatch {
+            inner: TestExecutor::default(),
+        };
+        let step = Step::for_prompt_template(Data::text(StringTemplate::tera("{{ text }}")));
+        let res = Frame::new(&exec, &step)
+            .format_and_execute_batch(&["a".into(), "b".into()], 2)
+            .await;
+        assert!(res[0].is_ok());
+        assert!(matches!(
+            res[1],
+            Err(FormatAndExecuteError::Execute(ExecutorError::InnerError(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_option_values_fail_before_executing() {
+        let exec = TestExecutor::default().with_capabilities(
+            Capabilities::new()
+                .supports(OptDiscriminants::Model)
+                .supports_between(OptDiscriminants::Temperature, 0.0, 2.0),
+        );
+        let step = Step::for_prompt_and_options(
+            Data::text(StringTemplate::tera("{{ text }}")),
+            options!(Temperature: 3.0),
+        );
+        let frame = Frame::new(&exec, &step);
+        let res = frame.format_and_execute(&"hi".into()).await;
+        assert!(matches!(
+            res,
+            Err(FormatAndExecuteError::InvalidOption(issue))
+                if issue.option == OptDiscriminants::Temperature
+        ));
+        let res = frame
+            .format_and_execute_batch(&["a".into(), "b".into()], 2)
+            .await;
+        assert!(res
+            .iter()
+            .all(|r| matches!(r, Err(FormatAndExecuteError::InvalidOption(_)))));
+        assert_eq!(exec.calls(), 0);
+
+        // Options the executor ignores are only logged.
+        let step = Step::for_prompt_and_options(
+            Data::text(StringTemplate::tera("{{ text }}")),
+            options!(TopK: 40),
+        );
+        Frame::new(&exec, &step)
+            .format_and_execute(&"hi".into())
+            .await
+            .unwrap();
+        assert_eq!(exec.calls(), 1);
+    }
 }
@@ -27,6 +27,7 @@ pub mod chains;
 pub mod document_stores;
 pub mod executor;
 pub mod frame;
+pub mod middleware;
 pub mod options;
 pub mod output;
 pub mod parameters;
@@ -35,11 +36,16 @@ pub mod prompt;
 pub mod schema;
 pub mod serialization;
 pub mod step;
+pub mod structured;
 pub mod tokens;
 pub mod tools;
 pub mod traits;
 
+#[cfg(test)]
+pub(crate) mod test_util;
+
 // Utilities and tools
+pub mod few_shot;
 pub mod summarization;
 
 // Re-exports for convenient usage
@@ -0,0 +1,431 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::delegate_to_inner;
+use crate::options::{Opt, OptDiscriminants, Options, OptionsCascade};
+use crate::output::{Candidate, Output, OutputStream, RecordedSegment, ResponseMetadata};
+use crate::prompt::Prompt;
+use crate::traits::{Executor, ExecutorCreationError, ExecutorError};
+
+/// A response as stored in a `CacheStore`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CachedOutput {
+    /// The candidates and metadata of an immediate output.
+    Immediate {
+        candidates: Vec<Candidate>,
+        #[serde(default)]
+        metadata: ResponseMetadata,
+    },
+    /// The segments of a streaming output, in the order they were received.
+    Stream(Vec<RecordedSegment>),
+}
+
+impl CachedOutput {
+    /// Turns the cached response back into an `Output` of the same kind, or returns `None` for an
+    /// immediate response without candidates, which no `Output` can hold.
+    pub fn replay(self) -> Option<Output> {
+        match self {
+            CachedOutput::Immediate { candidates, .. } if candidates.is_empty() => None,
+            CachedOutput::Immediate {
+                candidates,
+                metadata,
+            } => Some(Output::new_immediate_with_candidates(candidates, metadata)),
+            CachedOutput::Stream(segments) => {
+                let (sender, output) = Output::new_stream();
+                for segment in segments {
+                    // The receiver is still held by `output`, so this can't fail.
+                    let _ = sender.send(segment.into());
+                }
+                Some(output)
+            }
+        }
+    }
+}
+
+/// An error reading from or writing to a `CacheStore`.
+#[derive(thiserror::Error, Debug)]
+pub enum CacheStoreError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// The storage backing a `Cache`.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    /// Returns the response stored under `key`, if any.
+    async fn get(&self, key: &str) -> Result<Option<CachedOutput>, CacheStoreError>;
+
+    /// Stores `value` under `key`, replacing any previous value.
+    async fn put(&self, key: &str, value: CachedOutput) -> Result<(), CacheStoreError>;
+}
+
+/// An in-memory `CacheStore` that evicts the least recently used response once it holds
+/// `capacity` of them.
+pub struct MemoryStore {
+    capacity: usize,
+    entries: Mutex<LruEntries>,
+}
+
+#[derive(Default)]
+struct LruEntries {
+    values: HashMap<String, CachedOutput>,
+    /// The keys, from least to most recently used.
+    order: VecDeque<String>,
+}
+
+impl LruEntries {
+    fn touch(&mut self, key: &str) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(pos).unwrap();
+            self.order.push_back(key);
+        }
+    }
+}
+
+impl MemoryStore {
+    /// Creates a store holding at most `capacity` responses.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(LruEntries::default()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    /// Creates a store holding at most 1000 responses.
+    fn default() -> Self {
+        Self::new(1000)
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<CachedOutput>, CacheStoreError> {
+        let mut entries = self.entries.lock().unwrap();
+        let value = entries.values.get(key).cloned();
+        if value.is_some() {
+            entries.touch(key);
+        }
+        Ok(value)
+    }
+
+    async fn put(&self, key: &str, value: CachedOutput) -> Result<(), CacheStoreError> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.values.insert(key.to_string(), value).is_some() {
+            entries.touch(key);
+        } else {
+            entries.order.push_back(key.to_string());
+        }
+        while entries.order.len() > self.capacity {
+            if let Some(evicted) = entries.order.pop_front() {
+                entries.values.remove(&evicted);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `CacheStore` that keeps every response as a JSON file in a directory, so the cache survives
+/// restarts. Entries are never evicted; delete the directory to clear the cache.
+pub struct DiskStore {
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    /// Creates a store in `dir`. The directory is created when the first response is stored.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+impl Default for DiskStore {
+    /// Creates a store in `.llm-chain-cache` in the working directory.
+    fn default() -> Self {
+        Self::new(".llm-chain-cache")
+    }
+}
+
+#[async_trait]
+impl CacheStore for DiskStore {
+    async fn get(&self, key: &str) -> Result<Option<CachedOutput>, CacheStoreError> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn put(&self, key: &str, value: CachedOutput) -> Result<(), CacheStoreError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let bytes = serde_json::to_vec(&value)?;
+        // Write to a temporary file first so concurrent readers never see a partial entry.
+        let tmp = self.dir.join(format!("{}.json.tmp", key));
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, self.path(key)).await?;
+        Ok(())
+    }
+}
+
+/// An executor that caches the responses of the inner executor.
+///
+/// Responses are keyed on a SHA-256 hash of the prompt and the effective options, i.e. the
+/// options the inner executor was created with, given to `Cache::new`, overridden by the
+/// per-invocation options. Executors with different models or defaults sharing a store so never
+/// serve each other's answers. The API key is left out of the key, so cached responses can be
+/// shared without leaking it and survive key rotation.
+///
+/// Streaming outputs are passed through while they are recorded, and replayed as streams on a
+/// cache hit. Streams that fail or are dropped before their end are not cached. Errors from the
+/// store are logged and otherwise ignored, so a broken cache never fails a call.
+pub struct Cache<E, S> {
+    inner: E,
+    store: Arc<S>,
+    options: Options,
+}
+
+impl<E, S> Cache<E, S> {
+    /// Wraps `inner`, caching its responses in `store`. `options` are the options `inner` was
+    /// created with, which become part of the cache key.
+    pub fn new(inner: E, store: S, options: Options) -> Self {
+        Self {
+            inner,
+            store: Arc::new(store),
+            options,
+        }
+    }
+
+    /// Returns a reference to the wrapped executor.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Unwraps the middleware, returning the inner executor.
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    /// Computes the cache key for a call, or `None` if the call can't be serialized.
+    fn key(&self, options: &Options, prompt: &Prompt) -> Option<String> {
+        let cascade = OptionsCascade::from_vec(vec![&self.options, options]);
+        let mut opts: Vec<(String, &Opt)> = cascade
+            .effective_options()
+            .into_iter()
+            .filter(|opt| !matches!(opt, Opt::ApiKey(_)))
+            .map(|opt| (format!("{:?}", OptDiscriminants::from(opt)), opt))
+            .collect();
+        opts.sort_by(|a, b| a.0.cmp(&b.0));
+        let opts: Vec<&Opt> = opts.into_iter().map(|(_, opt)| opt).collect();
+        let payload = serde_json::to_vec(&(prompt, opts)).ok()?;
+        Some(hex::encode(Sha256::digest(payload)))
+    }
+}
+
+impl<E, S> Cache<E, S>
+where
+    S: CacheStore + 'static,
+{
+    async fn store(store: &S, key: &str, value: CachedOutput) {
+        if let Err(err) = store.put(key, value).await {
+            log::warn!("failed to write to the response cache: {}", err);
+        }
+    }
+
+    /// Forwards `stream` to a new output while recording it, storing the recording at its end.
+    fn record_stream(&self, key: String, stream: OutputStream) -> Output {
+        let store = self.store.clone();
+        stream.record(|segments| async move {
+            Self::store(&store, &key, CachedOutput::Stream(segments)).await;
+            Ok(())
+        })
+    }
+}
+
+#[async_trait]
+impl<E, S> Executor for Cache<E, S>
+where
+    E: Executor + Send + Sync,
+    S: CacheStore + Default + 'static,
+{
+    type StepTokenizer<'a>
+        = E::StepTokenizer<'a>
+    where
+        Self: 'a;
+
+    /// Creates the inner executor with `options` and caches in the default store.
+    fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
+        Ok(Self::new(
+            E::new_with_options(options.clone())?,
+            S::default(),
+            options,
+        ))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        let Some(key) = self.key(options, prompt) else {
+            return self.inner.execute(options, prompt).await;
+        };
+        match self.store.get(&key).await {
+            Ok(Some(cached)) => match cached.replay() {
+                Some(output) => return Ok(output),
+                None => log::warn!("ignoring an empty response in the response cache"),
+            },
+            Ok(None) => {}
+            Err(err) => log::warn!("failed to read from the response cache: {}", err),
+        }
+        match self.inner.execute(options, prompt).await? {
+            Output::Immediate(immediate) => {
+                let (candidates, metadata) = immediate.into_parts();
+                let cached = CachedOutput::Immediate {
+                    candidates: candidates.clone(),
+                    metadata: metadata.clone(),
+                };
+                Self::store(&self.store, &key, cached).await;
+                Ok(Output::new_immediate_with_candidates(candidates, metadata))
+            }
+            Output::Stream(stream) => Ok(self.record_stream(key, stream)),
+        }
+    }
+
+    delegate_to_inner!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use crate::options::ModelRef;
+    use crate::output::FinishReason;
+    use crate::prompt::{ChatRole, Data};
+    use crate::test_util::TestExecutor;
+
+    fn prompt() -> Prompt {
+        Prompt::text("hi".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_serves_repeated_calls_from_cache() {
+        let exec = Cache::new(
+            TestExecutor::default(),
+            MemoryStore::default(),
+            Options::default(),
+        );
+        for _ in 0..2 {
+            let res = exec.execute(Options::empty(), &prompt()).await.unwrap();
+            assert_eq!(res.to_string(), "ok");
+        }
+        assert_eq!(exec.inner().calls(), 1);
+
+        exec.execute(&options!(Temperature: 0.5), &prompt())
+            .await
+            .unwrap();
+        assert_eq!(exec.inner().calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_differently_configured_executors_share_a_store() {
+        let dir = std::env::temp_dir().join(format!("llm-chain-cache-{}", std::process::id()));
+        let cache = |reply, model| {
+            Cache::new(
+                TestExecutor::replying(reply, 100),
+                DiskStore::new(&dir),
+                options!(Model: ModelRef::from_model_name(model)),
+            )
+        };
+        let small = cache("small", "small-model");
+        let large = cache("large", "large-model");
+        for _ in 0..2 {
+            let res = small.execute(Options::empty(), &prompt()).await.unwrap();
+            assert_eq!(res.to_string(), "small");
+            let res = large.execute(Options::empty(), &prompt()).await.unwrap();
+            assert_eq!(res.to_string(), "large");
+        }
+        assert_eq!(small.inner().calls(), 1);
+        assert_eq!(large.inner().calls(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_key_ignores_api_key_and_option_order() {
+        let exec = Cache::new(
+            TestExecutor::default(),
+            MemoryStore::default(),
+            Options::default(),
+        );
+        let a = exec.key(
+            &options!(ApiKey: "a", TopP: 0.5, Temperature: 0.1),
+            &prompt(),
+        );
+        let b = exec.key(
+            &options!(Temperature: 0.1, TopP: 0.5, ApiKey: "b"),
+            &prompt(),
+        );
+        assert_eq!(a, b);
+        let c = exec.key(&options!(Temperature: 0.2, TopP: 0.5), &prompt());
+        assert_ne!(a, c);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_evicts_least_recently_used() {
+        let store = MemoryStore::new(2);
+        let value = || CachedOutput::Immediate {
+            candidates: vec![Candidate::new(Data::text("x".to_string()))],
+            metadata: ResponseMetadata::default(),
+        };
+        store.put("a", value()).await.unwrap();
+        store.put("b", value()).await.unwrap();
+        store.get("a").await.unwrap();
+        store.put("c", value()).await.unwrap();
+        assert!(store.get("a").await.unwrap().is_some());
+        assert!(store.get("b").await.unwrap().is_none());
+        assert!(store.get("c").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_empty_entries_are_misses() {
+        let exec = Cache::new(
+            TestExecutor::default(),
+            MemoryStore::default(),
+            Options::default(),
+        );
+        let key = exec.key(Options::empty(), &prompt()).unwrap();
+        let empty = CachedOutput::Immediate {
+            candidates: Vec::new(),
+            metadata: ResponseMetadata::default(),
+        };
+        exec.store.put(&key, empty).await.unwrap();
+        let res = exec.execute(Options::empty(), &prompt()).await.unwrap();
+        assert_eq!(res.to_string(), "ok");
+        assert_eq!(exec.inner().calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replays_streams() {
+        let cached = CachedOutput::Stream(vec![
+            RecordedSegment::Role(ChatRole::Assistant),
+            RecordedSegment::Content("Hello ".to_string()),
+            RecordedSegment::Content("world".to_string()),
+            RecordedSegment::Metadata(ResponseMetadata {
+                finish_reason: Some(FinishReason::Stop),
+                ..Default::default()
+            }),
+        ]);
+        let replayed = cached.replay().unwrap().to_immediate().await.unwrap();
+        assert_eq!(
+            replayed.primary_textual_output(),
+            Some("Hello world".to_string())
+        );
+        assert_eq!(replayed.metadata().finish_reason, Some(FinishReason::Stop));
+    }
+}
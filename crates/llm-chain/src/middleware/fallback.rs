@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+
+use crate::options::{Capabilities, Options};
+use crate::output::Output;
+use crate::prompt::Prompt;
+use crate::tokens::{DynTokenizer, PromptTokensError, TokenCount, TokenizerError};
+use crate::traits::{DynExecutor, Executor, ExecutorCreationError, ExecutorError};
+
+type ErrorPredicate = Box<dyn Fn(&ExecutorError) -> bool + Send + Sync>;
+
+/// An executor that tries a primary executor and falls back to the next one in line when a call
+/// fails with a chosen kind of error.
+///
+/// By default it falls back on retryable errors, see `ExecutorError::is_retryable`. The executors
+/// may use different backends. Token counting and the tokenizer are those of the primary executor,
+/// while the capabilities are those every executor shares, as any of them may handle a call.
+///
+/// # Example
+///
+/// ```ignore
+/// let exec = Fallback::new(openai_executor)
+///     .or(azure_executor)
+///     .falling_back_on(|err| matches!(err, ExecutorError::RateLimited(_)));
+/// ```
+pub struct Fallback {
+    executors: Vec<Box<dyn DynExecutor>>,
+    should_fall_back: ErrorPredicate,
+}
+
+impl Fallback {
+    /// Creates a fallback chain with `primary` as its first executor.
+    pub fn new<E: DynExecutor + 'static>(primary: E) -> Self {
+        Self {
+            executors: vec![Box::new(primary)],
+            should_fall_back: Box::new(ExecutorError::is_retryable),
+        }
+    }
+
+    /// Adds `executor` to the end of the chain.
+    pub fn or<E: DynExecutor + 'static>(mut self, executor: E) -> Self {
+        self.executors.push(Box::new(executor));
+        self
+    }
+
+    /// Sets which errors cause the next executor to be tried. Other errors are returned as is.
+    pub fn falling_back_on<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&ExecutorError) -> bool + Send + Sync + 'static,
+    {
+        self.should_fall_back = Box::new(predicate);
+        self
+    }
+
+    fn primary(&self) -> &dyn DynExecutor {
+        self.executors[0].as_ref()
+    }
+}
+
+#[async_trait]
+impl Executor for Fallback {
+    type StepTokenizer<'a> = DynTokenizer<'a>;
+
+    /// A fallback chain can't be created from options, use `Fallback::new` instead.
+    fn new_with_options(_: Options) -> Result<Self, ExecutorCreationError> {
+        Err(ExecutorCreationError::FieldRequiredError(
+            "primary executor".to_string(),
+        ))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        let (last, rest) = self.executors.split_last().unwrap();
+        for executor in rest {
+            match executor.execute(options, prompt).await {
+                Err(err) if (self.should_fall_back)(&err) => {
+                    log::info!("falling back to the next executor after: {}", err);
+                }
+                res => return res,
+            }
+        }
+        last.execute(options, prompt).await
+    }
+
+    fn tokens_used(
+        &self,
+        options: &Options,
+        prompt: &Prompt,
+    ) -> Result<TokenCount, PromptTokensError> {
+        self.primary().tokens_used(options, prompt)
+    }
+
+    fn max_tokens_allowed(&self, options: &Options) -> i32 {
+        self.primary().max_tokens_allowed(options)
+    }
+
+    fn answer_prefix(&self, prompt: &Prompt) -> Option<String> {
+        self.primary().answer_prefix(prompt)
+    }
+
+    fn get_tokenizer(&self, options: &Options) -> Result<DynTokenizer<'_>, TokenizerError> {
+        self.primary().get_tokenizer(options)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let (primary, rest) = self.executors.split_first().unwrap();
+        rest.iter()
+            .fold(primary.capabilities(), |capabilities, executor| {
+                capabilities.intersection(&executor.capabilities())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{OptDiscriminants, ValueRange};
+    use crate::test_util::TestExecutor;
+
+    async fn run(exec: &Fallback) -> Result<String, ExecutorError> {
+        let prompt = Prompt::text("hi".to_string());
+        let res = Executor::execute(exec, Options::empty(), &prompt).await?;
+        Ok(res.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_on_chosen_errors_only() {
+        let exec = Fallback::new(TestExecutor::failing_with(vec![ExecutorError::Timeout]))
+            .or(TestExecutor::replying("secondary", 100));
+        assert_eq!(run(&exec).await.unwrap(), "secondary");
+
+        let exec = Fallback::new(TestExecutor::failing_with(vec![
+            ExecutorError::InvalidOptions,
+        ]))
+        .or(TestExecutor::replying("secondary", 100));
+        assert!(matches!(
+            run(&exec).await,
+            Err(ExecutorError::InvalidOptions)
+        ));
+
+        let exec = Fallback::new(TestExecutor::failing_with(vec![
+            ExecutorError::InvalidOptions,
+        ]))
+        .or(TestExecutor::replying("secondary", 100))
+        .falling_back_on(|_| true);
+        assert_eq!(run(&exec).await.unwrap(), "secondary");
+    }
+
+    #[tokio::test]
+    async fn test_returns_error_of_last_executor() {
+        let exec = Fallback::new(TestExecutor::failing_with(vec![ExecutorError::Timeout])).or(
+            TestExecutor::failing_with(vec![ExecutorError::Network("down".into())]),
+        );
+        assert!(matches!(run(&exec).await, Err(ExecutorError::Network(_))));
+    }
+
+    #[test]
+    fn test_capabilities_are_shared_by_every_executor() {
+        let exec = Fallback::new(
+            TestExecutor::default().with_capabilities(
+                Capabilities::new()
+                    .supports(OptDiscriminants::Model)
+                    .supports_between(OptDiscriminants::Temperature, 0.0, 2.0)
+                    .supports(OptDiscriminants::TopK),
+            ),
+        )
+        .or(TestExecutor::default().with_capabilities(
+            Capabilities::new()
+                .supports(OptDiscriminants::Model)
+                .supports_between(OptDiscriminants::Temperature, 0.0, 1.0),
+        ));
+        let capabilities = Executor::capabilities(&exec);
+        assert!(capabilities.is_supported(OptDiscriminants::Model));
+        assert!(!capabilities.is_supported(OptDiscriminants::TopK));
+        assert_eq!(
+            capabilities.range(OptDiscriminants::Temperature),
+            Some(&ValueRange::Between { min: 0.0, max: 1.0 })
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Instant;
+
+use async_trait::async_trait;
+
+use super::delegate_to_inner;
+use crate::options::Options;
+use crate::output::Output;
+use crate::prompt::Prompt;
+use crate::traits::{Executor, ExecutorCreationError, ExecutorError};
+
+/// An executor that logs calls to the inner executor through the `log` crate.
+///
+/// Prompts and immediate responses are logged at the `debug` level, the duration of successful
+/// calls at `info` and failures at `warn`. Streaming responses are not consumed, so only the start
+/// of the stream is logged.
+pub struct Logging<E> {
+    inner: E,
+}
+
+impl<E> Logging<E> {
+    /// Wraps `inner`, logging its calls.
+    pub fn new(inner: E) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the wrapped executor.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Unwraps the middleware, returning the inner executor.
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<E> Executor for Logging<E>
+where
+    E: Executor + Send + Sync,
+{
+    type StepTokenizer<'a>
+        = E::StepTokenizer<'a>
+    where
+        Self: 'a;
+
+    /// Creates the inner executor with `options`.
+    fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
+        Ok(Self::new(E::new_with_options(options)?))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        log::debug!("executing prompt: {}", prompt);
+        let start = Instant::now();
+        let res = self.inner.execute(options, prompt).await;
+        let elapsed = start.elapsed();
+        match &res {
+            Ok(Output::Immediate(immediate)) => {
+                log::debug!("response: {}", immediate.get_content());
+                if let Some(usage) = immediate.metadata().usage {
+                    log::debug!(
+                        "tokens used: {} prompt, {} completion",
+                        usage.prompt_tokens,
+                        usage.completion_tokens
+                    );
+                }
+                log::info!("executor call succeeded in {:?}", elapsed);
+            }
+            Ok(Output::Stream(_)) => {
+                log::info!("executor call started a stream in {:?}", elapsed);
+            }
+            Err(err) => {
+                log::warn!("executor call failed after {:?}: {}", elapsed, err);
+            }
+        }
+        res
+    }
+
+    delegate_to_inner!();
+}
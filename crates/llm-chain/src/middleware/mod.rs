@@ -0,0 +1,121 @@
+//! Composable executor middleware.
+//!
+//! The types in this module wrap an inner executor and implement `traits::Executor` themselves, so
+//! they can be stacked like layers and used anywhere a plain executor is accepted:
+//!
+//! - [`Retry`] retries transient failures with exponential backoff and jitter.
+//! - [`Timeout`] bounds how long a single call may take.
+//! - [`RateLimit`] limits requests and prompt tokens per minute.
+//! - [`Logging`] logs requests and responses through the `log` crate.
+//! - [`Cache`] stores responses in a [`CacheStore`] and replays them for identical calls.
+//!
+//! Two more executors combine several, possibly different, backends given as `DynExecutor`s:
+//!
+//! - [`Fallback`] tries executors in order until one succeeds or fails with an error that
+//!   shouldn't be retried elsewhere.
+//! - [`Router`] picks the executor per call, based on the prompt size or a predicate.
+//!
+//! The [`ExecutorMiddlewareExt`] trait adds builder-style methods to every executor. Layers
+//! added later wrap the earlier ones, so in the example below every retry attempt is subject to
+//! the timeout and the rate limit, and the logger sees the final outcome.
+//!
+//! ```ignore
+//! use llm_chain::middleware::{ExecutorMiddlewareExt, RateLimits, RetryPolicy};
+//!
+//! let exec = llm_chain_openai::chatgpt::Executor::new()?
+//!     .with_rate_limit(RateLimits {
+//!         requests_per_minute: Some(60),
+//!         tokens_per_minute: Some(90_000),
+//!     })
+//!     .with_timeout(std::time::Duration::from_secs(30))
+//!     .with_retry(RetryPolicy::default())
+//!     .with_logging();
+//! ```
+//!
+//! Retries and timeouts apply to obtaining the `Output`. Once a streaming output has been handed
+//! out, errors that occur while reading it are delivered through the stream as usual.
+
+mod cache;
+mod fallback;
+mod logging;
+mod rate_limit;
+mod retry;
+mod router;
+mod timeout;
+
+use std::time::Duration;
+
+pub use cache::{Cache, CacheStore, CacheStoreError, CachedOutput, DiskStore, MemoryStore};
+pub use fallback::Fallback;
+pub use logging::Logging;
+pub use rate_limit::{RateLimit, RateLimits};
+pub use retry::{Retry, RetryPolicy};
+pub use router::Router;
+pub use timeout::Timeout;
+
+use crate::options::Options;
+use crate::traits::Executor;
+
+/// Implements the methods of `Executor` that the middleware passes straight to `self.inner`.
+macro_rules! delegate_to_inner {
+    () => {
+        fn tokens_used(
+            &self,
+            options: &$crate::options::Options,
+            prompt: &$crate::prompt::Prompt,
+        ) -> Result<$crate::tokens::TokenCount, $crate::tokens::PromptTokensError> {
+            self.inner.tokens_used(options, prompt)
+        }
+
+        fn max_tokens_allowed(&self, options: &$crate::options::Options) -> i32 {
+            self.inner.max_tokens_allowed(options)
+        }
+
+        fn answer_prefix(&self, prompt: &$crate::prompt::Prompt) -> Option<String> {
+            self.inner.answer_prefix(prompt)
+        }
+
+        fn get_tokenizer(
+            &self,
+            options: &$crate::options::Options,
+        ) -> Result<Self::StepTokenizer<'_>, $crate::tokens::TokenizerError> {
+            self.inner.get_tokenizer(options)
+        }
+
+        fn capabilities(&self) -> $crate::options::Capabilities {
+            self.inner.capabilities()
+        }
+    };
+}
+pub(crate) use delegate_to_inner;
+
+/// An extension trait that wraps any executor in middleware.
+pub trait ExecutorMiddlewareExt: Executor {
+    /// Retries transient failures according to `policy`.
+    fn with_retry(self, policy: RetryPolicy) -> Retry<Self> {
+        Retry::new(self, policy)
+    }
+
+    /// Fails calls that take longer than `timeout` with `ExecutorError::Timeout`.
+    fn with_timeout(self, timeout: Duration) -> Timeout<Self> {
+        Timeout::new(self, timeout)
+    }
+
+    /// Delays calls so they stay within `limits`.
+    fn with_rate_limit(self, limits: RateLimits) -> RateLimit<Self> {
+        RateLimit::new(self, limits)
+    }
+
+    /// Logs every request and its outcome.
+    fn with_logging(self) -> Logging<Self> {
+        Logging::new(self)
+    }
+
+    /// Caches responses in `store`. `options` are the options the executor was created with,
+    /// which are part of the cache key so differently configured executors can share a store.
+    fn with_cache<S: CacheStore>(self, store: S, options: Options) -> Cache<Self, S> {
+        Cache::new(self, store, options)
+    }
+}
+
+impl<E: Executor> ExecutorMiddlewareExt for E {}
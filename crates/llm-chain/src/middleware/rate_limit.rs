@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use super::delegate_to_inner;
+use crate::options::Options;
+use crate::output::Output;
+use crate::prompt::Prompt;
+use crate::traits::{Executor, ExecutorCreationError, ExecutorError};
+
+/// The limits enforced by `RateLimit`. A limit that is `None` is not enforced.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits {
+    /// The maximum number of calls per minute.
+    pub requests_per_minute: Option<u32>,
+    /// The maximum number of prompt tokens per minute, as counted by `Executor::tokens_used`.
+    pub tokens_per_minute: Option<u32>,
+}
+
+/// An executor that delays calls to the inner executor so they stay within the configured
+/// requests and tokens per minute.
+///
+/// Each limit is a token bucket that holds up to a minute's worth of budget and refills
+/// continuously, so short bursts are allowed. Calls that would exceed the budget wait until
+/// enough of it has been refilled. If the prompt can't be tokenized, it only counts against the
+/// request limit.
+pub struct RateLimit<E> {
+    inner: E,
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+impl<E> RateLimit<E> {
+    /// Wraps `inner`, enforcing `limits`.
+    pub fn new(inner: E, limits: RateLimits) -> Self {
+        Self {
+            inner,
+            requests: limits.requests_per_minute.map(Bucket::per_minute),
+            tokens: limits.tokens_per_minute.map(Bucket::per_minute),
+        }
+    }
+
+    /// Returns a reference to the wrapped executor.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Unwraps the middleware, returning the inner executor.
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<E> Executor for RateLimit<E>
+where
+    E: Executor + Send + Sync,
+{
+    type StepTokenizer<'a>
+        = E::StepTokenizer<'a>
+    where
+        Self: 'a;
+
+    /// Creates the inner executor with `options` and no limits.
+    fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
+        Ok(Self::new(
+            E::new_with_options(options)?,
+            RateLimits::default(),
+        ))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        if let Some(tokens) = &self.tokens {
+            let used = self
+                .inner
+                .tokens_used(options, prompt)
+                .map(|count| count.tokens_used())
+                .unwrap_or(0);
+            tokens.acquire(used.max(0) as f64).await;
+        }
+        if let Some(requests) = &self.requests {
+            requests.acquire(1.0).await;
+        }
+        self.inner.execute(options, prompt).await
+    }
+
+    delegate_to_inner!();
+}
+
+struct Bucket {
+    capacity: f64,
+    refill_per_second: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn per_minute(limit: u32) -> Self {
+        let capacity = limit.max(1) as f64;
+        Self {
+            capacity,
+            refill_per_second: capacity / 60.0,
+            state: Mutex::new(BucketState {
+                available: capacity,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until `amount` is available and takes it from the bucket. The lock is held while
+    /// waiting, so callers are served in order.
+    async fn acquire(&self, amount: f64) {
+        let amount = amount.min(self.capacity);
+        let mut state = self.state.lock().await;
+        loop {
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.updated).as_secs_f64();
+            state.available =
+                (state.available + elapsed * self.refill_per_second).min(self.capacity);
+            state.updated = now;
+            if state.available >= amount {
+                state.available -= amount;
+                return;
+            }
+            let missing = amount - state.available;
+            tokio::time::sleep(Duration::from_secs_f64(missing / self.refill_per_second)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestExecutor;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waits_when_request_budget_is_spent() {
+        let exec = RateLimit::new(
+            TestExecutor::default(),
+            RateLimits {
+                requests_per_minute: Some(2),
+                tokens_per_minute: None,
+            },
+        );
+        let start = Instant::now();
+        for _ in 0..3 {
+            exec.execute(Options::empty(), &Prompt::text("hi".to_string()))
+                .await
+                .unwrap();
+        }
+        // Two calls fit in the bucket, the third has to wait for half a minute of refill.
+        assert!(start.elapsed() >= Duration::from_secs(30));
+        assert!(start.elapsed() < Duration::from_secs(31));
+    }
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::Rng;
+
+use super::delegate_to_inner;
+use crate::options::Options;
+use crate::output::Output;
+use crate::prompt::Prompt;
+use crate::traits::{Executor, ExecutorCreationError, ExecutorError};
+
+/// Controls how often and how quickly `Retry` retries a failed call.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt.
+    pub max_retries: u32,
+    /// The delay before the first retry.
+    pub initial_backoff: Duration,
+    /// The upper bound for the delay between two attempts.
+    pub max_backoff: Duration,
+    /// The factor the delay is multiplied with after every retry.
+    pub multiplier: f64,
+    /// If true, every delay is picked at random between half and all of the computed backoff, so
+    /// concurrent callers don't retry in lockstep.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before retry number `retry`, counting from zero.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(retry as i32);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        if self.jitter && backoff > 0.0 {
+            Duration::from_secs_f64(rand::thread_rng().gen_range(backoff / 2.0..=backoff))
+        } else {
+            Duration::from_secs_f64(backoff)
+        }
+    }
+}
+
+/// An executor that retries calls to the inner executor that fail with a retryable error, see
+/// `ExecutorError::is_retryable`.
+pub struct Retry<E> {
+    inner: E,
+    policy: RetryPolicy,
+}
+
+impl<E> Retry<E> {
+    /// Wraps `inner`, retrying according to `policy`.
+    pub fn new(inner: E, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    /// Returns a reference to the wrapped executor.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Unwraps the middleware, returning the inner executor.
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<E> Executor for Retry<E>
+where
+    E: Executor + Send + Sync,
+{
+    type StepTokenizer<'a>
+        = E::StepTokenizer<'a>
+    where
+        Self: 'a;
+
+    /// Creates the inner executor with `options` and retries with the default policy.
+    fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
+        Ok(Self::new(
+            E::new_with_options(options)?,
+            RetryPolicy::default(),
+        ))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        let mut retry = 0;
+        loop {
+            match self.inner.execute(options, prompt).await {
+                Err(err) if err.is_retryable() && retry < self.policy.max_retries => {
+                    tokio::time::sleep(self.policy.backoff(retry)).await;
+                    retry += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    delegate_to_inner!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestExecutor;
+
+    fn fast_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_errors() {
+        let inner = TestExecutor::failing_with(vec![
+            ExecutorError::Timeout,
+            ExecutorError::Server("unavailable".into()),
+        ]);
+        let exec = Retry::new(inner, fast_policy(3));
+        let res = exec
+            .execute(Options::empty(), &Prompt::text("hi".to_string()))
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(exec.inner().calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_on_permanent_errors_and_after_max_retries() {
+        let exec = Retry::new(
+            TestExecutor::failing_with(vec![ExecutorError::InvalidOptions]),
+            fast_policy(3),
+        );
+        let res = exec
+            .execute(Options::empty(), &Prompt::text("hi".to_string()))
+            .await;
+        assert!(matches!(res, Err(ExecutorError::InvalidOptions)));
+        assert_eq!(exec.inner().calls(), 1);
+
+        let exec = Retry::new(
+            TestExecutor::failing_with(vec![ExecutorError::Timeout, ExecutorError::Timeout]),
+            fast_policy(1),
+        );
+        let res = exec
+            .execute(Options::empty(), &Prompt::text("hi".to_string()))
+            .await;
+        assert!(matches!(res, Err(ExecutorError::Timeout)));
+        assert_eq!(exec.inner().calls(), 2);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(500));
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(20), Duration::from_secs(30));
+    }
+}
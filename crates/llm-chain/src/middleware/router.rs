@@ -0,0 +1,214 @@
+use async_trait::async_trait;
+
+use crate::options::{Capabilities, Options};
+use crate::output::Output;
+use crate::prompt::Prompt;
+use crate::tokens::{DynTokenizer, PromptTokensError, TokenCount, TokenizerError};
+use crate::traits::{DynExecutor, Executor, ExecutorCreationError, ExecutorError};
+
+type RoutePredicate = Box<dyn Fn(&Prompt, &Options) -> bool + Send + Sync>;
+
+enum Rule {
+    /// Matches when the prompt fits in the context of the route's executor.
+    Fits,
+    /// Matches when the predicate returns true.
+    When(RoutePredicate),
+}
+
+/// An executor that picks the executor to use for every call.
+///
+/// Routes are checked in the order they were added and the first one that matches handles the
+/// call. If none matches, the default executor is used. A route either matches when the prompt
+/// fits in the context window of its executor, or when a user supplied predicate over the prompt
+/// and options holds.
+///
+/// Token counting and the answer prefix are those of the executor the prompt would be routed to.
+/// As `answer_prefix` gets no options, it routes as if the call had none: predicates get
+/// `Options::empty()`, and prompts are measured without options. `max_tokens_allowed` is the
+/// largest context of all executors, the tokenizer is the one of the default executor, and the
+/// capabilities are those every executor shares, as any of them may handle a call.
+///
+/// # Example
+///
+/// ```ignore
+/// // Use the cheap model when the prompt fits, escalate to the large context model otherwise.
+/// let exec = Router::new(gpt4_32k).route_if_fits(gpt35);
+///
+/// // Send code questions to a dedicated model.
+/// let exec = Router::new(general).route_when(
+///     |prompt, _options| prompt.to_text().contains("```"),
+///     coder,
+/// );
+/// ```
+pub struct Router {
+    routes: Vec<(Rule, Box<dyn DynExecutor>)>,
+    default: Box<dyn DynExecutor>,
+}
+
+impl Router {
+    /// Creates a router that sends every call to `default` until routes are added.
+    pub fn new<E: DynExecutor + 'static>(default: E) -> Self {
+        Self {
+            routes: Vec::new(),
+            default: Box::new(default),
+        }
+    }
+
+    /// Routes calls to `executor` if the prompt fits in its context window.
+    ///
+    /// Add routes from the smallest to the largest context, with the largest as the default, to
+    /// escalate only the prompts that need it.
+    pub fn route_if_fits<E: DynExecutor + 'static>(mut self, executor: E) -> Self {
+        self.routes.push((Rule::Fits, Box::new(executor)));
+        self
+    }
+
+    /// Routes calls to `executor` if `predicate` returns true for the prompt and options.
+    pub fn route_when<F, E>(mut self, predicate: F, executor: E) -> Self
+    where
+        F: Fn(&Prompt, &Options) -> bool + Send + Sync + 'static,
+        E: DynExecutor + 'static,
+    {
+        self.routes
+            .push((Rule::When(Box::new(predicate)), Box::new(executor)));
+        self
+    }
+
+    /// Returns the executor that handles `prompt` with `options`.
+    pub fn select(&self, options: &Options, prompt: &Prompt) -> &dyn DynExecutor {
+        self.routes
+            .iter()
+            .find(|(rule, executor)| match rule {
+                Rule::Fits => executor
+                    .tokens_used(options, prompt)
+                    .map(|count| count.has_tokens_remaining())
+                    .unwrap_or(false),
+                Rule::When(predicate) => predicate(prompt, options),
+            })
+            .map(|(_, executor)| executor.as_ref())
+            .unwrap_or(self.default.as_ref())
+    }
+}
+
+#[async_trait]
+impl Executor for Router {
+    type StepTokenizer<'a> = DynTokenizer<'a>;
+
+    /// A router can't be created from options, use `Router::new` instead.
+    fn new_with_options(_: Options) -> Result<Self, ExecutorCreationError> {
+        Err(ExecutorCreationError::FieldRequiredError(
+            "default executor".to_string(),
+        ))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        self.select(options, prompt).execute(options, prompt).await
+    }
+
+    fn tokens_used(
+        &self,
+        options: &Options,
+        prompt: &Prompt,
+    ) -> Result<TokenCount, PromptTokensError> {
+        self.select(options, prompt).tokens_used(options, prompt)
+    }
+
+    fn max_tokens_allowed(&self, options: &Options) -> i32 {
+        self.routes
+            .iter()
+            .map(|(_, executor)| executor.max_tokens_allowed(options))
+            .fold(self.default.max_tokens_allowed(options), i32::max)
+    }
+
+    /// Routes `prompt` with empty options, as the options of the call aren't known here.
+    fn answer_prefix(&self, prompt: &Prompt) -> Option<String> {
+        self.select(Options::empty(), prompt).answer_prefix(prompt)
+    }
+
+    fn get_tokenizer(&self, options: &Options) -> Result<DynTokenizer<'_>, TokenizerError> {
+        self.default.get_tokenizer(options)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.routes.iter().fold(
+            self.default.capabilities(),
+            |capabilities, (_, executor)| capabilities.intersection(&executor.capabilities()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{OptDiscriminants, ValueRange};
+    use crate::test_util::{TestExecutor, TestTokenizer};
+
+    async fn run(exec: &Router, prompt: &str) -> String {
+        let prompt = Prompt::text(prompt.to_string());
+        let res = Executor::execute(exec, Options::empty(), &prompt).await;
+        res.unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_escalates_when_prompt_does_not_fit() {
+        let exec = |reply, max_tokens| {
+            TestExecutor::replying(reply, max_tokens).with_tokenizer(TestTokenizer::Chars)
+        };
+        let exec = Router::new(exec("large", 100))
+            .route_if_fits(exec("tiny", 5))
+            .route_if_fits(exec("small", 20));
+        assert_eq!(run(&exec, "ten tokens").await, "small");
+        assert_eq!(Executor::max_tokens_allowed(&exec, Options::empty()), 100);
+    }
+
+    #[tokio::test]
+    async fn test_routes_on_predicate() {
+        let exec = Router::new(TestExecutor::replying("general", 100)).route_when(
+            |prompt, _| prompt.to_text().contains("code"),
+            TestExecutor::replying("coder", 100),
+        );
+        assert_eq!(run(&exec, "write code").await, "coder");
+        assert_eq!(run(&exec, "write a poem").await, "general");
+    }
+
+    #[test]
+    fn test_answer_prefix_routes_without_options() {
+        let exec = Router::new(TestExecutor::default().with_answer_prefix("general:")).route_when(
+            |_, options| options.get(OptDiscriminants::Model).is_some(),
+            TestExecutor::default().with_answer_prefix("model:"),
+        );
+        let prompt = Prompt::text("hi".to_string());
+        assert_eq!(
+            Executor::answer_prefix(&exec, &prompt),
+            Some("general:".to_string())
+        );
+    }
+
+    #[test]
+    fn test_capabilities_are_shared_by_every_route() {
+        let exec = Router::new(
+            TestExecutor::default().with_capabilities(
+                Capabilities::new()
+                    .supports(OptDiscriminants::Model)
+                    .supports_between(OptDiscriminants::Temperature, 0.0, 2.0)
+                    .supports(OptDiscriminants::TopK),
+            ),
+        )
+        .route_when(
+            |_, _| false,
+            TestExecutor::default().with_capabilities(
+                Capabilities::new()
+                    .supports(OptDiscriminants::Model)
+                    .supports_between(OptDiscriminants::Temperature, 0.0, 1.0),
+            ),
+        )
+        .route_when(|_, _| false, TestExecutor::default());
+        let capabilities = Executor::capabilities(&exec);
+        assert!(capabilities.is_supported(OptDiscriminants::Model));
+        assert!(!capabilities.is_supported(OptDiscriminants::TopK));
+        assert_eq!(
+            capabilities.range(OptDiscriminants::Temperature),
+            Some(&ValueRange::Between { min: 0.0, max: 1.0 })
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::delegate_to_inner;
+use crate::options::Options;
+use crate::output::Output;
+use crate::prompt::Prompt;
+use crate::traits::{Executor, ExecutorCreationError, ExecutorError};
+
+/// An executor that fails calls to the inner executor with `ExecutorError::Timeout` if they take
+/// longer than the configured duration.
+///
+/// For streaming outputs the timeout covers the time until the stream is returned, not the time
+/// it takes to read it.
+pub struct Timeout<E> {
+    inner: E,
+    timeout: Duration,
+}
+
+impl<E> Timeout<E> {
+    /// Wraps `inner`, bounding every call to `timeout`.
+    pub fn new(inner: E, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+
+    /// Returns a reference to the wrapped executor.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Unwraps the middleware, returning the inner executor.
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<E> Executor for Timeout<E>
+where
+    E: Executor + Send + Sync,
+{
+    type StepTokenizer<'a>
+        = E::StepTokenizer<'a>
+    where
+        Self: 'a;
+
+    /// Creates the inner executor with `options` and a timeout of 60 seconds.
+    fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
+        Ok(Self::new(
+            E::new_with_options(options)?,
+            Duration::from_secs(60),
+        ))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        tokio::time::timeout(self.timeout, self.inner.execute(options, prompt))
+            .await
+            .map_err(|_| ExecutorError::Timeout)?
+    }
+
+    delegate_to_inner!();
+}
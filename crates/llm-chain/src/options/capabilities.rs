@@ -0,0 +1,295 @@
+use std::fmt;
+
+use super::{Opt, OptDiscriminants, OptionsCascade};
+
+/// The values an executor accepts for an option.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueRange {
+    /// Any value of the option's type.
+    Any,
+    /// A number between `min` and `max`, inclusive.
+    Between { min: f64, max: f64 },
+    /// A list of at most this many items.
+    AtMostItems(usize),
+}
+
+impl ValueRange {
+    /// Returns a description of why `opt` is outside of the range, or `None` if it is inside.
+    fn check(&self, opt: &Opt) -> Option<String> {
+        match self {
+            ValueRange::Any => None,
+            ValueRange::Between { min, max } => {
+                let value = numeric_value(opt)?;
+                (value < *min || value > *max)
+                    .then(|| format!("{} is outside of the range {} to {}", value, min, max))
+            }
+            ValueRange::AtMostItems(max) => {
+                let count = item_count(opt)?;
+                (count > *max).then(|| format!("{} items are set, at most {} are used", count, max))
+            }
+        }
+    }
+
+    /// Returns the values accepted by both ranges.
+    fn intersect(&self, other: &ValueRange) -> ValueRange {
+        match (self, other) {
+            (ValueRange::Any, range) | (range, ValueRange::Any) => range.clone(),
+            (
+                ValueRange::Between { min, max },
+                ValueRange::Between {
+                    min: other_min,
+                    max: other_max,
+                },
+            ) => ValueRange::Between {
+                min: min.max(*other_min),
+                max: max.min(*other_max),
+            },
+            (ValueRange::AtMostItems(max), ValueRange::AtMostItems(other_max)) => {
+                ValueRange::AtMostItems(*max.min(other_max))
+            }
+            // An option is either numeric or a list, so the kinds never differ in practice.
+            (range, _) => range.clone(),
+        }
+    }
+}
+
+/// Returns the value of a numeric option.
+fn numeric_value(opt: &Opt) -> Option<f64> {
+    let value = match opt {
+        Opt::NThreads(v)
+        | Opt::MaxTokens(v)
+        | Opt::MaxContextSize(v)
+        | Opt::RepeatPenaltyLastN(v)
+        | Opt::NBatch(v)
+        | Opt::NChoices(v)
+        | Opt::TopLogprobs(v) => *v as f64,
+        Opt::TopK(v) | Opt::Mirostat(v) => *v as f64,
+        Opt::FrequencyPenalty(v)
+        | Opt::PresencePenalty(v)
+        | Opt::TopP(v)
+        | Opt::Temperature(v)
+        | Opt::RepeatPenalty(v)
+        | Opt::TfsZ(v)
+        | Opt::TypicalP(v)
+        | Opt::MirostatTau(v)
+        | Opt::MirostatEta(v) => *v as f64,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Returns the number of items of a list option.
+fn item_count(opt: &Opt) -> Option<usize> {
+    match opt {
+        Opt::StopSequence(stops) => Some(stops.len()),
+        Opt::TokenBias(bias) => Some(bias.iter().count()),
+        Opt::TextBias(bias) => Some(bias.iter().count()),
+        Opt::Tools(tools) => Some(tools.len()),
+        _ => None,
+    }
+}
+
+/// The options an executor understands, and the values it accepts for them.
+///
+/// Executors describe themselves with `Executor::capabilities`, which lets `validate` find the
+/// options that would be ignored or rejected before anything is sent to the model.
+///
+/// ```rust
+/// # use llm_chain::options::*;
+/// let capabilities = Capabilities::new()
+///     .supports(OptDiscriminants::Model)
+///     .supports_between(OptDiscriminants::Temperature, 0.0, 2.0);
+/// let mut builder = Options::builder();
+/// builder.add_option(Opt::Temperature(3.0));
+/// builder.add_option(Opt::TopK(40));
+/// let call = builder.build();
+/// let issues = capabilities.validate(&OptionsCascade::new().with_options(&call));
+/// assert_eq!(issues.len(), 2);
+/// assert!(issues.iter().any(|issue| issue.is_error()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// The supported options and their ranges, or `None` if every option is accepted.
+    supported: Option<Vec<(OptDiscriminants, ValueRange)>>,
+}
+
+impl Capabilities {
+    /// Returns capabilities supporting no options, to be extended with `supports`.
+    pub fn new() -> Self {
+        Self {
+            supported: Some(Vec::new()),
+        }
+    }
+
+    /// Returns capabilities accepting every option with any value. This is what executors that
+    /// don't describe themselves report.
+    pub fn any() -> Self {
+        Self { supported: None }
+    }
+
+    fn with(mut self, option: OptDiscriminants, range: ValueRange) -> Self {
+        let supported = self.supported.get_or_insert_with(Vec::new);
+        supported.retain(|(o, _)| *o != option);
+        supported.push((option, range));
+        self
+    }
+
+    /// Adds an option supported with any value.
+    pub fn supports(self, option: OptDiscriminants) -> Self {
+        self.with(option, ValueRange::Any)
+    }
+
+    /// Adds a numeric option supported with values between `min` and `max`, inclusive.
+    pub fn supports_between(self, option: OptDiscriminants, min: f64, max: f64) -> Self {
+        self.with(option, ValueRange::Between { min, max })
+    }
+
+    /// Adds a list option supported with at most `max` items.
+    pub fn supports_at_most(self, option: OptDiscriminants, max: usize) -> Self {
+        self.with(option, ValueRange::AtMostItems(max))
+    }
+
+    /// Returns true if `option` is used by the executor.
+    pub fn is_supported(&self, option: OptDiscriminants) -> bool {
+        self.range(option).is_some()
+    }
+
+    /// Returns the values accepted for `option`, or `None` if it isn't supported.
+    pub fn range(&self, option: OptDiscriminants) -> Option<&ValueRange> {
+        static ANY: ValueRange = ValueRange::Any;
+        match &self.supported {
+            None => Some(&ANY),
+            Some(supported) => supported
+                .iter()
+                .find(|(o, _)| *o == option)
+                .map(|(_, range)| range),
+        }
+    }
+
+    /// Returns the capabilities of an executor that may send a call to either `self` or `other`:
+    /// the options both support, with the values both accept.
+    pub fn intersection(&self, other: &Capabilities) -> Capabilities {
+        let (Some(supported), Some(other_supported)) = (&self.supported, &other.supported) else {
+            return if self.supported.is_none() {
+                other.clone()
+            } else {
+                self.clone()
+            };
+        };
+        Capabilities {
+            supported: Some(
+                supported
+                    .iter()
+                    .filter_map(|(option, range)| {
+                        other_supported
+                            .iter()
+                            .find(|(o, _)| o == option)
+                            .map(|(_, other_range)| (*option, range.intersect(other_range)))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Checks the effective options of `options`. Options the executor doesn't support give a
+    /// warning, as they are ignored, and values outside of the supported range give an error.
+    pub fn validate(&self, options: &OptionsCascade) -> Vec<OptionIssue> {
+        options
+            .effective_options()
+            .into_iter()
+            .filter_map(|opt| {
+                let option = OptDiscriminants::from(opt);
+                match self.range(option) {
+                    None => Some(OptionIssue {
+                        option,
+                        severity: Severity::Warning,
+                        message: "the option isn't supported and is ignored".to_string(),
+                    }),
+                    Some(range) => range.check(opt).map(|message| OptionIssue {
+                        option,
+                        severity: Severity::Error,
+                        message,
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for Capabilities {
+    /// Returns capabilities supporting no options.
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How serious an `OptionIssue` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The option has no effect.
+    Warning,
+    /// The executor will reject the option, or can't honor it.
+    Error,
+}
+
+/// A problem with an option, found by `Capabilities::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionIssue {
+    pub option: OptDiscriminants,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl OptionIssue {
+    /// Returns true if the issue is an error rather than a warning.
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for OptionIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {:?}: {}", severity, self.option, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    #[test]
+    fn test_validate_reports_ignored_and_out_of_range_options() {
+        let capabilities = Capabilities::new()
+            .supports(OptDiscriminants::Model)
+            .supports_between(OptDiscriminants::Temperature, 0.0, 2.0)
+            .supports_at_most(OptDiscriminants::StopSequence, 1);
+        let mut defaults = Options::builder();
+        defaults.add_option(Opt::Temperature(5.0));
+        defaults.add_option(Opt::StopSequence(vec!["\n".to_string()]));
+        let defaults = defaults.build();
+        let mut call = Options::builder();
+        call.add_option(Opt::Temperature(0.5));
+        call.add_option(Opt::TopK(40));
+        let call = call.build();
+
+        let cascade = OptionsCascade::new().with_options(&defaults);
+        let issues = capabilities.validate(&cascade);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].is_error());
+        assert_eq!(issues[0].option, OptDiscriminants::Temperature);
+
+        // The temperature is overridden with a valid value.
+        let issues = capabilities.validate(&cascade.with_options(&call));
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].option, OptDiscriminants::TopK);
+        assert_eq!(issues[0].severity, Severity::Warning);
+
+        let cascade = OptionsCascade::new().with_options(&call);
+        assert!(Capabilities::any().validate(&cascade).is_empty());
+    }
+}
@@ -0,0 +1,482 @@
+//! Loading options from configuration files.
+//!
+//! Options can be kept in a YAML or TOML file. Every key is an option, named like its `Opt`
+//! variant in snake case, and the value has the type the option holds. Named profiles, each a set
+//! of options overriding the ones at the top level, go under `profiles`:
+//!
+//! ```yaml
+//! model: gpt-3.5-turbo
+//! api_key: ${OPENAI_API_KEY}
+//! stop_sequence: ["\n\n"]
+//! profiles:
+//!   fast:
+//!     max_tokens: 256
+//!   accurate:
+//!     model: gpt-4
+//!     temperature: 0.0
+//! ```
+//!
+//! `${NAME}` is replaced with the value of the environment variable `NAME`, and `${NAME:-value}`
+//! falls back to `value` when the variable isn't set. `$${` stands for a literal `${`. Variables
+//! are replaced before the file is parsed, so they can hold numbers and lists as well as strings,
+//! but values containing YAML or TOML syntax need to be quoted.
+//!
+//! Unknown keys and values of the wrong type are reported with their line and column.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, VariantAccess, Visitor,
+};
+use serde::Deserialize;
+use strum::VariantNames;
+use thiserror::Error;
+
+use super::{Opt, OptDiscriminants, Options, OptionsBuilder};
+
+/// The key holding the profiles in a configuration file.
+const PROFILES_KEY: &str = "profiles";
+
+/// An error loading options from a configuration file.
+#[derive(Debug, Error)]
+pub enum OptionsConfigError {
+    #[error("unable to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("the configuration file {0} isn't YAML or TOML")]
+    UnsupportedFormat(PathBuf),
+    #[error("the environment variable `{name}` used on line {line} isn't set")]
+    MissingVariable { name: String, line: usize },
+    #[error("invalid YAML configuration: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("invalid TOML configuration: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("there is no profile named `{0}`")]
+    UnknownProfile(String),
+}
+
+/// Options read from a configuration file: the options at the top level, and named profiles
+/// overriding them.
+///
+/// A profile is usually layered into a cascade as the model configuration:
+///
+/// ```rust
+/// # use llm_chain::options::*;
+/// let config = OptionsConfig::from_yaml_str(
+///     "temperature: 0.7\nprofiles:\n  accurate:\n    temperature: 0.0\n",
+/// )
+/// .unwrap();
+/// let model_defaults = Options::builder().build();
+/// let env_defaults = options_from_env().unwrap();
+/// let accurate = config.profile("accurate").unwrap();
+/// let cascade = OptionsCascade::new_typical(&model_defaults, &env_defaults, &accurate, None);
+/// assert!(matches!(
+///     cascade.get(OptDiscriminants::Temperature),
+///     Some(Opt::Temperature(t)) if *t == 0.0
+/// ));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OptionsConfig {
+    defaults: Options,
+    profiles: BTreeMap<String, Options>,
+}
+
+impl OptionsConfig {
+    /// Reads a configuration file, in YAML if its extension is `.yaml` or `.yml` and in TOML if
+    /// it is `.toml`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, OptionsConfigError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let parse = match extension {
+            "yaml" | "yml" => Self::from_yaml_str,
+            "toml" => Self::from_toml_str,
+            _ => return Err(OptionsConfigError::UnsupportedFormat(path.to_path_buf())),
+        };
+        let text = std::fs::read_to_string(path).map_err(|source| OptionsConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        parse(&text)
+    }
+
+    /// Parses a configuration in YAML.
+    pub fn from_yaml_str(text: &str) -> Result<Self, OptionsConfigError> {
+        let text = interpolate_env(text)?;
+        if text.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        Ok(serde_yaml::from_str(&text)?)
+    }
+
+    /// Parses a configuration in TOML.
+    pub fn from_toml_str(text: &str) -> Result<Self, OptionsConfigError> {
+        Ok(toml::from_str(&interpolate_env(text)?)?)
+    }
+
+    /// Returns the options set at the top level of the file.
+    pub fn defaults(&self) -> &Options {
+        &self.defaults
+    }
+
+    /// Returns the names of the profiles, in alphabetical order.
+    pub fn profile_names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    /// Returns the options of the profile `name`: the options at the top level, overridden by the
+    /// ones set in the profile.
+    pub fn profile(&self, name: &str) -> Result<Options, OptionsConfigError> {
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| OptionsConfigError::UnknownProfile(name.to_string()))?;
+        let mut builder = OptionsBuilder::new();
+        for opt in profile.opts.iter().chain(&self.defaults.opts) {
+            if builder
+                .opts
+                .iter()
+                .all(|o| OptDiscriminants::from(o) != OptDiscriminants::from(opt))
+            {
+                builder.add_option(opt.clone());
+            }
+        }
+        Ok(builder.build())
+    }
+}
+
+/// Loads the options of `profile`, or the ones at the top level if it is `None`, from a
+/// configuration file. See `OptionsConfig`.
+pub fn options_from_file<P: AsRef<Path>>(
+    path: P,
+    profile: Option<&str>,
+) -> Result<Options, OptionsConfigError> {
+    let config = OptionsConfig::load(path)?;
+    match profile {
+        Some(name) => config.profile(name),
+        None => Ok(config.defaults),
+    }
+}
+
+/// Replaces `${NAME}` and `${NAME:-default}` with the values of environment variables.
+fn interpolate_env(text: &str) -> Result<String, OptionsConfigError> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(escaped) = after.strip_prefix("$${") {
+            out.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let end = match after.strip_prefix("${").and_then(|r| r.find('}')) {
+            Some(end) => end + 2,
+            None => {
+                out.push('$');
+                rest = &after[1..];
+                continue;
+            }
+        };
+        let (name, default) = match after[2..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[2..end], None),
+        };
+        match (std::env::var(name), default) {
+            (Ok(value), _) => out.push_str(&value),
+            (Err(_), Some(default)) => out.push_str(default),
+            (Err(_), None) => {
+                return Err(OptionsConfigError::MissingVariable {
+                    name: name.to_string(),
+                    line: text[..text.len() - after.len()].matches('\n').count() + 1,
+                })
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+impl<'de> Deserialize<'de> for OptionsConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(OptionsVisitor { top_level: true })
+    }
+}
+
+/// Reads a table of options, and at the top level the profiles.
+struct OptionsVisitor {
+    top_level: bool,
+}
+
+impl<'de> Visitor<'de> for OptionsVisitor {
+    type Value = OptionsConfig;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of options")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut config = OptionsConfig::default();
+        while let Some(key) = map.next_key_seed(KeySeed {
+            top_level: self.top_level,
+        })? {
+            match key {
+                Key::Profiles => {
+                    config.profiles = map.next_value_seed(ProfilesSeed)?;
+                }
+                Key::Opt(discriminant) => {
+                    let opt = map.next_value_seed(OptSeed(discriminant))?;
+                    config.defaults.opts.push(opt);
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+enum Key {
+    Profiles,
+    Opt(OptDiscriminants),
+}
+
+/// Reads a key, failing on keys that aren't options.
+struct KeySeed {
+    top_level: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for KeySeed {
+    type Value = Key;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Key, D::Error> {
+        // Failing in the visitor lets the format report where the key is.
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeySeed {
+    type Value = Key;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("the name of an option")
+    }
+
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<Key, E> {
+        if self.top_level && key == PROFILES_KEY {
+            return Ok(Key::Profiles);
+        }
+        key.parse()
+            .map(Key::Opt)
+            .map_err(|_| E::unknown_field(key, OptDiscriminants::VARIANTS))
+    }
+}
+
+/// Reads the map of profiles.
+struct ProfilesSeed;
+
+impl<'de> DeserializeSeed<'de> for ProfilesSeed {
+    type Value = BTreeMap<String, Options>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ProfilesSeed {
+    type Value = BTreeMap<String, Options>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of profiles")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut profiles = BTreeMap::new();
+        while let Some(name) = map.next_key::<String>()? {
+            let profile = map.next_value_seed(ProfileSeed)?;
+            profiles.insert(name, profile);
+        }
+        Ok(profiles)
+    }
+}
+
+/// Reads the options of a profile.
+struct ProfileSeed;
+
+impl<'de> DeserializeSeed<'de> for ProfileSeed {
+    type Value = Options;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Options, D::Error> {
+        let config = deserializer.deserialize_map(OptionsVisitor { top_level: false })?;
+        Ok(config.defaults)
+    }
+}
+
+/// Reads the value of an option.
+///
+/// The value is handed to the `Deserialize` implementation of `Opt` as the content of the
+/// variant, so errors in it are reported by the format, with their location.
+struct OptSeed(OptDiscriminants);
+
+impl<'de> DeserializeSeed<'de> for OptSeed {
+    type Value = Opt;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Opt, D::Error> {
+        let variant = format!("{:?}", self.0);
+        Opt::deserialize(VariantDeserializer {
+            variant: &variant,
+            value: deserializer,
+        })
+    }
+}
+
+/// A deserializer for the enum variant `variant`, holding `value`.
+struct VariantDeserializer<'a, D> {
+    variant: &'a str,
+    value: D,
+}
+
+impl<'de, 'a, D: Deserializer<'de>> Deserializer<'de> for VariantDeserializer<'a, D> {
+    type Error = D::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        visitor.visit_enum(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de, 'a, D: Deserializer<'de>> EnumAccess<'de> for VariantDeserializer<'a, D> {
+    type Error = D::Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), D::Error> {
+        let variant = seed.deserialize(de::value::StrDeserializer::new(self.variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a, D: Deserializer<'de>> VariantAccess<'de> for VariantDeserializer<'a, D> {
+    type Error = D::Error;
+
+    fn unit_variant(self) -> Result<(), D::Error> {
+        Err(de::Error::custom("options always hold a value"))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, D::Error> {
+        seed.deserialize(self.value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _: usize, _: V) -> Result<V::Value, D::Error> {
+        Err(de::Error::custom("options always hold a single value"))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, D::Error> {
+        Err(de::Error::custom("options always hold a single value"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temperature(options: &Options) -> Option<f32> {
+        match options.get(OptDiscriminants::Temperature) {
+            Some(Opt::Temperature(t)) => Some(*t),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_profiles_override_defaults() {
+        std::env::set_var("LLM_CHAIN_TEST_CONFIG_KEY", "sk-123");
+        let yaml = r#"
+model: gpt-3.5-turbo
+api_key: ${LLM_CHAIN_TEST_CONFIG_KEY}
+temperature: ${LLM_CHAIN_TEST_CONFIG_UNSET:-0.7}
+stop_sequence: ["\n\n", "END"]
+stream: true
+profiles:
+  accurate:
+    model: gpt-4
+    temperature: 0.0
+"#;
+        let toml = r#"
+model = "gpt-3.5-turbo"
+api_key = "${LLM_CHAIN_TEST_CONFIG_KEY}"
+temperature = ${LLM_CHAIN_TEST_CONFIG_UNSET:-0.7}
+stop_sequence = ["\n\n", "END"]
+stream = true
+
+[profiles.accurate]
+model = "gpt-4"
+temperature = 0.0
+"#;
+        for config in [
+            OptionsConfig::from_yaml_str(yaml).unwrap(),
+            OptionsConfig::from_toml_str(toml).unwrap(),
+        ] {
+            assert_eq!(temperature(config.defaults()), Some(0.7));
+            assert!(matches!(
+                config.defaults().get(OptDiscriminants::ApiKey),
+                Some(Opt::ApiKey(key)) if key == "sk-123"
+            ));
+            assert!(matches!(
+                config.defaults().get(OptDiscriminants::StopSequence),
+                Some(Opt::StopSequence(stops)) if stops.len() == 2
+            ));
+            let accurate = config.profile("accurate").unwrap();
+            assert_eq!(temperature(&accurate), Some(0.0));
+            assert!(matches!(
+                accurate.get(OptDiscriminants::Model),
+                Some(Opt::Model(model)) if model.to_name() == "gpt-4"
+            ));
+            assert!(matches!(
+                accurate.get(OptDiscriminants::Stream),
+                Some(Opt::Stream(true))
+            ));
+            assert!(matches!(
+                config.profile("fast"),
+                Err(OptionsConfigError::UnknownProfile(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_errors_have_locations() {
+        let err = OptionsConfig::from_yaml_str("model: gpt-4\ntemprature: 0.5\n").unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains("unknown field `temprature`"),
+            "{}",
+            message
+        );
+        assert!(message.contains("line 2"), "{}", message);
+
+        let err =
+            OptionsConfig::from_toml_str("model = \"gpt-4\"\nmax_tokens = \"many\"\n").unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("line 2"), "{}", message);
+
+        let err = OptionsConfig::from_yaml_str("profiles:\n  fast:\n    top_k: 1.5\n").unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+
+        assert!(matches!(
+            OptionsConfig::from_yaml_str("model: gpt-4\napi_key: ${LLM_CHAIN_TEST_CONFIG_MISSING}"),
+            Err(OptionsConfigError::MissingVariable { line: 2, .. })
+        ));
+    }
+}
@@ -3,9 +3,16 @@ use paste::paste;
 use std::{collections::HashMap, env::VarError, ffi::OsStr};
 
 use serde::{Deserialize, Serialize};
-use strum_macros::EnumDiscriminants;
+use strum_macros::{EnumDiscriminants, EnumString, EnumVariantNames};
 
-use crate::tokens::Token;
+use crate::prompt::ChatFormat;
+use crate::tokens::{Token, Tokenizer, TokenizerError};
+use crate::tools::ToolDescription;
+
+mod capabilities;
+mod config;
+pub use capabilities::{Capabilities, OptionIssue, Severity, ValueRange};
+pub use config::{options_from_file, OptionsConfig, OptionsConfigError};
 
 /// A collection of options that can be used to configure a model.
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -224,7 +231,7 @@ impl OptionsBuilder {
 /// A cascade of option sets.
 ///
 /// Options added later in the cascade override earlier options.
-#[derive(Debug,Clone)]
+#[derive(Debug, Clone)]
 pub struct OptionsCascade<'a> {
     /// The sets of options, in the order they were added.
     cascades: Vec<&'a Options>,
@@ -275,6 +282,23 @@ impl<'a> OptionsCascade<'a> {
         None
     }
 
+    /// Returns the effective value of every option set anywhere in this cascade, i.e. the value
+    /// `get` would return for it. Each kind of option appears once.
+    pub fn effective_options(&self) -> Vec<&Opt> {
+        let mut seen = Vec::new();
+        let mut effective = Vec::new();
+        for options in self.cascades.iter().rev() {
+            for opt in &options.opts {
+                let discriminant = OptDiscriminants::from(opt);
+                if !seen.contains(&discriminant) {
+                    seen.push(discriminant);
+                    effective.push(opt);
+                }
+            }
+        }
+        effective
+    }
+
     /// Returns a boolean indicating if options indicate that requests should be streamed or not.
     pub fn is_streaming(&self) -> bool {
         let Some(Opt::Stream(val)) = self.get(OptDiscriminants::Stream) else {
@@ -282,6 +306,44 @@ impl<'a> OptionsCascade<'a> {
         };
         *val
     }
+
+    /// Returns the number of top alternatives to report with each token's log-probability, or
+    /// `None` if log-probabilities were not requested.
+    pub fn requested_logprobs(&self) -> Option<usize> {
+        match (
+            self.get(OptDiscriminants::Logprobs),
+            self.get(OptDiscriminants::TopLogprobs),
+        ) {
+            (Some(Opt::Logprobs(false)), _) => None,
+            (_, Some(Opt::TopLogprobs(n))) => Some(*n),
+            (Some(Opt::Logprobs(true)), _) => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Returns the token bias to apply: the `TokenBias` option followed by the `TextBias` option,
+    /// tokenized with `tokenizer`. Returns `None` if neither option sets a bias.
+    pub fn token_bias<T: Tokenizer + ?Sized>(
+        &self,
+        tokenizer: &T,
+    ) -> Result<Option<TokenBias>, TokenizerError> {
+        let mut bias = match self.get(OptDiscriminants::TokenBias) {
+            Some(Opt::TokenBias(bias)) => bias.0.clone(),
+            _ => Vec::new(),
+        };
+        if let Some(Opt::TextBias(text_bias)) = self.get(OptDiscriminants::TextBias) {
+            bias.extend(text_bias.resolve(tokenizer)?.0);
+        }
+        Ok((!bias.is_empty()).then(|| TokenBias::new(bias)))
+    }
+
+    /// Returns the chat format to render chat prompts with, `ChatFormat::Plain` if none is set.
+    pub fn chat_format(&self) -> ChatFormat {
+        match self.get(OptDiscriminants::ChatFormat) {
+            Some(Opt::ChatFormat(format)) => format.clone(),
+            _ => ChatFormat::default(),
+        }
+    }
 }
 
 impl<'a> Default for OptionsCascade<'a> {
@@ -318,6 +380,16 @@ impl ModelRef {
 pub struct TokenBias(Vec<(Token, f32)>); // TODO: Serialize to a JSON object of str(F32) =>
 
 impl TokenBias {
+    /// Creates a new token bias from a list of tokens and the bias to apply to each of them.
+    pub fn new(bias: Vec<(Token, f32)>) -> Self {
+        Self(bias)
+    }
+
+    /// Returns an iterator over the tokens and their bias.
+    pub fn iter(&self) -> impl Iterator<Item = &(Token, f32)> {
+        self.0.iter()
+    }
+
     /// Returns the token bias as a hashmap where the keys are i32 and the value f32. If the type doesn't match returns None
     pub fn as_i32_f32_hashmap(&self) -> Option<HashMap<i32, f32>> {
         let mut map = HashMap::new();
@@ -328,10 +400,48 @@ impl TokenBias {
     }
 }
 
+/// A list of texts to bias during the process of inferencing.
+///
+/// Unlike `TokenBias`, the bias is written in text, which the executor turns into the tokens of its
+/// model with its tokenizer. Every token of a text gets the bias. Tokenizers using byte-pair
+/// encoding, like OpenAI's, have different tokens for a word at the start of the text and in the
+/// middle of a sentence, so add a leading space (`" word"`) to bias the word as it usually appears.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TextBias(Vec<(String, f32)>);
+
+impl TextBias {
+    /// Creates a new text bias from a list of texts and the bias to apply to each of them.
+    pub fn new(bias: Vec<(String, f32)>) -> Self {
+        Self(bias)
+    }
+
+    /// Returns an iterator over the texts and their bias.
+    pub fn iter(&self) -> impl Iterator<Item = &(String, f32)> {
+        self.0.iter()
+    }
+
+    /// Tokenizes the texts with `tokenizer`, giving each of their tokens the bias of the text.
+    pub fn resolve<T: Tokenizer + ?Sized>(
+        &self,
+        tokenizer: &T,
+    ) -> Result<TokenBias, TokenizerError> {
+        let mut bias = Vec::new();
+        for (text, value) in &self.0 {
+            let tokens = tokenizer.tokenize_str(text)?.into_tokens();
+            bias.extend(tokens.into_iter().map(|token| (token, *value)));
+        }
+        Ok(TokenBias::new(bias))
+    }
+}
+
 #[derive(EnumDiscriminants, Clone, Debug, Serialize, Deserialize)]
+#[strum_discriminants(
+    derive(EnumString, EnumVariantNames),
+    strum(serialize_all = "snake_case")
+)]
 pub enum Opt {
     /// The name or path of the model used.
-    Model(ModelRef),        
+    Model(ModelRef),
     /// The API key for the model service.
     ApiKey(String),
     /// The number of threads to use for parallel processing.
@@ -359,6 +469,9 @@ pub enum Opt {
     /// A bias to apply to certain tokens during the inference process.
     /// This is known as logit bias in OpenAI and is also used in llm-chain-local.
     TokenBias(TokenBias),
+    /// A bias to apply to the tokens of certain texts, resolved with the executor's tokenizer.
+    /// This is used wherever `TokenBias` is, see `TextBias`.
+    TextBias(TextBias),
 
     /// The maximum number of tokens to consider for each step of generation.
     /// This is common to all models, but is not used by OpenAI.
@@ -400,6 +513,24 @@ pub enum Opt {
     User(String),
     /// The type of the model.
     ModelType(String),
+    /// The tools the model may invoke through native tool calling.
+    /// This is used by llm-chain-openai and llm-chain-azure.
+    Tools(Vec<ToolDescription>),
+    /// The number of candidate completions to generate for each prompt.
+    /// This is used by llm-chain-openai and llm-chain-azure.
+    NChoices(usize),
+    /// Whether or not to return the log-probability of every generated token.
+    /// This is used by llm-chain-openai, llm-chain-azure and llm-chain-llama.
+    Logprobs(bool),
+    /// The number of most likely alternatives to return with the log-probability of each token.
+    /// Setting it implies `Logprobs(true)`. OpenAI allows up to twenty.
+    TopLogprobs(usize),
+    /// A grammar, in GBNF, that the generated text must match.
+    /// This is used by llm-chain-llama, see its `grammar` module for the supported syntax.
+    Grammar(String),
+    /// The way chat prompts are written for models that take a single string.
+    /// This is used by llm-chain-llama, llm-chain-local and llm-chain-sagemaker.
+    ChatFormat(ChatFormat),
 }
 
 // Helper function to extract environment variables
@@ -456,6 +587,9 @@ opt_parse_str!(RepeatPenaltyLastN);
 opt_parse_str!(TfsZ);
 opt_parse_str!(PenalizeNl);
 opt_parse_str!(NBatch);
+opt_parse_str!(NChoices);
+opt_parse_str!(Logprobs);
+opt_parse_str!(TopLogprobs);
 
 macro_rules! opt_from_env {
     ($opt:ident, $v:ident) => {
@@ -479,6 +613,9 @@ macro_rules! opts_from_env {
 /// Every option that can be easily understood from a string is avaliable the name
 /// of the option will be in upper snake case, that means that the option `Opt::ApiKey` has the environment variable
 /// `LLM_CHAIN_API_KEY`
+///
+/// Options that can't be set this way, such as `Opt::StopSequence`, can be loaded from a
+/// configuration file with `options_from_file`.
 pub fn options_from_env() -> Result<Options, VarError> {
     let mut opts = OptionsBuilder::new();
     opts_from_env!(
@@ -497,7 +634,10 @@ pub fn options_from_env() -> Result<Options, VarError> {
         RepeatPenaltyLastN,
         TfsZ,
         PenalizeNl,
-        NBatch
+        NBatch,
+        NChoices,
+        Logprobs,
+        TopLogprobs
     );
     Ok(opts.build())
 }
@@ -505,6 +645,7 @@ pub fn options_from_env() -> Result<Options, VarError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::TestTokenizer;
     // Tests for FromStr
     #[test]
     fn test_options_from_env() {
@@ -541,4 +682,22 @@ mod tests {
         assert_eq!(nbatch.clone(), orig_nbatch);
         assert_eq!(api_key, orig_api_key);
     }
+
+    #[test]
+    fn test_token_bias_resolves_text_bias() {
+        let mut builder = Options::builder();
+        builder.add_option(Opt::TokenBias(TokenBias::new(vec![(1.into(), -1.0)])));
+        builder.add_option(Opt::TextBias(TextBias::new(vec![("ab".to_string(), 5.0)])));
+        let options = builder.build();
+        let cascade = OptionsCascade::new().with_options(&options);
+        let bias = cascade.token_bias(&TestTokenizer::Chars).unwrap().unwrap();
+        let bias: Vec<_> = bias
+            .iter()
+            .map(|(token, value)| (token.to_i32().unwrap(), *value))
+            .collect();
+        assert_eq!(bias, vec![(1, -1.0), (97, 5.0), (98, 5.0)]);
+
+        let cascade = OptionsCascade::new();
+        assert!(cascade.token_bias(&TestTokenizer::Chars).unwrap().is_none());
+    }
 }
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+use super::FinishReason;
+use crate::prompt::Data;
+
+/// The log-probability of a generated token.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    /// The generated token.
+    pub token: String,
+    /// The natural logarithm of the probability of the token.
+    pub logprob: f32,
+    /// The most likely tokens at this position and their log-probabilities, most likely first.
+    /// Only filled in when top log-probabilities were requested.
+    #[serde(default)]
+    pub top_logprobs: Vec<(String, f32)>,
+}
+
+/// One of the completions the model produced for a prompt.
+///
+/// Models produce a single candidate unless more are requested with `Opt::NChoices`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    /// The content of the completion.
+    pub content: Data<String>,
+    /// Why the model stopped generating this candidate.
+    pub finish_reason: Option<FinishReason>,
+    /// The log-probability of every generated token, if requested with `Opt::Logprobs`.
+    pub logprobs: Option<Vec<TokenLogprob>>,
+}
+
+impl Candidate {
+    /// Creates a candidate without a finish reason or log-probabilities.
+    pub fn new(content: Data<String>) -> Self {
+        Self {
+            content,
+            finish_reason: None,
+            logprobs: None,
+        }
+    }
+
+    /// Returns the sum of the log-probabilities of the generated tokens, if they are known.
+    ///
+    /// This is the log-probability of the whole completion, useful to rank candidates.
+    pub fn total_logprob(&self) -> Option<f32> {
+        self.logprobs
+            .as_ref()
+            .map(|logprobs| logprobs.iter().map(|t| t.logprob).sum())
+    }
+}
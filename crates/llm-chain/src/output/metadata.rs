@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+/// The number of tokens a call consumed, as reported by the model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    /// The number of tokens in the prompt.
+    pub prompt_tokens: u32,
+    /// The number of tokens the model generated.
+    pub completion_tokens: u32,
+    /// The total number of tokens, usually the sum of the two above.
+    pub total_tokens: u32,
+}
+
+impl TokenUsage {
+    /// Creates a usage report, computing the total from the prompt and completion tokens.
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// The reason the model stopped generating.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinishReason {
+    /// The model finished its answer or hit a stop sequence.
+    Stop,
+    /// The token limit or the end of the context window was reached.
+    Length,
+    /// The model stopped to call one or more tools.
+    ToolCalls,
+    /// The answer was cut off by a content filter.
+    ContentFilter,
+    /// A reason specific to the backend.
+    Other(String),
+}
+
+/// Information about a response, besides its content.
+///
+/// Every field is optional, since not every backend reports all of them. Streaming outputs
+/// deliver it in a `StreamSegment::Metadata` segment, usually the last one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseMetadata {
+    /// The identifier the backend assigned to the response.
+    pub id: Option<String>,
+    /// The model that produced the response.
+    pub model: Option<String>,
+    /// Why the model stopped generating.
+    pub finish_reason: Option<FinishReason>,
+    /// The tokens the call consumed.
+    pub usage: Option<TokenUsage>,
+}
+
+impl ResponseMetadata {
+    /// Fills the fields of `self` that are unset with those of `other`.
+    pub fn merge(&mut self, other: ResponseMetadata) {
+        self.id = self.id.take().or(other.id);
+        self.model = self.model.take().or(other.model);
+        self.finish_reason = self.finish_reason.take().or(other.finish_reason);
+        self.usage = self.usage.take().or(other.usage);
+    }
+}
@@ -1,12 +1,21 @@
+mod candidate;
+mod metadata;
+mod partial;
 mod stream;
 
 use core::fmt;
 
-use crate::{prompt::Data, traits::ExecutorError};
+use crate::{
+    prompt::{Data, ToolCall},
+    traits::ExecutorError,
+};
 use thiserror;
 use tokio::sync::mpsc;
 
-pub use stream::{OutputStream, StreamSegment};
+pub use candidate::{Candidate, TokenLogprob};
+pub use metadata::{FinishReason, ResponseMetadata, TokenUsage};
+pub use partial::{PartialParseError, PartialParser, PartialStream};
+pub use stream::{OutputStream, RecordedSegment, StreamSegment, ToolCallDelta};
 pub use tokio_stream::{Stream, StreamExt};
 
 /// The `Output` enum provides a general interface for outputs of different types.
@@ -31,7 +40,13 @@ impl Output {
     pub async fn to_immediate(self) -> Result<Immediate, ExecutorError> {
         match self {
             Output::Immediate(x) => Ok(x),
-            Output::Stream(x) => Ok(Immediate(x.into_data().await?)),
+            Output::Stream(x) => {
+                let (candidate, metadata) = x.into_candidate().await?;
+                Ok(Immediate {
+                    candidates: vec![candidate],
+                    metadata,
+                })
+            }
         }
     }
 
@@ -51,6 +66,16 @@ impl Output {
         (sender, Output::Stream(stream))
     }
 
+    /// Creates a new `Stream` output backed by a channel that holds at most `capacity` segments.
+    ///
+    /// Sending waits while the channel is full, so a slow consumer slows down the producer
+    /// instead of making the buffer grow.
+    pub fn new_bounded_stream(capacity: usize) -> (mpsc::Sender<StreamSegment>, Self) {
+        let (sender, stream) = OutputStream::new_bounded(capacity);
+
+        (sender, Output::Stream(stream))
+    }
+
     pub fn from_stream<S>(stream: S) -> Self
     where
         S: Stream<Item = StreamSegment> + Send + 'static,
@@ -60,38 +85,102 @@ impl Output {
 
     /// Creates a new `Immediate` output from the given data.
     pub fn new_immediate(data: Data<String>) -> Self {
-        Output::Immediate(Immediate(data))
+        Self::new_immediate_with_metadata(data, ResponseMetadata::default())
+    }
+
+    /// Creates a new `Immediate` output from the given data and response metadata.
+    pub fn new_immediate_with_metadata(data: Data<String>, metadata: ResponseMetadata) -> Self {
+        let candidate = Candidate {
+            finish_reason: metadata.finish_reason.clone(),
+            ..Candidate::new(data)
+        };
+        Self::new_immediate_with_candidates(vec![candidate], metadata)
+    }
+
+    /// Creates a new `Immediate` output holding several candidate completions.
+    ///
+    /// The first candidate is the primary one, returned by `Immediate::get_content`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `candidates` is empty.
+    pub fn new_immediate_with_candidates(
+        candidates: Vec<Candidate>,
+        metadata: ResponseMetadata,
+    ) -> Self {
+        assert!(
+            !candidates.is_empty(),
+            "an output needs at least one candidate"
+        );
+        Output::Immediate(Immediate {
+            candidates,
+            metadata,
+        })
     }
 }
 
 impl fmt::Display for Output {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Output::Immediate(Immediate(data)) => data.fmt(f),
+            Output::Immediate(immediate) => immediate.fmt(f),
             Output::Stream(_) => write!(f, "<OutputStream>"),
         }
     }
 }
 
-pub struct Immediate(Data<String>);
+pub struct Immediate {
+    candidates: Vec<Candidate>,
+    metadata: ResponseMetadata,
+}
 
 impl Immediate {
     /// Returns a reference to the content if it is immediately available.
     pub fn get_content(&self) -> &Data<String> {
-        &self.0
+        &self.candidates[0].content
     }
 
     pub fn as_content(self) -> Data<String> {
-        self.0
+        self.candidates.into_iter().next().unwrap().content
+    }
+
+    /// Returns every candidate completion, the primary one first.
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    /// Returns the log-probabilities of the tokens of the primary candidate, if requested.
+    pub fn logprobs(&self) -> Option<&[TokenLogprob]> {
+        self.candidates[0].logprobs.as_deref()
+    }
+
+    /// Returns the token usage, finish reason and other information the backend reported.
+    pub fn metadata(&self) -> &ResponseMetadata {
+        &self.metadata
+    }
+
+    /// Splits the output into its candidates and metadata.
+    pub fn into_parts(self) -> (Vec<Candidate>, ResponseMetadata) {
+        (self.candidates, self.metadata)
     }
 
     pub fn primary_textual_output(&self) -> Option<String> {
         self.get_content().extract_last_body().cloned()
     }
+
+    /// Returns the tool calls the model requested in its last message, if any.
+    pub fn tool_calls(&self) -> &[ToolCall] {
+        match self.get_content() {
+            Data::Chat(chat) => chat
+                .last_message()
+                .map(|msg| msg.tool_calls())
+                .unwrap_or_default(),
+            Data::Text(_) => &[],
+        }
+    }
 }
 
 impl fmt::Display for Immediate {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0.fmt(f)
+        self.get_content().fmt(f)
     }
 }
@@ -0,0 +1,266 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio_stream::Stream;
+
+use super::{OutputStream, StreamSegment};
+use crate::parsing::{find_yaml_or_json, json_spans, repair_json, starts_yaml, ExtractionError};
+use crate::traits::ExecutorError;
+
+/// Parses JSON or YAML incrementally, as the text of a response arrives.
+///
+/// After every chunk the text received so far is parsed into a `serde_json::Value` snapshot.
+/// Objects, arrays and strings that are still open are closed, so the snapshot already holds the
+/// values that are still arriving. A key is only included once it is complete.
+///
+/// Like `parsing::find_yaml`, leading prose and markdown code fences are skipped. JSON is
+/// recognized by its opening brace or bracket. YAML is parsed one complete line at a time, either
+/// inside a `yaml` code block or when the text starts with a `key:` line or a list item.
+#[derive(Debug, Default)]
+pub struct PartialParser {
+    text: String,
+    snapshot: Option<Value>,
+}
+
+impl PartialParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `chunk` to the text, returning the new snapshot if it changed.
+    pub fn push(&mut self, chunk: &str) -> Option<&Value> {
+        self.text.push_str(chunk);
+        let snapshot = parse_snapshot(&self.text)?;
+        if self.snapshot.as_ref() == Some(&snapshot) {
+            return None;
+        }
+        self.snapshot = Some(snapshot);
+        self.snapshot.as_ref()
+    }
+
+    /// Returns the latest snapshot, if anything could be parsed yet.
+    pub fn snapshot(&self) -> Option<&Value> {
+        self.snapshot.as_ref()
+    }
+
+    /// Returns the text received so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Parses the complete text into a `T`, the same way `structured` parses answers: leading prose
+    /// is skipped, and text that holds no object or list is an error.
+    pub fn finish<T: DeserializeOwned>(&self) -> Result<T, ExtractionError> {
+        find_yaml_or_json(&self.text)
+    }
+}
+
+/// Returns the language of the first code block in `text`, if any, and the text to parse: the
+/// contents of that code block so far, or the whole text.
+fn body(text: &str) -> (Option<&str>, &str) {
+    let Some(start) = text.find("```") else {
+        return (None, text);
+    };
+    let rest = &text[start + 3..];
+    let Some(newline) = rest.find('\n') else {
+        return (Some(rest.trim()), "");
+    };
+    let lang = rest[..newline].trim();
+    let body = &rest[newline + 1..];
+    let body = match body.find("```") {
+        Some(end) => &body[..end],
+        // Drop what may be the start of the closing fence.
+        None => body.trim_end_matches('`'),
+    };
+    (Some(lang), body)
+}
+
+fn parse_json_snapshot(body: &str) -> Option<Value> {
+    let candidate = *json_spans(body).first()?;
+    if let Ok(value) = serde_json::from_str(&repair_json(candidate)) {
+        return Some(value);
+    }
+    // The text most likely ends in the middle of a key or a literal, drop the last member.
+    let cut = candidate.rfind(',')?;
+    serde_json::from_str(&repair_json(&candidate[..cut])).ok()
+}
+
+fn parse_yaml_snapshot(body: &str) -> Option<Value> {
+    // The last line may still be incomplete.
+    let complete = &body[..body.rfind('\n')? + 1];
+    let yaml: serde_yaml::Value = serde_yaml::from_str(complete).ok()?;
+    match serde_json::to_value(yaml).ok()? {
+        Value::Null => None,
+        value => Some(value),
+    }
+}
+
+fn parse_snapshot(text: &str) -> Option<Value> {
+    let (lang, body) = body(text);
+    let first_line = body.trim_start().lines().next().unwrap_or_default();
+    match lang {
+        Some("yaml") | Some("yml") => parse_yaml_snapshot(body),
+        Some(_) => parse_json_snapshot(body).or_else(|| parse_yaml_snapshot(body)),
+        None if starts_yaml(first_line) => parse_yaml_snapshot(body),
+        // Without a code block the text may be prose around JSON, but not around YAML.
+        None => parse_json_snapshot(body),
+    }
+}
+
+/// An error reading the final value from a `PartialStream`.
+#[derive(Debug, thiserror::Error)]
+pub enum PartialParseError {
+    #[error(transparent)]
+    Executor(#[from] ExecutorError),
+    #[error(transparent)]
+    Extraction(#[from] ExtractionError),
+}
+
+/// A stream of progressively more complete snapshots of the structured value in an
+/// `OutputStream`, created by `OutputStream::parse_partial`.
+///
+/// A snapshot is yielded whenever a content segment changes it. Errors from the executor are
+/// passed through, and other segments are skipped. Once the stream is done, or at any point,
+/// `into_value` reads the rest of the output and parses the final value.
+pub struct PartialStream {
+    stream: OutputStream,
+    parser: PartialParser,
+}
+
+impl PartialStream {
+    /// Returns the parser, holding the text received so far and the latest snapshot.
+    pub fn parser(&self) -> &PartialParser {
+        &self.parser
+    }
+
+    /// Reads the rest of the output and parses the complete text into a `T`.
+    pub async fn into_value<T: DeserializeOwned>(mut self) -> Result<T, PartialParseError> {
+        if self.stream.is_cancelled() {
+            return Err(ExecutorError::Cancelled.into());
+        }
+        while let Some(snapshot) = self.next().await {
+            snapshot?;
+        }
+        Ok(self.parser.finish()?)
+    }
+}
+
+impl Stream for PartialStream {
+    type Item = Result<Value, ExecutorError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let segment = match self.stream.poll_next_unpin(cx) {
+                Poll::Ready(Some(segment)) => segment,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            match segment {
+                StreamSegment::Content(chunk) => {
+                    if let Some(snapshot) = self.parser.push(&chunk) {
+                        return Poll::Ready(Some(Ok(snapshot.clone())));
+                    }
+                }
+                StreamSegment::Err(err) => return Poll::Ready(Some(Err(err))),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl OutputStream {
+    /// Parses the content of the stream incrementally as JSON or YAML. See `PartialParser`.
+    pub fn parse_partial(self) -> PartialStream {
+        PartialStream {
+            stream: self,
+            parser: PartialParser::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn snapshots(chunks: &[&str]) -> Vec<Value> {
+        let mut parser = PartialParser::new();
+        chunks
+            .iter()
+            .filter_map(|chunk| parser.push(chunk).cloned())
+            .collect()
+    }
+
+    #[test]
+    fn test_json_snapshots_grow() {
+        let chunks = [
+            "Sure, here it is:\n```json\n{\"name\": \"Bo",
+            "b\", \"tags\": [\"a\"",
+            ", \"b\"], \"ag",
+            "e\": 42}\n```\nAnything else?",
+        ];
+        assert_eq!(
+            snapshots(&chunks),
+            vec![
+                json!({"name": "Bo"}),
+                json!({"name": "Bob", "tags": ["a"]}),
+                json!({"name": "Bob", "tags": ["a", "b"]}),
+                json!({"name": "Bob", "tags": ["a", "b"], "age": 42}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_yaml_snapshots_use_complete_lines() {
+        let chunks = ["name: Bob\nag", "e: 42\ntags:\n", "  - a\n"];
+        assert_eq!(
+            snapshots(&chunks),
+            vec![
+                json!({"name": "Bob"}),
+                json!({"name": "Bob", "age": 42, "tags": null}),
+                json!({"name": "Bob", "age": 42, "tags": ["a"]}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_finish_skips_leading_prose() {
+        let mut parser = PartialParser::new();
+        parser.push(r#"Sure: {"name": "Bob", "age": 42}"#);
+        assert_eq!(
+            parser.finish::<Value>().unwrap(),
+            json!({"name": "Bob", "age": 42})
+        );
+
+        let mut parser = PartialParser::new();
+        parser.push("Sure, I can help with that.");
+        assert!(parser.finish::<Value>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_yields_snapshots_and_final_value() {
+        #[derive(serde::Deserialize)]
+        struct Person {
+            name: String,
+        }
+
+        let (sender, stream) = OutputStream::new();
+        for chunk in ["{\"na", "me\": \"Al", "ice\"}"] {
+            sender
+                .send(StreamSegment::Content(chunk.to_string()))
+                .unwrap();
+        }
+        drop(sender);
+        let mut partial = stream.parse_partial();
+        // Nothing can be parsed until the first key is complete.
+        assert_eq!(
+            partial.next().await.unwrap().unwrap(),
+            json!({"name": "Al"})
+        );
+        let person: Person = partial.into_value().await.unwrap();
+        assert_eq!(person.name, "Alice");
+    }
+}
@@ -1,17 +1,43 @@
 use crate::prompt::{ChatRole, Data};
 use crate::traits::ExecutorError;
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::sync::mpsc::{self, Receiver, UnboundedReceiver};
 use tokio_stream::Stream;
 
-use crate::prompt::{ChatMessage, ChatMessageCollection};
+use crate::prompt::{ChatMessage, ChatMessageCollection, ToolCall};
+
+use super::{Candidate, Output, ResponseMetadata, TokenLogprob};
+
+/// A fragment of a tool call received while streaming.
+///
+/// Fragments with the same `index` belong to the same call. The `id` and `name` usually arrive
+/// with the first fragment, while the `arguments` are spread across many of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    /// The position of the tool call within the message.
+    pub index: usize,
+    /// The identifier of the call, if present in this fragment.
+    pub id: Option<String>,
+    /// The name of the tool, if present in this fragment.
+    pub name: Option<String>,
+    /// The next piece of the JSON arguments.
+    pub arguments: String,
+}
+
 #[derive(Debug)]
 pub enum StreamSegment {
     Role(ChatRole),
     Content(String),
+    ToolCall(ToolCallDelta),
+    /// The log-probability of the latest generated token, sent when requested.
+    Logprob(TokenLogprob),
+    /// Information about the response, sent once the backend has reported it.
+    Metadata(ResponseMetadata),
     Err(ExecutorError),
 }
 
@@ -20,18 +46,91 @@ impl fmt::Display for StreamSegment {
         match self {
             StreamSegment::Role(chat_role) => write!(f, "{}", chat_role),
             StreamSegment::Content(content) => write!(f, "{}", content),
+            StreamSegment::ToolCall(delta) => write!(f, "{}", delta.arguments),
+            StreamSegment::Logprob(_) | StreamSegment::Metadata(_) => Ok(()),
             StreamSegment::Err(executor_error) => write!(f, "{}", executor_error),
         }
     }
 }
 
+/// A successfully received `StreamSegment`, as recorded by `OutputStream::record`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordedSegment {
+    Role(ChatRole),
+    Content(String),
+    ToolCall(ToolCallDelta),
+    Logprob(TokenLogprob),
+    Metadata(ResponseMetadata),
+}
+
+impl From<RecordedSegment> for StreamSegment {
+    fn from(segment: RecordedSegment) -> Self {
+        match segment {
+            RecordedSegment::Role(role) => StreamSegment::Role(role),
+            RecordedSegment::Content(content) => StreamSegment::Content(content),
+            RecordedSegment::ToolCall(delta) => StreamSegment::ToolCall(delta),
+            RecordedSegment::Logprob(logprob) => StreamSegment::Logprob(logprob),
+            RecordedSegment::Metadata(metadata) => StreamSegment::Metadata(metadata),
+        }
+    }
+}
+
+impl RecordedSegment {
+    /// Records `segment`, or returns `None` for errors, which aren't recorded.
+    pub fn from_segment(segment: &StreamSegment) -> Option<Self> {
+        match segment {
+            StreamSegment::Role(role) => Some(RecordedSegment::Role(role.clone())),
+            StreamSegment::Content(content) => Some(RecordedSegment::Content(content.clone())),
+            StreamSegment::ToolCall(delta) => Some(RecordedSegment::ToolCall(delta.clone())),
+            StreamSegment::Logprob(logprob) => Some(RecordedSegment::Logprob(logprob.clone())),
+            StreamSegment::Metadata(metadata) => Some(RecordedSegment::Metadata(metadata.clone())),
+            StreamSegment::Err(_) => None,
+        }
+    }
+}
+
+/// The number of segments `OutputStream::from_stream` buffers ahead of the consumer.
+const FORWARD_BUFFER: usize = 32;
+
+enum SegmentReceiver {
+    Unbounded(UnboundedReceiver<StreamSegment>),
+    Bounded(Receiver<StreamSegment>),
+    Cancelled,
+}
+
+impl SegmentReceiver {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<StreamSegment>> {
+        match self {
+            SegmentReceiver::Unbounded(receiver) => receiver.poll_recv(cx),
+            SegmentReceiver::Bounded(receiver) => receiver.poll_recv(cx),
+            SegmentReceiver::Cancelled => Poll::Ready(None),
+        }
+    }
+
+    async fn recv(&mut self) -> Option<StreamSegment> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+}
+
+/// A stream of segments produced by an executor over time.
+///
+/// Dropping the stream, or calling `cancel`, closes the channel it is read from. Producers see
+/// this through a failing `send` or through `is_closed` on their sender and should stop
+/// generating.
 pub struct OutputStream {
-    receiver: UnboundedReceiver<StreamSegment>,
+    receiver: SegmentReceiver,
 }
 
 impl OutputStream {
     pub(super) fn new() -> (mpsc::UnboundedSender<StreamSegment>, Self) {
         let (sender, receiver) = mpsc::unbounded_channel();
+        let receiver = SegmentReceiver::Unbounded(receiver);
+        (sender, Self { receiver })
+    }
+
+    pub(super) fn new_bounded(capacity: usize) -> (mpsc::Sender<StreamSegment>, Self) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let receiver = SegmentReceiver::Bounded(receiver);
         (sender, Self { receiver })
     }
 
@@ -39,35 +138,101 @@ impl OutputStream {
     where
         S: Stream<Item = StreamSegment> + Send + 'static,
     {
-        let (sender, receiver) = mpsc::unbounded_channel();
-        let sender_clone = sender;
+        let (sender, output) = Self::new_bounded(FORWARD_BUFFER);
         let mut stream = Box::pin(stream);
 
+        // The source stream is dropped as soon as the output is, which cancels it.
         tokio::spawn(async move {
-            while let Some(segment) = stream.next().await {
-                if sender_clone.send(segment).is_err() {
-                    break;
+            loop {
+                tokio::select! {
+                    segment = stream.next() => match segment {
+                        Some(segment) => {
+                            if sender.send(segment).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    },
+                    _ = sender.closed() => break,
                 }
             }
         });
 
-        Self { receiver }
+        output
     }
 
-    pub(super) async fn into_data(self) -> Result<Data<String>, ExecutorError> {
+    /// Forwards the stream to a new output while recording its segments, and passes the recording
+    /// to `on_end` once the stream is over. An error returned by `on_end` is sent as the last
+    /// segment of the output.
+    ///
+    /// Streams that fail, or whose output is dropped before their end, aren't passed to `on_end`.
+    /// Dropping the output stops reading, and so cancels, this stream.
+    pub fn record<F, Fut>(mut self, on_end: F) -> Output
+    where
+        F: FnOnce(Vec<RecordedSegment>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), ExecutorError>> + Send,
+    {
+        let (sender, output) = Output::new_stream();
+        tokio::spawn(async move {
+            let mut segments = Vec::new();
+            loop {
+                let segment = tokio::select! {
+                    segment = self.next() => segment,
+                    _ = sender.closed() => return,
+                };
+                let Some(segment) = segment else { break };
+                let recorded = RecordedSegment::from_segment(&segment);
+                if sender.send(segment).is_err() {
+                    return;
+                }
+                match recorded {
+                    Some(segment) => segments.push(segment),
+                    None => return,
+                }
+            }
+            if let Err(err) = on_end(segments).await {
+                let _ = sender.send(StreamSegment::Err(err));
+            }
+        });
+        output
+    }
+
+    /// Stops the stream. No more segments are returned and the producer is told to stop
+    /// generating.
+    pub fn cancel(&mut self) {
+        self.receiver = SegmentReceiver::Cancelled;
+    }
+
+    /// Returns true if `cancel` was called on the stream.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.receiver, SegmentReceiver::Cancelled)
+    }
+
+    pub(super) async fn into_candidate(
+        self,
+    ) -> Result<(Candidate, ResponseMetadata), ExecutorError> {
+        let mut metadata = ResponseMetadata::default();
+        let mut logprobs: Option<Vec<TokenLogprob>> = None;
         let mut messages = ChatMessageCollection::new();
         let mut current_role = None;
         let mut current_body = Vec::new();
+        let mut current_tool_calls: Vec<ToolCall> = Vec::new();
 
         let mut stream = self.receiver;
+        if let SegmentReceiver::Cancelled = stream {
+            return Err(ExecutorError::Cancelled);
+        }
 
         while let Some(segment) = stream.recv().await {
             match segment {
                 StreamSegment::Role(role) => {
                     if let Some(role) = current_role {
-                        if !current_body.is_empty() {
+                        if !current_body.is_empty() || !current_tool_calls.is_empty() {
                             let body = current_body.join("");
-                            messages.add_message(ChatMessage::new(role, body));
+                            messages.add_message(
+                                ChatMessage::new(role, body)
+                                    .with_tool_calls(std::mem::take(&mut current_tool_calls)),
+                            );
                             current_body.clear();
                         }
                     }
@@ -76,20 +241,50 @@ impl OutputStream {
                 StreamSegment::Content(text) => {
                     current_body.push(text);
                 }
+                StreamSegment::ToolCall(delta) => {
+                    if current_tool_calls.len() <= delta.index {
+                        current_tool_calls.resize_with(delta.index + 1, || {
+                            ToolCall::new(String::new(), String::new(), String::new())
+                        });
+                    }
+                    let call = &mut current_tool_calls[delta.index];
+                    if let Some(id) = delta.id {
+                        call.id = id;
+                    }
+                    if let Some(name) = delta.name {
+                        call.name.push_str(&name);
+                    }
+                    call.arguments.push_str(&delta.arguments);
+                }
+                StreamSegment::Logprob(logprob) => {
+                    logprobs.get_or_insert_with(Vec::new).push(logprob)
+                }
+                StreamSegment::Metadata(reported) => metadata.merge(reported),
                 StreamSegment::Err(err) => return Err(err),
             }
         }
 
         let body = current_body.join("");
+        // Tool calls are only ever made by the assistant, so they imply a chat response.
+        if current_role.is_none() && !current_tool_calls.is_empty() {
+            current_role = Some(ChatRole::Assistant);
+        }
         // Handle any remaining message
-        if let Some(role) = current_role {
-            if !current_body.is_empty() {
-                messages.add_message(ChatMessage::new(role, body));
+        let content = if let Some(role) = current_role {
+            if !current_body.is_empty() || !current_tool_calls.is_empty() {
+                messages
+                    .add_message(ChatMessage::new(role, body).with_tool_calls(current_tool_calls));
             }
-            Ok(messages.into())
+            messages.into()
         } else {
-            Ok(Data::text(body))
-        }
+            Data::text(body)
+        };
+        let candidate = Candidate {
+            content,
+            finish_reason: metadata.finish_reason.clone(),
+            logprobs,
+        };
+        Ok((candidate, metadata))
     }
 }
 
@@ -100,3 +295,52 @@ impl Stream for OutputStream {
         self.receiver.poll_recv(cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_cancel_closes_the_channel() {
+        let (sender, mut stream) = OutputStream::new();
+        sender
+            .send(StreamSegment::Content("a".to_string()))
+            .unwrap();
+        stream.cancel();
+        assert!(sender.is_closed());
+        assert!(stream.next().await.is_none());
+        assert!(matches!(
+            stream.into_candidate().await,
+            Err(ExecutorError::Cancelled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dropping_the_output_drops_the_source() {
+        struct SetOnDrop(Arc<AtomicBool>);
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        let source = futures::stream::repeat_with(move || {
+            let _ = &guard;
+            StreamSegment::Content("a".to_string())
+        });
+        let mut stream = OutputStream::from_stream(source);
+        assert!(stream.next().await.is_some());
+        drop(stream);
+        for _ in 0..100 {
+            if dropped.load(Ordering::SeqCst) {
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+        panic!("the source stream was not dropped");
+    }
+}
@@ -7,6 +7,10 @@ use std::{
     fmt::Debug,
 };
 
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
 type Map = BTreeMap<String, Box<dyn ParamFull>>;
 
 /// Parameters define the parameters sent into each step. The parameters are used to fill in the prompt template, and are also filled in by the output of the previous step. Parameters have a special key, `text`, which is used as a default key for simple use cases.
@@ -28,6 +32,21 @@ type Map = BTreeMap<String, Box<dyn ParamFull>>;
 /// assert_eq!(p.get("text").unwrap().as_str(), "Hello world!");
 /// assert_eq!(p.get("name").unwrap().as_str(), "John Doe");
 /// ```
+/// **Creating parameters holding lists and objects from any serializable value**
+/// ```
+/// use llm_chain::prompt::StringTemplate;
+/// use llm_chain::Parameters;
+/// #[derive(serde::Serialize)]
+/// struct Order {
+///     customer: String,
+///     items: Vec<&'static str>,
+/// }
+/// let order = Order { customer: "Ann".to_string(), items: vec!["tea", "cake"] };
+/// let p = Parameters::from_serialize(&order).unwrap();
+/// let template = StringTemplate::tera("{{ customer }}: {% for i in items %}[{{ i }}]{% endfor %}");
+/// assert_eq!(template.format(&p).unwrap(), "Ann: [tea][cake]");
+/// assert_eq!(p.get("items").unwrap(), r#"["tea","cake"]"#);
+/// ```
 #[derive(Default, Debug)]
 pub struct Parameters {
     map: Map,
@@ -48,7 +67,7 @@ impl PartialEq for Parameters {
         self.map.keys().len() == other.map.keys().len()
             && self.map.iter().all(|(k, v)| {
                 if let Some(other_v) = other.map.get(k) {
-                    v.get() == other_v.get()
+                    v.get_value() == other_v.get_value()
                 } else {
                     false
                 }
@@ -58,6 +77,11 @@ impl PartialEq for Parameters {
 
 pub trait Param: Send + Sync {
     fn get(&self) -> String;
+
+    /// Returns the value templates see, the text of `get` unless the parameter is structured.
+    fn get_value(&self) -> Value {
+        Value::String(self.get())
+    }
 }
 
 /// This trait is used to implement a dynamic parameter this shouldn't be used but exists only for internal purposes.
@@ -90,6 +114,36 @@ impl Param for StringParam {
     }
 }
 
+/// A parameter holding a list, an object, a number or any other JSON value.
+#[derive(Debug, Clone)]
+struct ValueParam {
+    value: Value,
+}
+
+impl Param for ValueParam {
+    /// Returns strings as they are and other values as JSON.
+    fn get(&self) -> String {
+        match &self.value {
+            Value::String(text) => text.clone(),
+            value => value.to_string(),
+        }
+    }
+
+    fn get_value(&self) -> Value {
+        self.value.clone()
+    }
+}
+
+/// An error creating parameters from a serializable value.
+#[derive(Debug, Error)]
+pub enum ParametersError {
+    #[error("unable to serialize the value: {0}")]
+    Serialize(#[from] serde_json::Error),
+    /// Only values serialized as a map, such as structs and maps, have keys to use as names.
+    #[error("the value isn't serialized as a map, but as {0}")]
+    NotAMap(Value),
+}
+
 const TEXT_KEY: &str = "text";
 
 impl Parameters {
@@ -114,6 +168,32 @@ impl Parameters {
         copy
     }
 
+    /// Copies the parameters and adds a new key-value pair, where the value is structured, such as a
+    /// list or an object. Templates can loop over lists and read the fields of objects.
+    pub fn with_value<K: Into<String>, V: Into<Value>>(&self, key: K, value: V) -> Parameters {
+        let mut copy = self.clone();
+        copy.map.insert(
+            key.into(),
+            Box::new(ValueParam {
+                value: value.into(),
+            }),
+        );
+        copy
+    }
+
+    /// Creates parameters from a value serialized as a map, such as a struct, with a parameter for
+    /// each of its fields. Fields holding lists, maps or numbers keep their structure.
+    pub fn from_serialize<T: Serialize + ?Sized>(value: &T) -> Result<Parameters, ParametersError> {
+        match serde_json::to_value(value)? {
+            Value::Object(fields) => Ok(fields
+                .into_iter()
+                .fold(Parameters::new(), |parameters, (key, value)| {
+                    parameters.with_value(key, value)
+                })),
+            value => Err(ParametersError::NotAMap(value)),
+        }
+    }
+
     /// Copies the parameters and adds a new key-value pair pair, where the value is a dynamic parameter.
     pub fn with_dynamic<K: Into<String>, V: ParamFull>(&self, key: K, value: V) -> Parameters {
         let mut copy = self.clone();
@@ -139,6 +219,11 @@ impl Parameters {
         self.map.get(key).map(|param| param.get())
     }
 
+    /// Returns the value of the given key as templates see it, or `None` if the key does not exist.
+    pub fn get_value(&self, key: &str) -> Option<Value> {
+        self.map.get(key).map(|param| param.get_value())
+    }
+
     pub fn get_text(&self) -> Option<String> {
         self.get(TEXT_KEY)
     }
@@ -146,7 +231,7 @@ impl Parameters {
     pub(crate) fn to_tera(&self) -> tera::Context {
         let mut context = tera::Context::new();
         for (key, value) in self.map.iter() {
-            context.insert(key, &value.get());
+            context.insert(key, &value.get_value());
         }
         context
     }
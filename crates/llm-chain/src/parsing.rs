@@ -6,6 +6,7 @@
 //! - Parsing YAML and Markdown content produced by LLMs
 //! - Handling common edge cases and being lenient with LLM outputs
 //! - Extracting and deserializing YAML objects from text
+//! - Extracting JSON from code blocks or prose, repairing the mistakes models commonly make
 //!
 //! With these functions, you can easily work with the outputs of LLMs, simplifying the process of integrating LLMs into your applications and workflows.
 
@@ -34,23 +35,42 @@ enum ExtractionErrorImpl {
     #[error("YAML parsing failed with: {0}")]
     ParseError(#[from] serde_yaml::Error),
 
+    /// The JSON content was valid, possibly after repair, but it did not match the expected format.
+    #[error("The JSON was valid, but it didn't match the expected format: {0}")]
+    JsonFoundButFormatWrong(serde_json::Error),
+
+    /// An error occurred while parsing the JSON content, even after repair.
+    #[error("JSON parsing failed with: {0}")]
+    JsonParseError(serde_json::Error),
+
+    /// The text outside of code blocks parsed as YAML, but not into an object or a list.
+    #[error("The text isn't an object or a list")]
+    NotStructured,
+
     /// No YAML content was found to parse.
     #[error("The string to parse was empty")]
     NoneFound,
 }
 
 impl ExtractionErrorImpl {
+    /// Ranks how much an error tells about what went wrong, higher is more.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::YamlFoundButFormatWrong(_) | Self::JsonFoundButFormatWrong(_) => 2,
+            Self::ParseError(_) | Self::JsonParseError(_) | Self::NotStructured => 1,
+            Self::NoneFound => 0,
+        }
+    }
+
     /// Determines the most representative error between two instances of `ExtractionErrorImpl`.
     ///
-    /// The function prefers `YamlFoundButFormatWrong` errors over `ParseError` errors,
-    /// and `ParseError` errors over `NoneFound` errors.
+    /// The function prefers errors about content in the wrong format over parse errors, and
+    /// parse errors over `NoneFound` errors. On a tie the first error is kept.
     fn most_representative(a: Self, b: Self) -> Self {
-        match (&a, &b) {
-            (Self::YamlFoundButFormatWrong(_), _) => a,
-            (_, Self::YamlFoundButFormatWrong(_)) => b,
-            (Self::ParseError(_), _) => a,
-            (_, Self::ParseError(_)) => b,
-            _ => a,
+        if b.rank() > a.rank() {
+            b
+        } else {
+            a
         }
     }
 }
@@ -177,6 +197,318 @@ pub fn find_yaml<T: DeserializeOwned>(text: &str) -> Result<Vec<T>, ExtractionEr
     }
 }
 
+/// Returns the code blocks in a markdown document, with their language, in document order.
+fn code_blocks(text: &str) -> Vec<(String, String)> {
+    let options = ParseOptions::default();
+    let ast = to_mdast(text, &options).expect("we're not using MDX, so this should never fail");
+    let mut nodes = vec![ast];
+    let mut blocks = VecDeque::new();
+    while let Some(node) = nodes.pop() {
+        if let Some(children) = node.children() {
+            children.iter().for_each(|child| nodes.push(child.clone()));
+        }
+        if let Node::Code(Code { value, lang, .. }) = node {
+            blocks.push_front((lang.unwrap_or_default(), value));
+        }
+    }
+    blocks.into()
+}
+
+/// Returns the spans of `text` that look like JSON objects or arrays: everything from an opening
+/// brace or bracket to the one closing it, or to the end of the text if it is never closed.
+pub(crate) fn json_spans(text: &str) -> Vec<&str> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' if start.is_some() => quote = Some(c),
+            '{' | '[' => {
+                start.get_or_insert(i);
+                depth += 1;
+            }
+            '}' | ']' if start.is_some() => {
+                depth -= 1;
+                if depth == 0 {
+                    spans.extend(start.take().map(|start| &text[start..=i]));
+                }
+            }
+            _ => {}
+        }
+    }
+    spans.extend(start.map(|start| &text[start..]));
+    spans
+}
+
+/// Removes the last non-whitespace character of `out` if it is a comma.
+fn drop_trailing_comma(out: &mut String) {
+    let trimmed = out.trim_end().len();
+    if out[..trimmed].ends_with(',') {
+        out.truncate(trimmed - 1);
+    }
+}
+
+/// Turns almost-JSON, as models like to write it, into JSON.
+///
+/// It removes comments and trailing commas, turns single quoted strings into double quoted ones,
+/// quotes bare object keys, and closes strings, objects and arrays left open by a truncated
+/// answer. Valid JSON is returned unchanged, apart from comments.
+pub(crate) fn repair_json(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut closers = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                out.push('"');
+                while let Some(c2) = chars.next() {
+                    match c2 {
+                        '\\' => match chars.next() {
+                            Some('\'') if c == '\'' => out.push('\''),
+                            Some(escaped) => {
+                                out.push('\\');
+                                out.push(escaped);
+                            }
+                            None => {}
+                        },
+                        _ if c2 == c => break,
+                        '"' => out.push_str("\\\""),
+                        '\n' => out.push_str("\\n"),
+                        _ => out.push(c2),
+                    }
+                }
+                // This also closes a string cut off by the end of the text.
+                out.push('"');
+            }
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|c| *c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c2 in chars.by_ref() {
+                    if previous == '*' && c2 == '/' {
+                        break;
+                    }
+                    previous = c2;
+                }
+            }
+            '{' => {
+                closers.push('}');
+                out.push(c);
+            }
+            '[' => {
+                closers.push(']');
+                out.push(c);
+            }
+            '}' | ']' => {
+                drop_trailing_comma(&mut out);
+                closers.pop();
+                out.push(c);
+            }
+            _ if (c.is_alphabetic() || c == '_' || c == '$')
+                && closers.last() == Some(&'}')
+                && matches!(out.trim_end().chars().last(), Some('{') | Some(',')) =>
+            {
+                let mut key = c.to_string();
+                while let Some(c2) =
+                    chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+                {
+                    key.push(c2);
+                }
+                out.push('"');
+                out.push_str(&key);
+                out.push('"');
+            }
+            _ => out.push(c),
+        }
+    }
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    if out.ends_with(':') {
+        out.push_str(" null");
+    }
+    while let Some(closer) = closers.pop() {
+        drop_trailing_comma(&mut out);
+        out.push(closer);
+    }
+    out
+}
+
+/// Attempts to parse JSON from a candidate, repairing it if needed, and deserialize it into the
+/// specified type.
+fn extract_json<T: DeserializeOwned>(candidate: &str) -> Result<T, ExtractionErrorImpl> {
+    let candidate = Some(candidate.trim())
+        .filter(|s| !s.is_empty())
+        .ok_or(ExtractionErrorImpl::NoneFound)?;
+    let json: serde_json::Value = match serde_json::from_str(candidate) {
+        Ok(json) => json,
+        Err(err) => serde_json::from_str(&repair_json(candidate))
+            .map_err(|_| ExtractionErrorImpl::JsonParseError(err))?,
+    };
+    serde_json::from_value(json).map_err(ExtractionErrorImpl::JsonFoundButFormatWrong)
+}
+
+/// Attempts to find JSON values in a string and deserialize them into the specified type.
+///
+/// Code blocks marked as JSON, JavaScript or without a language are tried first. If none of them
+/// holds a usable value, the objects and arrays found in the bare text are tried, so prose around
+/// the JSON doesn't matter. Every candidate that isn't valid JSON goes through a lenient repair
+/// pass, which removes comments and trailing commas, replaces single quotes, quotes bare keys and
+/// closes a truncated value.
+///
+/// # Arguments
+///
+/// * `text` - A string slice containing the document which may contain JSON content.
+///
+/// # Returns
+///
+/// * `Ok(Vec<T>)` - The values that were found, in the order they appear.
+/// * `Err(ExtractionError)` - The most representative error if no value was found.
+///
+/// # Examples
+///
+/// It handles JSON surrounded by prose, with the usual mistakes.
+///
+/// ```
+/// #[derive(serde::Deserialize)]
+/// struct Dummy {
+///    hello: String
+/// }
+/// use llm_chain::parsing::find_json;
+/// let data = "Sure! Here it is: {'hello': 'world', /* done */ }";
+/// let data: Vec<Dummy> = find_json(data).unwrap();
+/// assert_eq!(data[0].hello, "world");
+/// ```
+///
+/// It closes values cut off by the token limit.
+///
+/// ```
+/// use llm_chain::parsing::find_json;
+/// let data = "\u{60}``json\n{\"items\": [1, 2,";
+/// let data: Vec<serde_json::Value> = find_json(data).unwrap();
+/// assert_eq!(data[0]["items"], serde_json::json!([1, 2]));
+/// ```
+pub fn find_json<T: DeserializeOwned>(text: &str) -> Result<Vec<T>, ExtractionError> {
+    let mut current_error = ExtractionErrorImpl::NoneFound;
+    let mut found = Vec::new();
+    for (lang, code_block) in code_blocks(text) {
+        if matches!(
+            lang.as_str(),
+            "json" | "json5" | "jsonc" | "javascript" | "js" | ""
+        ) {
+            match extract_json(&code_block) {
+                Ok(o) => found.push(o),
+                Err(e) => {
+                    current_error = ExtractionErrorImpl::most_representative(current_error, e)
+                }
+            }
+        }
+    }
+    if found.is_empty() {
+        for span in json_spans(text) {
+            match extract_json(span) {
+                Ok(o) => found.push(o),
+                Err(e) => {
+                    current_error = ExtractionErrorImpl::most_representative(current_error, e)
+                }
+            }
+        }
+    }
+    if !found.is_empty() {
+        Ok(found)
+    } else {
+        Err(current_error.into())
+    }
+}
+
+/// Returns true if `line` looks like the first line of a YAML document rather than prose: a
+/// `key:` line, a list item or a document marker.
+pub(crate) fn starts_yaml(line: &str) -> bool {
+    let line = line.trim();
+    if line.starts_with("- ") || line == "---" {
+        return true;
+    }
+    match line.split_once(':') {
+        Some((key, _)) => {
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        }
+        None => false,
+    }
+}
+
+/// Parses a whole answer as YAML, accepting only an object or a list, as almost any prose parses
+/// as a YAML string.
+fn extract_structured_yaml<T: DeserializeOwned>(text: &str) -> Result<T, ExtractionErrorImpl> {
+    let yaml: Value = serde_yaml::from_str(text)?;
+    if !yaml.is_mapping() && !yaml.is_sequence() {
+        return Err(ExtractionErrorImpl::NotStructured);
+    }
+    serde_yaml::from_value(yaml).map_err(ExtractionErrorImpl::YamlFoundButFormatWrong)
+}
+
+/// Returns true if `text` is a single line of prose introducing strict JSON, like
+/// `Sure: {"a": 1}`, which would otherwise parse as a YAML object keyed by the prose.
+fn introduces_json(text: &str) -> bool {
+    let text = text.trim();
+    !text.contains('\n')
+        && json_spans(text)
+            .first()
+            .is_some_and(|span| serde_json::from_str::<serde_json::Value>(span).is_ok())
+}
+
+/// Finds the structured value in an answer that may wrap it in prose.
+///
+/// The code blocks of the answer are tried first, as YAML, or as JSON repaired like `find_json`
+/// does. Without a code block that parses, the whole text is parsed as YAML if it starts like a
+/// YAML document, unless it is a single line introducing JSON, and otherwise the first JSON object
+/// or array in the prose is used. Outside of code blocks only objects and lists are accepted.
+pub(crate) fn find_yaml_or_json<T: DeserializeOwned>(text: &str) -> Result<T, ExtractionError> {
+    let mut current_error = ExtractionErrorImpl::NoneFound;
+    for (lang, code_block) in code_blocks(text) {
+        let parsed = match lang.as_str() {
+            "yaml" | "yml" => extract_yaml(&code_block),
+            "json" | "json5" | "jsonc" | "javascript" | "js" | "" => extract_json(&code_block)
+                .or_else(|e| {
+                    extract_yaml(&code_block)
+                        .map_err(|e2| ExtractionErrorImpl::most_representative(e, e2))
+                }),
+            _ => continue,
+        };
+        match parsed {
+            Ok(o) => return Ok(o),
+            Err(e) => current_error = ExtractionErrorImpl::most_representative(current_error, e),
+        }
+    }
+    let first_line = text.trim_start().lines().next().unwrap_or_default();
+    if starts_yaml(first_line) && !introduces_json(text) {
+        match extract_structured_yaml(text) {
+            Ok(o) => return Ok(o),
+            Err(e) => current_error = ExtractionErrorImpl::most_representative(current_error, e),
+        }
+    }
+    for span in json_spans(text) {
+        match extract_json(span) {
+            Ok(o) => return Ok(o),
+            Err(e) => current_error = ExtractionErrorImpl::most_representative(current_error, e),
+        }
+    }
+    Err(current_error.into())
+}
+
 /// Extracts labeled text from markdown
 ///
 /// LLMs often generate text that looks something like this
@@ -307,3 +639,38 @@ fn format_value(s: String) -> String {
         .trim_start()
         .to_owned()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_repairs_almost_json() {
+        let text = "{\n  // the name\n  name: 'Bob \"the\" builder', 'tags': ['a', 'b',],\n}";
+        let value: serde_json::Value = serde_json::from_str(&repair_json(text)).unwrap();
+        assert_eq!(
+            value,
+            json!({"name": "Bob \"the\" builder", "tags": ["a", "b"]})
+        );
+
+        let truncated = "{\"answer\": \"it was cut o";
+        let value: serde_json::Value = serde_json::from_str(&repair_json(truncated)).unwrap();
+        assert_eq!(value, json!({"answer": "it was cut o"}));
+    }
+
+    #[test]
+    fn test_find_json_reports_most_representative_error() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Dummy {
+            #[allow(dead_code)]
+            hello: String,
+        }
+        let text = "First {not json at all} then {\"goodbye\": \"world\"}";
+        let err = find_json::<Dummy>(text).unwrap_err();
+        assert!(err.to_string().contains("didn't match the expected format"));
+
+        let values: Vec<serde_json::Value> = find_json("a [1, 2] and {\"b\": 3}").unwrap();
+        assert_eq!(values, vec![json!([1, 2]), json!({"b": 3})]);
+    }
+}
@@ -9,16 +9,18 @@ use crate::Parameters;
 
 /// The `ChatRole` enum represents the role of a chat message sender in a conversation.
 ///
-/// It has four variants:
+/// It has five variants:
 /// - `User`: Represents a message sent by a user.
 /// - `Assistant`: Represents a message sent by an AI assistant.
 /// - `System`: Represents a message sent by a system or service.
+/// - `Tool`: Represents the result of a tool invocation requested by the assistant.
 /// - `Other`: Represents a message sent by any other role, specified by a string.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum ChatRole {
     User,
     Assistant,
     System,
+    Tool,
     Other(String),
 }
 
@@ -41,19 +43,52 @@ impl fmt::Display for ChatRole {
             ChatRole::User => write!(f, "User"),
             ChatRole::Assistant => write!(f, "Assistant"),
             ChatRole::System => write!(f, "System"),
+            ChatRole::Tool => write!(f, "Tool"),
             ChatRole::Other(s) => write!(f, "{}", s),
         }
     }
 }
 
+/// A request by the model to invoke a tool, as returned by backends with native tool calling.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// The identifier of the call, which the tool result must refer back to.
+    pub id: String,
+    /// The name of the tool to invoke.
+    pub name: String,
+    /// The input to the tool, as the JSON text produced by the model.
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// Creates a new tool call.
+    pub fn new<I: Into<String>, N: Into<String>, A: Into<String>>(
+        id: I,
+        name: N,
+        arguments: A,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            name: name.into(),
+            arguments: arguments.into(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// The `ChatMessage` struct represents a chat message.
-/// It has two fields:
+/// It has the following fields:
 /// - `role`: The role of the message sender.
 /// - `body`: The body of the message.
+/// - `tool_calls`: The tools the assistant asked to invoke, if any.
+/// - `tool_call_id`: For tool messages, the id of the call this message is the result of.
 pub struct ChatMessage<Body> {
     role: ChatRole,
     body: Body,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
 }
 
 impl<Body> ChatMessage<Body> {
@@ -63,7 +98,12 @@ impl<Body> ChatMessage<Body> {
     /// * `role` - The role of the message sender.
     /// * `body` - The body of the message.
     pub fn new(role: ChatRole, body: Body) -> Self {
-        Self { role, body }
+        Self {
+            role,
+            body,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
     }
 
     /// Creates a new chat message with the role of `Assistant`.
@@ -117,6 +157,36 @@ impl<Body> ChatMessage<Body> {
         Self::new(ChatRole::System, body)
     }
 
+    /// Creates a new chat message with the role of `Tool`, holding the result of a tool call.
+    ///
+    /// # Arguments
+    /// * `tool_call_id` - The id of the `ToolCall` this message answers.
+    /// * `body` - The output of the tool.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use llm_chain::prompt::{ChatMessage, ChatRole};
+    /// let msg = ChatMessage::tool_result("call_1", "stdout: hello");
+    ///
+    /// assert_eq!(msg.role(), &ChatRole::Tool);
+    /// assert_eq!(msg.tool_call_id(), Some("call_1"));
+    /// ```
+    pub fn tool_result<I: Into<String>>(tool_call_id: I, body: Body) -> Self {
+        let mut msg = Self::new(ChatRole::Tool, body);
+        msg.tool_call_id = Some(tool_call_id.into());
+        msg
+    }
+
+    /// Returns the message with the given tool calls attached.
+    ///
+    /// # Arguments
+    /// * `tool_calls` - The tools the assistant asks to invoke.
+    pub fn with_tool_calls(mut self, tool_calls: Vec<ToolCall>) -> Self {
+        self.tool_calls = tool_calls;
+        self
+    }
+
     /// Maps the body of the chat message using the provided function `f`.
     ///
     /// # Arguments
@@ -136,6 +206,8 @@ impl<Body> ChatMessage<Body> {
         ChatMessage {
             role,
             body: f(&self.body),
+            tool_calls: self.tool_calls.clone(),
+            tool_call_id: self.tool_call_id.clone(),
         }
     }
 
@@ -147,7 +219,12 @@ impl<Body> ChatMessage<Body> {
     pub fn try_map<U, E, F: Fn(&Body) -> Result<U, E>>(&self, f: F) -> Result<ChatMessage<U>, E> {
         let body = f(&self.body)?;
         let role = self.role.clone();
-        Ok(ChatMessage { role, body })
+        Ok(ChatMessage {
+            role,
+            body,
+            tool_calls: self.tool_calls.clone(),
+            tool_call_id: self.tool_call_id.clone(),
+        })
     }
 
     /// Returns a reference to the role of the message sender.
@@ -159,6 +236,16 @@ impl<Body> ChatMessage<Body> {
     pub fn body(&self) -> &Body {
         &self.body
     }
+
+    /// Returns the tool calls requested in this message.
+    pub fn tool_calls(&self) -> &[ToolCall] {
+        &self.tool_calls
+    }
+
+    /// Returns the id of the tool call this message is the result of, if any.
+    pub fn tool_call_id(&self) -> Option<&str> {
+        self.tool_call_id.as_deref()
+    }
 }
 
 impl<T: fmt::Display> fmt::Display for ChatMessage<T> {
@@ -249,6 +336,11 @@ impl<Body> ChatMessageCollection<Body> {
         self.messages.len()
     }
 
+    /// Returns a reference to the last message in the collection, or `None` if the collection is empty.
+    pub fn last_message(&self) -> Option<&ChatMessage<Body>> {
+        self.messages.back()
+    }
+
     /// Gets the body of the last message in the collection
     pub(crate) fn extract_last_body(&self) -> Option<&Body> {
         self.messages.back().map(|x| &x.body)
@@ -535,4 +627,17 @@ mod tests {
             "Hi there! (mapped)"
         );
     }
+
+    #[test]
+    fn test_map_keeps_tool_calls() {
+        let msg = ChatMessage::assistant("")
+            .with_tool_calls(vec![ToolCall::new("call_1", "BashTool", "{}")]);
+        let mapped_msg = msg.map(|body| body.to_string());
+        assert_eq!(mapped_msg.tool_calls(), msg.tool_calls());
+
+        let result = ChatMessage::tool_result("call_1", "ok");
+        let mapped_result = result.map(|body| body.to_uppercase());
+        assert_eq!(mapped_result.tool_call_id(), Some("call_1"));
+        assert_eq!(mapped_result.role(), &ChatRole::Tool);
+    }
 }
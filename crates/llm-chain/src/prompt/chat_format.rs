@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+
+use super::string_template::render_with_context;
+use super::{ChatMessage, ChatMessageCollection, ChatRole, Data, StringTemplateError};
+
+/// The way a model expects a conversation to be written, for models that take a single string
+/// rather than a list of messages, such as the models run by llm-chain-llama and llm-chain-local.
+///
+/// A format renders the messages of a chat, defines the answer prefix that makes the model answer
+/// as the assistant, and the stop sequences that end its answer. The beginning and end of sequence
+/// tokens are left to the tokenizer. Select a format with `Opt::ChatFormat`; `Plain` is used when
+/// none is set.
+///
+/// # Examples
+/// ```
+/// use llm_chain::prompt::{ChatFormat, ChatMessageCollection};
+/// let chat = ChatMessageCollection::new()
+///     .with_system("You are terse.".to_string())
+///     .with_user("Hi!".to_string());
+/// assert_eq!(
+///     ChatFormat::ChatMl.format(&chat).unwrap(),
+///     "<|im_start|>system\nYou are terse.<|im_end|>\n<|im_start|>user\nHi!<|im_end|>\n<|im_start|>assistant\n"
+/// );
+/// assert_eq!(
+///     ChatFormat::Llama2.format(&chat).unwrap(),
+///     "[INST] <<SYS>>\nYou are terse.\n<</SYS>>\n\nHi! [/INST]"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatFormat {
+    /// `Role: message` lines, answered after `Assistant:`.
+    #[default]
+    Plain,
+    /// Llama 2 chat: `[INST] message [/INST]`, with the system message in a `<<SYS>>` block.
+    Llama2,
+    /// ChatML, used by many fine-tunes: `<|im_start|>role\nmessage<|im_end|>`.
+    ChatMl,
+    /// Alpaca: `### Instruction:` and `### Response:` sections.
+    Alpaca,
+    /// Vicuna 1.1: `USER:` and `ASSISTANT:` turns.
+    Vicuna,
+    /// Mistral instruct: `[INST] message [/INST]`, with the system message before the first user
+    /// message.
+    Mistral,
+    /// A user-defined template.
+    Template(ChatTemplate),
+}
+
+/// A user-defined chat format, written as a template in the Jinja-like syntax of Tera, in the
+/// style of the chat templates of Hugging Face tokenizers.
+///
+/// The template gets `messages`, a list of objects with a `role` (`system`, `user`, `assistant`,
+/// `tool` or a custom name) and a `content`, and `add_generation_prompt`, which is always true as
+/// the template must end with the answer prefix. `bos_token` and `eos_token` are empty strings,
+/// as the tokenizer adds the tokens. Note that Tera concatenates strings with `~`, not `+`.
+///
+/// ```
+/// use llm_chain::prompt::{ChatFormat, ChatMessageCollection, ChatTemplate};
+/// let format = ChatFormat::Template(ChatTemplate::new(
+///     "{% for message in messages %}<{{ message.role }}>{{ message.content }}\n{% endfor %}\
+///      {% if add_generation_prompt %}<assistant>{% endif %}",
+///     vec!["<user>".to_string()],
+/// ));
+/// let chat = ChatMessageCollection::new().with_user("Hi!".to_string());
+/// assert_eq!(format.format(&chat).unwrap(), "<user>Hi!\n<assistant>");
+/// assert_eq!(format.stop_sequences(), vec!["<user>"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatTemplate {
+    /// The template rendering the messages.
+    pub template: String,
+    /// The sequences that end the answer of the model.
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+}
+
+impl ChatTemplate {
+    pub fn new<S: Into<String>>(template: S, stop_sequences: Vec<String>) -> Self {
+        Self {
+            template: template.into(),
+            stop_sequences,
+        }
+    }
+}
+
+/// Returns the name of `role` in lowercase, as chat templates expect.
+fn role_name(role: &ChatRole) -> String {
+    match role {
+        ChatRole::User => "user".to_string(),
+        ChatRole::Assistant => "assistant".to_string(),
+        ChatRole::System => "system".to_string(),
+        ChatRole::Tool => "tool".to_string(),
+        ChatRole::Other(name) => name.clone(),
+    }
+}
+
+/// Returns the body of a message in a user turn. Formats without other roles label tool results
+/// and custom roles with their name.
+fn user_body(message: &ChatMessage<String>) -> String {
+    match message.role() {
+        ChatRole::User => message.body().clone(),
+        role => format!("{}: {}", role, message.body()),
+    }
+}
+
+/// Renders the `[INST]` turns of Llama 2 and Mistral. With `sys_block`, the system message is put
+/// in a `<<SYS>>` block, otherwise it is written before the next user message.
+fn render_inst(chat: &ChatMessageCollection<String>, sys_block: bool) -> String {
+    let mut out = String::new();
+    let mut system: Option<&str> = None;
+    for message in chat.iter() {
+        match message.role() {
+            ChatRole::System => system = Some(message.body()),
+            ChatRole::Assistant => {
+                out.push(' ');
+                out.push_str(message.body());
+            }
+            _ => {
+                if !out.is_empty() {
+                    out.push(' ');
+                }
+                out.push_str("[INST] ");
+                match system.take() {
+                    Some(system) if sys_block => {
+                        out.push_str(&format!("<<SYS>>\n{}\n<</SYS>>\n\n", system))
+                    }
+                    Some(system) => out.push_str(&format!("{}\n\n", system)),
+                    None => {}
+                }
+                out.push_str(&user_body(message));
+                out.push_str(" [/INST]");
+            }
+        }
+    }
+    out
+}
+
+impl ChatFormat {
+    /// Renders the messages of `chat`, without the answer prefix.
+    pub fn render(
+        &self,
+        chat: &ChatMessageCollection<String>,
+    ) -> Result<String, StringTemplateError> {
+        let rendered = match self {
+            ChatFormat::Plain => chat.to_string(),
+            ChatFormat::Llama2 => render_inst(chat, true),
+            ChatFormat::Mistral => render_inst(chat, false),
+            ChatFormat::ChatMl => chat
+                .iter()
+                .map(|message| {
+                    format!(
+                        "<|im_start|>{}\n{}<|im_end|>\n",
+                        role_name(message.role()),
+                        message.body()
+                    )
+                })
+                .collect(),
+            ChatFormat::Alpaca => chat
+                .iter()
+                .map(|message| match message.role() {
+                    ChatRole::System => format!("{}\n\n", message.body()),
+                    ChatRole::Assistant => format!("### Response:\n{}\n\n", message.body()),
+                    _ => format!("### Instruction:\n{}\n\n", user_body(message)),
+                })
+                .collect(),
+            ChatFormat::Vicuna => chat
+                .iter()
+                .map(|message| match message.role() {
+                    ChatRole::System => format!("{}\n\n", message.body()),
+                    ChatRole::Assistant => format!("ASSISTANT: {}\n", message.body()),
+                    _ => format!("USER: {}\n", user_body(message)),
+                })
+                .collect(),
+            ChatFormat::Template(template) => {
+                let messages: Vec<_> = chat
+                    .iter()
+                    .map(|message| {
+                        serde_json::json!({
+                            "role": role_name(message.role()),
+                            "content": message.body(),
+                        })
+                    })
+                    .collect();
+                let mut context = tera::Context::new();
+                context.insert("messages", &messages);
+                context.insert("add_generation_prompt", &true);
+                context.insert("bos_token", "");
+                context.insert("eos_token", "");
+                render_with_context(&template.template, &context)?
+            }
+        };
+        Ok(rendered)
+    }
+
+    /// Returns the text after the messages that starts the answer of the assistant. Templates
+    /// render it themselves, so they have none.
+    pub fn answer_prefix(&self) -> Option<String> {
+        let prefix = match self {
+            ChatFormat::Plain => "Assistant:",
+            ChatFormat::ChatMl => "<|im_start|>assistant\n",
+            ChatFormat::Alpaca => "### Response:\n",
+            ChatFormat::Vicuna => "ASSISTANT:",
+            ChatFormat::Llama2 | ChatFormat::Mistral | ChatFormat::Template(_) => return None,
+        };
+        Some(prefix.to_string())
+    }
+
+    /// Returns the sequences that mark the end of the answer of the assistant.
+    pub fn stop_sequences(&self) -> Vec<String> {
+        let stops: &[&str] = match self {
+            ChatFormat::Plain => &[],
+            ChatFormat::Llama2 | ChatFormat::Mistral => &["[INST]"],
+            ChatFormat::ChatMl => &["<|im_end|>"],
+            ChatFormat::Alpaca => &["### Instruction:"],
+            ChatFormat::Vicuna => &["USER:"],
+            ChatFormat::Template(template) => return template.stop_sequences.clone(),
+        };
+        stops.iter().map(|stop| stop.to_string()).collect()
+    }
+
+    /// Renders the messages of `chat` followed by the answer prefix, ready to be completed.
+    pub fn format(
+        &self,
+        chat: &ChatMessageCollection<String>,
+    ) -> Result<String, StringTemplateError> {
+        let mut text = self.render(chat)?;
+        if let Some(prefix) = self.answer_prefix() {
+            text.push_str(&prefix);
+        }
+        Ok(text)
+    }
+
+    /// Formats a prompt: chats with `format`, while text prompts are used as they are.
+    pub fn format_prompt(&self, prompt: &Data<String>) -> Result<String, StringTemplateError> {
+        match prompt {
+            Data::Chat(chat) => self.format(chat),
+            Data::Text(text) => Ok(text.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> ChatMessageCollection<String> {
+        ChatMessageCollection::new()
+            .with_system("Be brief.".to_string())
+            .with_user("Hi".to_string())
+            .with_assistant("Hello".to_string())
+            .with_user("Bye".to_string())
+    }
+
+    #[test]
+    fn test_builtin_formats() {
+        let chat = conversation();
+        assert_eq!(
+            ChatFormat::Plain.format(&chat).unwrap(),
+            "System: Be brief.\nUser: Hi\nAssistant: Hello\nUser: Bye\nAssistant:"
+        );
+        assert_eq!(
+            ChatFormat::Mistral.format(&chat).unwrap(),
+            "[INST] Be brief.\n\nHi [/INST] Hello [INST] Bye [/INST]"
+        );
+        assert_eq!(
+            ChatFormat::Alpaca.format(&chat).unwrap(),
+            "Be brief.\n\n### Instruction:\nHi\n\n### Response:\nHello\n\n### Instruction:\nBye\n\n### Response:\n"
+        );
+        assert_eq!(
+            ChatFormat::Vicuna.format(&chat).unwrap(),
+            "Be brief.\n\nUSER: Hi\nASSISTANT: Hello\nUSER: Bye\nASSISTANT:"
+        );
+        assert_eq!(ChatFormat::Vicuna.stop_sequences(), vec!["USER:"]);
+
+        let format: ChatFormat = serde_yaml::from_str("chat_ml").unwrap();
+        assert_eq!(format, ChatFormat::ChatMl);
+    }
+}
@@ -2,13 +2,15 @@
 //! Contains the `prompt!` macro, Prompts and PromptTemplates.
 
 mod chat;
+mod chat_format;
 mod model;
 mod serialization;
 mod string_template;
 
-pub use string_template::{StringTemplate, StringTemplateError};
+pub use chat_format::{ChatFormat, ChatTemplate};
+pub use string_template::{StringTemplate, StringTemplateError, TemplateRegistry};
 
-pub use chat::{ChatMessage, ChatMessageCollection, ChatRole};
+pub use chat::{ChatMessage, ChatMessageCollection, ChatRole, ToolCall};
 pub use model::Data;
 
 /// A prompt template.
@@ -138,11 +138,13 @@ impl<T> From<ChatMessage<T>> for Data<T> {
     }
 }
 
+use std::collections::BTreeSet;
+
 use crate::frame::FormatAndExecuteError;
 use crate::output::Output;
 use crate::prompt::{StringTemplate, StringTemplateError};
 use crate::step::Step;
-use crate::traits::Executor;
+use crate::traits::DynExecutor;
 use crate::Parameters;
 
 use super::chat::ChatMessageCollection;
@@ -157,7 +159,7 @@ impl Data<StringTemplate> {
     ///
     /// # Returns
     /// The output of applying the prompt template to the model.
-    pub async fn run<E: Executor>(
+    pub async fn run<E: DynExecutor + ?Sized>(
         &self,
         parameters: &Parameters,
         executor: &E,
@@ -170,4 +172,19 @@ impl Data<StringTemplate> {
     pub fn format(&self, parameters: &Parameters) -> Result<Data<String>, StringTemplateError> {
         self.try_map(|x| x.format(parameters))
     }
+
+    /// Returns the names of the parameters the templates of the prompt need, see
+    /// `StringTemplate::variables`.
+    pub fn variables(&self) -> Result<BTreeSet<String>, StringTemplateError> {
+        let mut variables = BTreeSet::new();
+        match self {
+            Self::Chat(chat) => {
+                for message in chat.iter() {
+                    variables.extend(message.body().variables()?);
+                }
+            }
+            Self::Text(text) => variables.extend(text.variables()?),
+        }
+        Ok(variables)
+    }
 }
@@ -8,6 +8,8 @@ pub enum StringTemplateErrorImpl {
     UnableToLoadFile(String),
     #[error("Unable to parse template: {0}")]
     LegacyTemplateError(String),
+    #[error("The template name {0} is reserved for inline templates")]
+    ReservedName(String),
 }
 
 impl From<std::io::Error> for StringTemplateErrorImpl {
@@ -1,8 +1,13 @@
+mod registry;
 mod tera;
+mod variables;
 
 mod error;
+pub(crate) use self::tera::render_with_context;
 pub use error::StringTemplateError;
 use error::StringTemplateErrorImpl;
+pub use registry::TemplateRegistry;
+use std::collections::BTreeSet;
 use std::fmt;
 mod io;
 
@@ -50,7 +55,7 @@ impl From<StringTemplateImpl> for StringTemplate {
 impl StringTemplate {
     /// Format the template with the given parameters.
     pub fn format(&self, parameters: &Parameters) -> Result<String, error::StringTemplateError> {
-        self.0.format(parameters).map_err(|e| e.into())
+        self.0.format(parameters)
     }
     /// Creates a non-dynmamic prompt template, useful for untrusted inputs.
     pub fn static_string<K: Into<String>>(template: K) -> StringTemplate {
@@ -72,6 +77,25 @@ impl StringTemplate {
         StringTemplateImpl::tera(template.into()).into()
     }
 
+    /// Creates a prompt template that renders the template named `name` in the global
+    /// `TemplateRegistry`. The template is parsed once, when it is added to the registry, rather
+    /// than every time this is formatted.
+    /// # Examples
+    ///
+    /// ```rust
+    /// use llm_chain::prompt::{StringTemplate, TemplateRegistry};
+    /// use llm_chain::Parameters;
+    /// TemplateRegistry::global()
+    ///     .add_template("hello", "Hello {{name}}!")
+    ///     .unwrap();
+    /// let template = StringTemplate::registered("hello");
+    /// let parameters: Parameters = vec![("name", "World")].into();
+    /// assert_eq!(template.format(&parameters).unwrap(), "Hello World!");
+    /// ```
+    pub fn registered<K: Into<String>>(name: K) -> StringTemplate {
+        StringTemplateImpl::registered(name.into()).into()
+    }
+
     /// Creates a prompt template from a file. The file should be a text file containing the template as a tera template.
     /// # Examples
     /// ```no_run
@@ -82,6 +106,26 @@ impl StringTemplate {
         io::read_prompt_template_file(path)
     }
 
+    /// Returns the names of the parameters the template needs to be formatted.
+    ///
+    /// Parameters the template can do without are left out: those only read with a `default`
+    /// filter, as a condition, in a test such as `is defined`, or inside an `if` branch whose
+    /// condition proves they are set. Loop variables and variables assigned with `set` aren't
+    /// parameters either. The parameters of the templates included from the `TemplateRegistry`
+    /// are part of the result, and `user.name` is reported as `user`.
+    /// # Examples
+    /// ```
+    /// use llm_chain::prompt::StringTemplate;
+    /// let template = StringTemplate::tera(
+    ///     "{% for item in items %}{{ item }}{% endfor %} for {{ user.name }}{% if note %}: {{ note }}{% endif %}",
+    /// );
+    /// let variables: Vec<String> = template.variables().unwrap().into_iter().collect();
+    /// assert_eq!(variables, vec!["items", "user"]);
+    /// ```
+    pub fn variables(&self) -> Result<BTreeSet<String>, StringTemplateError> {
+        self.0.variables()
+    }
+
     /// Combines two prompt templates into one.
     /// This is useful for creating a prompt template from multiple sources.
     /// # Examples
@@ -111,14 +155,16 @@ impl fmt::Display for StringTemplate {
 enum StringTemplateImpl {
     Static(String),
     Tera(String),
+    Registered(String),
     Combined(Vec<StringTemplateImpl>),
 }
 
 impl StringTemplateImpl {
-    pub fn format(&self, parameters: &Parameters) -> Result<String, StringTemplateErrorImpl> {
+    pub fn format(&self, parameters: &Parameters) -> Result<String, StringTemplateError> {
         match self {
             Self::Static(template) => Ok(template.clone()),
-            Self::Tera(template) => tera::render(template, parameters).map_err(|e| e.into()),
+            Self::Tera(template) => tera::render(template, parameters),
+            Self::Registered(name) => tera::render_registered(name, parameters),
             Self::Combined(templates) => {
                 let mut result = String::new();
                 for template in templates {
@@ -130,6 +176,21 @@ impl StringTemplateImpl {
         }
     }
 
+    pub fn variables(&self) -> Result<BTreeSet<String>, StringTemplateError> {
+        match self {
+            Self::Static(_) => Ok(BTreeSet::new()),
+            Self::Tera(template) => TemplateRegistry::global().variables_str(template),
+            Self::Registered(name) => TemplateRegistry::global().variables(name),
+            Self::Combined(templates) => {
+                let mut variables = BTreeSet::new();
+                for template in templates {
+                    variables.extend(template.variables()?);
+                }
+                Ok(variables)
+            }
+        }
+    }
+
     pub fn static_string(template: String) -> Self {
         Self::Static(template)
     }
@@ -138,6 +199,10 @@ impl StringTemplateImpl {
         Self::Tera(template)
     }
 
+    pub fn registered(name: String) -> Self {
+        Self::Registered(name)
+    }
+
     pub fn combine(templates: Vec<Self>) -> Self {
         Self::Combined(templates)
     }
@@ -148,6 +213,7 @@ impl fmt::Display for StringTemplateImpl {
         match self {
             Self::Static(s) => write!(f, "{}", s),
             Self::Tera(template) => write!(f, "{}", template),
+            Self::Registered(name) => write!(f, "{{% include \"{}\" %}}", name),
             Self::Combined(templates) => {
                 for template in templates {
                     write!(f, "{}", template)?;
@@ -0,0 +1,353 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+
+use lazy_static::lazy_static;
+use tera::{Template, Tera};
+
+use super::error::StringTemplateErrorImpl;
+use super::variables::VariableCollector;
+use super::StringTemplateError;
+use crate::Parameters;
+
+lazy_static! {
+    static ref GLOBAL: TemplateRegistry = TemplateRegistry::new();
+}
+
+/// The prefix of the names inline templates are added under, hidden from `names` and reserved.
+const INLINE_PREFIX: &str = "__inline/";
+
+/// The number of inline templates kept parsed before the least recently used one is dropped.
+const INLINE_CAPACITY: usize = 64;
+
+/// A set of named templates, parsed once when they are added.
+///
+/// Templates in a registry can `{% include %}` each other, `{% import %}` each other's macros and
+/// `{% extends %}` each other. The templates of a `StringTemplate` created with
+/// `StringTemplate::registered` are looked up by name in the global registry, so formatting them
+/// many times, like the map step of a `map_reduce` chain does, doesn't parse them again. Inline
+/// templates created with `StringTemplate::tera` can use the templates of the global registry as
+/// well. The last 64 of those that do are kept parsed, while the others are parsed every time
+/// they are formatted, without locking the registry. Template names starting with `__inline/`
+/// are reserved for them.
+///
+/// # Examples
+/// ```
+/// use llm_chain::prompt::{StringTemplate, TemplateRegistry};
+/// use llm_chain::Parameters;
+/// let registry = TemplateRegistry::global();
+/// registry
+///     .add_templates(vec![
+///         ("macros", "{% macro quote(text) %}\"{{ text }}\"{% endmacro quote %}"),
+///         ("summarize", "{% import \"macros\" as m %}Summarize {{ m::quote(text=text) }}."),
+///     ])
+///     .unwrap();
+/// let template = StringTemplate::registered("summarize");
+/// let parameters: Parameters = "the report".into();
+/// assert_eq!(template.format(&parameters).unwrap(), "Summarize \"the report\".");
+/// ```
+pub struct TemplateRegistry {
+    tera: RwLock<Tera>,
+    /// The inline templates using other templates that are kept parsed.
+    inline: Mutex<InlineTemplates>,
+}
+
+#[derive(Default)]
+struct InlineTemplates {
+    /// The names the templates were added under, by source.
+    names: HashMap<String, String>,
+    /// The sources, from least to most recently used.
+    order: VecDeque<String>,
+    next_id: usize,
+}
+
+impl InlineTemplates {
+    fn touch(&mut self, source: &str) {
+        if let Some(pos) = self.order.iter().position(|s| s == source) {
+            let source = self.order.remove(pos).unwrap();
+            self.order.push_back(source);
+        }
+    }
+}
+
+impl TemplateRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        let mut tera = Tera::default();
+        // Prompts aren't HTML, whatever the extension of their file.
+        tera.autoescape_on(vec![]);
+        Self {
+            tera: RwLock::new(tera),
+            inline: Mutex::new(InlineTemplates::default()),
+        }
+    }
+
+    /// Returns the global registry, used by `StringTemplate::registered` and inline templates.
+    pub fn global() -> &'static TemplateRegistry {
+        &GLOBAL
+    }
+
+    /// Creates a registry holding the templates in the directory at `path`, see `load_dir`.
+    pub fn from_dir<P: AsRef<Path>>(path: P) -> Result<Self, StringTemplateError> {
+        let registry = Self::new();
+        registry.load_dir(path)?;
+        Ok(registry)
+    }
+
+    /// Adds a template named `name`, replacing any template with the same name.
+    pub fn add_template(&self, name: &str, source: &str) -> Result<(), StringTemplateError> {
+        self.add_templates(vec![(name, source)])
+    }
+
+    /// Adds several templates at once, which may refer to each other in any order.
+    pub fn add_templates<N, S>(&self, templates: Vec<(N, S)>) -> Result<(), StringTemplateError>
+    where
+        N: AsRef<str>,
+        S: AsRef<str>,
+    {
+        if let Some((name, _)) = templates
+            .iter()
+            .find(|(name, _)| name.as_ref().starts_with(INLINE_PREFIX))
+        {
+            return Err(StringTemplateErrorImpl::ReservedName(name.as_ref().to_string()).into());
+        }
+        let mut tera = self.tera.write().unwrap();
+        tera.add_raw_templates(templates)
+            .map_err(StringTemplateErrorImpl::from)?;
+        Ok(())
+    }
+
+    /// Adds every file in the directory at `path` and its subdirectories, except hidden files.
+    /// A template is named after its path relative to the directory, with `/` as separator, such
+    /// as `summarize/map.txt`.
+    pub fn load_dir<P: AsRef<Path>>(&self, path: P) -> Result<(), StringTemplateError> {
+        let mut templates = Vec::new();
+        read_dir(path.as_ref(), "", &mut templates).map_err(StringTemplateErrorImpl::from)?;
+        self.add_templates(templates)
+    }
+
+    /// Returns true if a template named `name` was added.
+    pub fn contains(&self, name: &str) -> bool {
+        !name.starts_with(INLINE_PREFIX)
+            && self
+                .tera
+                .read()
+                .unwrap()
+                .get_template_names()
+                .any(|n| n == name)
+    }
+
+    /// Returns the names of the templates, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let tera = self.tera.read().unwrap();
+        let mut names: Vec<String> = tera
+            .get_template_names()
+            .filter(|name| !name.starts_with(INLINE_PREFIX))
+            .map(String::from)
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Renders the template named `name` with `parameters`.
+    pub fn render(
+        &self,
+        name: &str,
+        parameters: &Parameters,
+    ) -> Result<String, StringTemplateError> {
+        let tera = self.tera.read().unwrap();
+        let rendered = tera
+            .render(name, &parameters.to_tera())
+            .map_err(StringTemplateErrorImpl::from)?;
+        Ok(rendered)
+    }
+
+    /// Renders the inline template `source` with `parameters`. `source` can use the templates of
+    /// the registry, but isn't one of its `names`.
+    pub fn render_str(
+        &self,
+        source: &str,
+        parameters: &Parameters,
+    ) -> Result<String, StringTemplateError> {
+        if !uses_other_templates(source) {
+            let rendered = Tera::one_off(source, &parameters.to_tera(), false)
+                .map_err(StringTemplateErrorImpl::from)?;
+            return Ok(rendered);
+        }
+        let mut inline = self.inline.lock().unwrap();
+        let name = match inline.names.get(source) {
+            Some(name) => {
+                let name = name.clone();
+                inline.touch(source);
+                name
+            }
+            None => {
+                let name = format!("{}{}", INLINE_PREFIX, inline.next_id);
+                let mut tera = self.tera.write().unwrap();
+                if let Err(err) = tera.add_raw_template(&name, source) {
+                    // A template extending a missing parent is added before the error.
+                    tera.templates.remove(&name);
+                    return Err(StringTemplateErrorImpl::from(err).into());
+                }
+                inline.next_id += 1;
+                inline.names.insert(source.to_string(), name.clone());
+                inline.order.push_back(source.to_string());
+                while inline.order.len() > INLINE_CAPACITY {
+                    if let Some(evicted) = inline.order.pop_front() {
+                        if let Some(evicted) = inline.names.remove(&evicted) {
+                            tera.templates.remove(&evicted);
+                        }
+                    }
+                }
+                name
+            }
+        };
+        // The template can't be dropped before it is rendered, as that needs the write lock.
+        let tera = self.tera.read().unwrap();
+        drop(inline);
+        let rendered = tera
+            .render(&name, &parameters.to_tera())
+            .map_err(StringTemplateErrorImpl::from)?;
+        Ok(rendered)
+    }
+
+    /// Returns the parameters the template named `name` needs, including those of the templates
+    /// it includes or extends. See `StringTemplate::variables`.
+    pub fn variables(&self, name: &str) -> Result<BTreeSet<String>, StringTemplateError> {
+        let tera = self.tera.read().unwrap();
+        let mut collector = VariableCollector::new(&tera);
+        collector
+            .template_named(name)
+            .map_err(StringTemplateErrorImpl::from)?;
+        Ok(collector.into_variables())
+    }
+
+    /// Returns the parameters the inline template `source` needs, see `render_str`.
+    pub(crate) fn variables_str(
+        &self,
+        source: &str,
+    ) -> Result<BTreeSet<String>, StringTemplateError> {
+        let template =
+            Template::new("__variables", None, source).map_err(StringTemplateErrorImpl::from)?;
+        let tera = self.tera.read().unwrap();
+        let mut collector = VariableCollector::new(&tera);
+        collector.template(&template);
+        Ok(collector.into_variables())
+    }
+}
+
+impl Default for TemplateRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns true if `source` has an `include`, `import` or `extends` tag.
+fn uses_other_templates(source: &str) -> bool {
+    source.split("{%").skip(1).any(|tag| {
+        let tag = tag.strip_prefix('-').unwrap_or(tag).trim_start();
+        ["include", "import", "extends"].iter().any(|name| {
+            tag.strip_prefix(name)
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_whitespace() || c == '"'))
+        })
+    })
+}
+
+/// Reads the files under `dir` into `templates`, prefixing their names with `prefix`.
+fn read_dir(
+    dir: &Path,
+    prefix: &str,
+    templates: &mut Vec<(String, String)>,
+) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let name = format!("{}{}", prefix, file_name);
+        if entry.file_type()?.is_dir() {
+            read_dir(&entry.path(), &format!("{}/", name), templates)?;
+        } else {
+            templates.push((name, fs::read_to_string(entry.path())?));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::StringTemplate;
+
+    #[test]
+    fn test_load_dir_with_includes() {
+        let dir = std::env::temp_dir().join(format!("llm-chain-templates-{}", std::process::id()));
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(dir.join("shared/persona.txt"), "You are {{ name }}.").unwrap();
+        fs::write(
+            dir.join("greet.txt"),
+            "{% include \"shared/persona.txt\" %} Greet {{ text }}.",
+        )
+        .unwrap();
+        let registry = TemplateRegistry::from_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(registry.names(), vec!["greet.txt", "shared/persona.txt"]);
+        let parameters = Parameters::new_with_text("Bob").with("name", "a butler");
+        assert_eq!(
+            registry.render("greet.txt", &parameters).unwrap(),
+            "You are a butler. Greet Bob."
+        );
+        assert_eq!(
+            registry
+                .render_str("{% include \"shared/persona.txt\" %}", &parameters)
+                .unwrap(),
+            "You are a butler."
+        );
+        // The inline template is kept for the next render, but isn't listed.
+        assert_eq!(registry.inline.lock().unwrap().names.len(), 1);
+        assert_eq!(registry.names().len(), 2);
+        assert!(registry.render("missing.txt", &parameters).is_err());
+        // The global registry doesn't know about this one.
+        assert!(StringTemplate::registered("greet.txt")
+            .format(&parameters)
+            .is_err());
+    }
+
+    #[test]
+    fn test_inline_templates_are_bounded() {
+        let registry = TemplateRegistry::new();
+        registry
+            .add_template("persona", "You are {{ name }}.")
+            .unwrap();
+        let parameters = Parameters::new().with("name", "a butler");
+        for i in 0..INLINE_CAPACITY + 10 {
+            let source = format!("{{% include \"persona\" %}} ({})", i);
+            assert_eq!(
+                registry.render_str(&source, &parameters).unwrap(),
+                format!("You are a butler. ({})", i)
+            );
+        }
+        assert_eq!(registry.inline.lock().unwrap().names.len(), INLINE_CAPACITY);
+        assert_eq!(
+            registry.tera.read().unwrap().templates.len(),
+            INLINE_CAPACITY + 1
+        );
+
+        // Prose mentioning the tag names isn't kept.
+        registry
+            .render_str(
+                "{% if name %}Important, included: {{ name }}{% endif %}",
+                &parameters,
+            )
+            .unwrap();
+        assert_eq!(registry.inline.lock().unwrap().names.len(), INLINE_CAPACITY);
+        assert!(uses_other_templates("{%- import \"macros\" as m -%}"));
+        assert!(!uses_other_templates("{% set imported = true %}"));
+
+        assert!(registry.add_template("__inline/0", "hijacked").is_err());
+    }
+}
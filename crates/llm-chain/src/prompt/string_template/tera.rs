@@ -1,9 +1,27 @@
-use tera::Tera;
+use tera::{Context, Tera};
 
+use super::registry::TemplateRegistry;
+use super::StringTemplateError;
 use crate::Parameters;
 
 // Renders the given `template` using the `context` provided as `Parameters`.
+// The template can use the templates of the global registry, if any were added.
 // Returns a `Result` with a `String` containing the rendered template or an error.
-pub fn render(template: &str, context: &Parameters) -> Result<String, tera::Error> {
-    Tera::one_off(template, &context.to_tera(), false)
+pub fn render(template: &str, context: &Parameters) -> Result<String, StringTemplateError> {
+    TemplateRegistry::global().render_str(template, context)
+}
+
+// Renders the template named `name` of the global registry.
+pub fn render_registered(name: &str, context: &Parameters) -> Result<String, StringTemplateError> {
+    TemplateRegistry::global().render(name, context)
+}
+
+// Renders `template` with a `context` built by the caller rather than from `Parameters`.
+pub(crate) fn render_with_context(
+    template: &str,
+    context: &Context,
+) -> Result<String, StringTemplateError> {
+    let rendered =
+        Tera::one_off(template, context, false).map_err(super::StringTemplateErrorImpl::from)?;
+    Ok(rendered)
 }
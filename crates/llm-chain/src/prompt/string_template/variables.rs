@@ -0,0 +1,233 @@
+use std::collections::{BTreeSet, HashSet};
+
+use tera::ast::{Expr, ExprVal, LogicOperator, Node};
+use tera::{Template, Tera};
+
+/// Collects the variables a template reads from its parameters.
+///
+/// Variables that may be missing without failing the render are left out: those only read with a
+/// `default` filter, as a condition, or in a test such as `is defined`, and those read inside an
+/// `if` branch whose condition proves they are set. Loop variables, variables assigned with `set`
+/// and macro arguments aren't parameters either. Included and parent templates are looked up in
+/// `tera`.
+pub(super) struct VariableCollector<'a> {
+    tera: &'a Tera,
+    visited: HashSet<String>,
+    bound: Vec<String>,
+    variables: BTreeSet<String>,
+}
+
+impl<'a> VariableCollector<'a> {
+    pub fn new(tera: &'a Tera) -> Self {
+        Self {
+            tera,
+            visited: HashSet::new(),
+            bound: Vec::new(),
+            variables: BTreeSet::new(),
+        }
+    }
+
+    pub fn into_variables(self) -> BTreeSet<String> {
+        self.variables
+    }
+
+    /// Adds the variables of the template named `name`, once.
+    pub fn template_named(&mut self, name: &str) -> Result<(), tera::Error> {
+        if !self.visited.insert(name.to_string()) {
+            return Ok(());
+        }
+        let template = self.tera.get_template(name)?;
+        self.template(template);
+        Ok(())
+    }
+
+    pub fn template(&mut self, template: &Template) {
+        self.nodes(&template.ast);
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        let scope = self.bound.len();
+        for node in nodes {
+            self.node(node);
+        }
+        self.bound.truncate(scope);
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::VariableBlock(_, expr) => self.expr(expr),
+            Node::Extends(_, name) => {
+                // A missing parent fails when the template is added, so it's always found.
+                let _ = self.template_named(name);
+            }
+            Node::Include(_, names, _) => {
+                for name in names {
+                    // Missing includes are reported when rendering, not here.
+                    let _ = self.template_named(name);
+                }
+            }
+            Node::Set(_, set) => {
+                self.expr(&set.value);
+                self.bound.push(set.key.clone());
+            }
+            Node::FilterSection(_, section, _) => {
+                section.filter.args.values().for_each(|arg| self.expr(arg));
+                self.nodes(&section.body);
+            }
+            Node::Block(_, block, _) => self.nodes(&block.body),
+            Node::Forloop(_, forloop, _) => {
+                self.expr(&forloop.container);
+                let scope = self.bound.len();
+                self.bound.push("loop".to_string());
+                self.bound.push(forloop.value.clone());
+                self.bound.extend(forloop.key.clone());
+                self.nodes(&forloop.body);
+                self.bound.truncate(scope);
+                if let Some(body) = &forloop.empty_body {
+                    self.nodes(body);
+                }
+            }
+            Node::If(condition, _) => {
+                // A branch only runs when its condition holds, which may prove variables are set.
+                // Earlier conditions failing proves nothing, so `elif` and `else` bodies get
+                // nothing from them.
+                for (_, expr, body) in &condition.conditions {
+                    self.condition(expr);
+                    let scope = self.bound.len();
+                    proven_variables(expr, &mut self.bound);
+                    self.nodes(body);
+                    self.bound.truncate(scope);
+                }
+                if let Some((_, body)) = &condition.otherwise {
+                    self.nodes(body);
+                }
+            }
+            // Macros only see their arguments.
+            _ => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        for filter in &expr.filters {
+            filter.args.values().for_each(|arg| self.expr(arg));
+        }
+        if expr.has_default_filter() {
+            return;
+        }
+        self.value(&expr.val);
+    }
+
+    /// Reads an expression evaluated as a boolean, where a missing variable is false.
+    fn condition(&mut self, expr: &Expr) {
+        match &expr.val {
+            ExprVal::Ident(_) if expr.filters.is_empty() => {}
+            ExprVal::Logic(logic)
+                if matches!(logic.operator, LogicOperator::And | LogicOperator::Or) =>
+            {
+                self.condition(&logic.lhs);
+                self.condition(&logic.rhs);
+            }
+            _ => self.expr(expr),
+        }
+    }
+
+    fn value(&mut self, value: &ExprVal) {
+        match value {
+            ExprVal::Ident(ident) => self.ident(ident),
+            ExprVal::Math(math) => {
+                self.expr(&math.lhs);
+                self.expr(&math.rhs);
+            }
+            ExprVal::Logic(logic) => match logic.operator {
+                LogicOperator::And | LogicOperator::Or => {
+                    self.condition(&logic.lhs);
+                    self.condition(&logic.rhs);
+                }
+                _ => {
+                    self.expr(&logic.lhs);
+                    self.expr(&logic.rhs);
+                }
+            },
+            ExprVal::Test(test) => test.args.iter().for_each(|arg| self.expr(arg)),
+            ExprVal::MacroCall(call) => call.args.values().for_each(|arg| self.expr(arg)),
+            ExprVal::FunctionCall(call) => call.args.values().for_each(|arg| self.expr(arg)),
+            ExprVal::Array(items) => items.iter().for_each(|item| self.expr(item)),
+            ExprVal::StringConcat(concat) => concat.values.iter().for_each(|v| self.value(v)),
+            ExprVal::In(test) => {
+                self.expr(&test.lhs);
+                self.expr(&test.rhs);
+            }
+            ExprVal::String(_) | ExprVal::Int(_) | ExprVal::Float(_) | ExprVal::Bool(_) => {}
+        }
+    }
+
+    fn ident(&mut self, ident: &str) {
+        let root = root_name(ident);
+        if root == "__tera_context" || self.bound.iter().any(|bound| bound == root) {
+            return;
+        }
+        self.variables.insert(root.to_string());
+    }
+}
+
+/// Returns the parameter an identifier reads: `user.name` and `items[0]` read `user` and `items`.
+fn root_name(ident: &str) -> &str {
+    ident.split(['.', '[']).next().unwrap_or(ident)
+}
+
+/// Adds the variables that are set whenever `expr` holds as a condition: `note` in `note`,
+/// `note is defined` and `note and other`. Nothing is proven through `or` or `not`.
+fn proven_variables(expr: &Expr, variables: &mut Vec<String>) {
+    if expr.negated {
+        return;
+    }
+    match &expr.val {
+        ExprVal::Ident(ident) if expr.filters.is_empty() => {
+            variables.push(root_name(ident).to_string())
+        }
+        ExprVal::Test(test) if !test.negated && test.name == "defined" => {
+            variables.push(root_name(&test.ident).to_string())
+        }
+        ExprVal::Logic(logic) if logic.operator == LogicOperator::And => {
+            proven_variables(&logic.lhs, variables);
+            proven_variables(&logic.rhs, variables);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prompt::StringTemplate;
+
+    fn variables(template: &str) -> Vec<String> {
+        StringTemplate::tera(template)
+            .variables()
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_if_only_binds_what_its_condition_proves() {
+        assert!(variables("{% if a and b is defined %}{{ a }}{{ b }}{% endif %}").is_empty());
+        assert_eq!(variables("{% if a or b %}{{ a }}{% endif %}"), vec!["a"]);
+        assert_eq!(variables("{% if not a %}{{ a }}{% endif %}"), vec!["a"]);
+        assert_eq!(
+            variables("{% if a is not defined %}{{ a }}{% endif %}"),
+            vec!["a"]
+        );
+        assert_eq!(
+            variables("{% if note %}{{ note }}{% else %}{{ note }}{% endif %}"),
+            vec!["note"]
+        );
+        assert_eq!(
+            variables("{% if not note %}none{% else %}{{ note }}{% endif %}"),
+            vec!["note"]
+        );
+        assert_eq!(
+            variables("{% if a %}{% elif b %}{{ a }}{{ b }}{% endif %}"),
+            vec!["a"]
+        );
+    }
+}
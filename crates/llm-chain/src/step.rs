@@ -5,10 +5,14 @@ use crate::frame::{FormatAndExecuteError, Frame};
 use crate::options::Opt;
 use crate::options::Options;
 use crate::output::Output;
+use crate::parsing::{find_yaml_or_json, ExtractionError};
 use crate::prompt::{Prompt, StringTemplateError};
-use crate::traits::Executor;
+use crate::structured::{self, StructuredOutputError, TypedOutput};
+use crate::tools::Describe;
+use crate::traits::DynExecutor;
 use crate::{chains::sequential, prompt, Parameters};
 
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 #[derive(derive_builder::Builder, Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +20,10 @@ use serde::Serialize;
 pub struct Step {
     pub(crate) prompt: prompt::PromptTemplate,
     pub(crate) options: Options,
+    /// The parameter the parsed answer is stored under when the step runs in a sequential chain.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) output_key: Option<String>,
 }
 
 impl Step {
@@ -23,16 +31,21 @@ impl Step {
         Self {
             prompt,
             options: Options::empty().clone(),
+            output_key: None,
         }
     }
     pub fn for_prompt_with_streaming(prompt: prompt::PromptTemplate) -> Self {
         let mut options = Options::builder();
         options.add_option(Opt::Stream(true));
         let options = options.build();
-        Self { prompt, options }
+        Self::for_prompt_and_options(prompt, options)
     }
     pub fn for_prompt_and_options(prompt: prompt::PromptTemplate, options: Options) -> Self {
-        Self { prompt, options }
+        Self {
+            prompt,
+            options,
+            output_key: None,
+        }
     }
     pub fn prompt(&self) -> &prompt::PromptTemplate {
         &self.prompt
@@ -41,6 +54,35 @@ impl Step {
         &self.options
     }
 
+    /// Makes a sequential chain parse the answer of this step as YAML or JSON and store the value
+    /// under `key`, as well as the text under `text`, so that later prompts can read its fields.
+    pub fn with_output_key<K: Into<String>>(mut self, key: K) -> Self {
+        self.output_key = Some(key.into());
+        self
+    }
+
+    pub fn output_key(&self) -> Option<&str> {
+        self.output_key.as_deref()
+    }
+
+    /// Returns `parameters` with the answer of this step, `body`, stored under `text` and, parsed,
+    /// under the output key.
+    pub(crate) fn output_parameters(
+        &self,
+        parameters: &Parameters,
+        body: String,
+    ) -> Result<Parameters, ExtractionError> {
+        let value = match &self.output_key {
+            Some(key) => Some((key, find_yaml_or_json::<serde_json::Value>(&body)?)),
+            None => None,
+        };
+        let parameters = parameters.with_text(body);
+        Ok(match value {
+            Some((key, value)) => parameters.with_value(key.clone(), value),
+            None => parameters,
+        })
+    }
+
     /// Converts this step into a sequential chain with a single step.
     ///
     /// # Returns
@@ -53,6 +95,42 @@ impl Step {
         crate::chains::sequential::Chain::of_one(self)
     }
 
+    /// Returns the names of the parameters the prompt needs that `parameters` doesn't set.
+    pub fn missing_parameters(
+        &self,
+        parameters: &Parameters,
+    ) -> Result<Vec<String>, StringTemplateError> {
+        Ok(self
+            .prompt
+            .variables()?
+            .into_iter()
+            .filter(|name| parameters.get(name).is_none())
+            .collect())
+    }
+
+    /// Checks that `parameters` sets everything the prompt needs, for the step at `index` of a
+    /// chain.
+    pub(crate) fn validate(
+        &self,
+        index: usize,
+        parameters: &Parameters,
+    ) -> Result<(), ValidationError> {
+        let missing = self.missing_parameters(parameters).map_err(|source| {
+            ValidationError::InvalidTemplate {
+                step: index,
+                source,
+            }
+        })?;
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError::MissingParameters {
+                step: index,
+                missing,
+            })
+        }
+    }
+
     /// Formats the prompt for this step with the given parameters.
     pub fn format(&self, parameters: &Parameters) -> Result<Prompt, StringTemplateError> {
         self.prompt.format(parameters)
@@ -61,7 +139,7 @@ impl Step {
     /// Executes the step with the given parameters and executor.
     /// # Arguments
     /// * `parameters` - A `Parameters` object containing the input parameters for the step.
-    /// * `executor` - An executor to use to execute the step.
+    /// * `executor` - An executor to use to execute the step. Either an `Executor` or a `dyn DynExecutor`.
     /// # Returns
     /// The output of the executor.
     pub async fn run<E>(
@@ -71,10 +149,60 @@ impl Step {
     ) -> Result<Output, FormatAndExecuteError>
     where
         Self: Sized,
-        E: Executor,
+        E: DynExecutor + ?Sized,
     {
         Frame::new(executor, self)
             .format_and_execute(parameters)
             .await
     }
+
+    /// Executes the step and parses the answer into a `T`.
+    ///
+    /// A description of the expected format, taken from `T::describe`, is appended to the prompt.
+    /// If the answer can't be parsed, the model is asked again with the parse error, up to
+    /// `structured::DEFAULT_MAX_RETRIES` times. See the `structured` module for details.
+    pub async fn run_typed<T, E>(
+        &self,
+        parameters: &Parameters,
+        executor: &E,
+    ) -> Result<TypedOutput<T>, StructuredOutputError>
+    where
+        T: DeserializeOwned + Describe,
+        E: DynExecutor + ?Sized,
+    {
+        self.run_typed_with_retries(parameters, executor, structured::DEFAULT_MAX_RETRIES)
+            .await
+    }
+
+    /// Like `run_typed`, but asks again at most `max_retries` times.
+    pub async fn run_typed_with_retries<T, E>(
+        &self,
+        parameters: &Parameters,
+        executor: &E,
+        max_retries: usize,
+    ) -> Result<TypedOutput<T>, StructuredOutputError>
+    where
+        T: DeserializeOwned + Describe,
+        E: DynExecutor + ?Sized,
+    {
+        let prompt = self
+            .format(parameters)
+            .map_err(FormatAndExecuteError::from)?;
+        structured::execute_typed(executor, &self.options, &prompt, max_retries).await
+    }
+}
+
+/// A problem with a chain found before running it, so that it doesn't fail after some steps were
+/// already paid for.
+#[derive(thiserror::Error, Debug)]
+pub enum ValidationError {
+    /// The prompt of the step at index `step` needs parameters that won't be set.
+    #[error("step {step} needs parameters that aren't set: {}", .missing.join(", "))]
+    MissingParameters { step: usize, missing: Vec<String> },
+    /// The prompt of the step at index `step` can't be parsed.
+    #[error("the prompt of step {step} is invalid: {source}")]
+    InvalidTemplate {
+        step: usize,
+        source: StringTemplateError,
+    },
 }
@@ -0,0 +1,280 @@
+//! Typed structured output.
+//!
+//! This module runs a prompt and deserializes the answer into a Rust type. The expected format,
+//! derived from the type's [`Describe`] implementation, is appended to the prompt, and the answer
+//! is parsed from a code block or JSON in the prose if there is one, or else from the whole answer
+//! read as YAML. When the answer can't be parsed, the model is shown its answer and the parse error
+//! and asked to try again, up to a chosen number of times. The options are checked against the
+//! capabilities of the executor before the first call, as `Frame::format_and_execute` does.
+//!
+//! Most of the time this is used through [`Step::run_typed`](crate::step::Step::run_typed), or
+//! [`Data::<StringTemplate>::run_typed`](crate::prompt::Data::run_typed) for a prompt without
+//! options.
+//!
+//! ```ignore
+//! #[derive(serde::Deserialize)]
+//! struct Sentiment {
+//!     label: String,
+//!     confidence: f32,
+//! }
+//!
+//! impl Describe for Sentiment {
+//!     fn describe() -> Format {
+//!         vec![
+//!             ("label", "positive, negative or neutral").into(),
+//!             ("confidence", "a number between 0 and 1").into(),
+//!         ]
+//!         .into()
+//!     }
+//! }
+//!
+//! let typed = step.run_typed::<Sentiment, _>(&parameters, &exec).await?;
+//! println!("{} ({})", typed.value.label, typed.raw);
+//! ```
+
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::frame::{check_options, FormatAndExecuteError};
+use crate::options::Options;
+use crate::parsing::{find_yaml_or_json, ExtractionError};
+use crate::prompt::{Data, Prompt, PromptTemplate};
+use crate::tools::Describe;
+use crate::traits::{DynExecutor, ExecutorError};
+use crate::Parameters;
+
+/// The number of times the model is asked again when its answer can't be parsed, by default.
+pub const DEFAULT_MAX_RETRIES: usize = 2;
+
+/// A value parsed from the output of a model.
+#[derive(Debug, Clone)]
+pub struct TypedOutput<T> {
+    /// The parsed value.
+    pub value: T,
+    /// The text of the answer the value was parsed from.
+    pub raw: String,
+    /// The number of calls it took, 1 if the first answer could be parsed.
+    pub attempts: usize,
+}
+
+/// An error running a prompt for a typed value.
+#[derive(Debug, Error)]
+pub enum StructuredOutputError {
+    #[error(transparent)]
+    Run(#[from] FormatAndExecuteError),
+    /// None of the answers could be parsed. `raw` is the last answer.
+    #[error("the answer couldn't be parsed after {attempts} attempts: {error}")]
+    Parse {
+        error: ExtractionError,
+        raw: String,
+        attempts: usize,
+    },
+}
+
+impl From<ExecutorError> for StructuredOutputError {
+    fn from(err: ExecutorError) -> Self {
+        Self::Run(err.into())
+    }
+}
+
+/// Returns the instructions appended to a prompt to ask for a value of type `T`.
+pub fn format_instructions<T: Describe>() -> String {
+    let format = serde_yaml::to_string(&T::describe()).unwrap_or_default();
+    format!(
+        "Answer with a YAML object with the following keys, each described by its purpose, and nothing else:\n```yaml\n{}```",
+        format
+    )
+}
+
+/// Executes `prompt` with the format description for `T` appended, and parses the answer into a
+/// `T`. Answers that can't be parsed are sent back to the model with the parse error, at most
+/// `max_retries` times. Options the executor rejects fail before the first call.
+pub async fn execute_typed<T, E>(
+    executor: &E,
+    options: &Options,
+    prompt: &Prompt,
+    max_retries: usize,
+) -> Result<TypedOutput<T>, StructuredOutputError>
+where
+    T: DeserializeOwned + Describe,
+    E: DynExecutor + ?Sized,
+{
+    check_options(executor, options)?;
+    let mut prompt = prompt.combine(&Data::text(format_instructions::<T>()));
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let output = executor.execute(options, &prompt).await?;
+        // The body of the answer, without the role of a chat answer.
+        let raw = output
+            .to_immediate()
+            .await?
+            .primary_textual_output()
+            .unwrap_or_default();
+        let error = match find_yaml_or_json::<T>(&raw) {
+            Ok(value) => {
+                return Ok(TypedOutput {
+                    value,
+                    raw,
+                    attempts,
+                });
+            }
+            Err(error) => error,
+        };
+        if attempts > max_retries {
+            return Err(StructuredOutputError::Parse {
+                error,
+                raw,
+                attempts,
+            });
+        }
+        let chat = prompt.to_chat().with_assistant(raw).with_user(format!(
+            "Your answer couldn't be parsed: {}\nAnswer again, following the format described above.",
+            error
+        ));
+        prompt = Data::Chat(chat);
+    }
+}
+
+impl PromptTemplate {
+    /// Formats the template with `parameters`, executes it with the default options of `executor`
+    /// and parses the answer into a `T`, like `Step::run_typed`.
+    pub async fn run_typed<T, E>(
+        &self,
+        parameters: &Parameters,
+        executor: &E,
+    ) -> Result<TypedOutput<T>, StructuredOutputError>
+    where
+        T: DeserializeOwned + Describe,
+        E: DynExecutor + ?Sized,
+    {
+        self.run_typed_with_retries(parameters, executor, DEFAULT_MAX_RETRIES)
+            .await
+    }
+
+    /// Like `run_typed`, but asks again at most `max_retries` times.
+    pub async fn run_typed_with_retries<T, E>(
+        &self,
+        parameters: &Parameters,
+        executor: &E,
+        max_retries: usize,
+    ) -> Result<TypedOutput<T>, StructuredOutputError>
+    where
+        T: DeserializeOwned + Describe,
+        E: DynExecutor + ?Sized,
+    {
+        let prompt = self
+            .format(parameters)
+            .map_err(FormatAndExecuteError::from)?;
+        execute_typed(executor, Options::empty(), &prompt, max_retries).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::options;
+    use crate::options::{Capabilities, OptDiscriminants};
+    use crate::prompt::{ChatMessageCollection, StringTemplate};
+    use crate::step::Step;
+    use crate::test_util::TestExecutor;
+    use crate::tools::Format;
+
+    #[derive(Debug, Deserialize)]
+    struct Sentiment {
+        label: String,
+        confidence: f32,
+    }
+
+    impl Describe for Sentiment {
+        fn describe() -> Format {
+            vec![
+                ("label", "positive, negative or neutral").into(),
+                ("confidence", "a number between 0 and 1").into(),
+            ]
+            .into()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reasks_until_the_answer_parses() {
+        let exec = TestExecutor::scripted(vec![
+            Data::text("It is positive!".to_string()),
+            Data::text("```json\n{\"label\": \"positive\", \"confidence\": 0.9}\n```".to_string()),
+        ]);
+        let prompt = Data::text("I love it".to_string());
+        let typed: TypedOutput<Sentiment> = execute_typed(&exec, Options::empty(), &prompt, 1)
+            .await
+            .unwrap();
+        assert_eq!(typed.value.label, "positive");
+        assert!((typed.value.confidence - 0.9).abs() < 1e-6);
+        assert_eq!(typed.attempts, 2);
+
+        let prompts = exec.prompts();
+        assert!(prompts[0].to_text().contains("confidence"));
+        let retry = prompts[1].to_chat();
+        assert_eq!(retry.len(), 3);
+        assert!(retry
+            .last_message()
+            .unwrap()
+            .body()
+            .contains("couldn't be parsed"));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let exec = TestExecutor::scripted(vec![
+            Data::text("no".to_string()),
+            Data::text("still no".to_string()),
+        ]);
+        let prompt = Data::text("I love it".to_string());
+        let res = execute_typed::<Sentiment, _>(&exec, Options::empty(), &prompt, 1).await;
+        match res {
+            Err(StructuredOutputError::Parse { raw, attempts, .. }) => {
+                assert_eq!(raw, "still no");
+                assert_eq!(attempts, 2);
+            }
+            other => panic!("unexpected result: {:?}", other.map(|t| t.raw)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parses_the_body_of_chat_answers() {
+        let answer =
+            |body: &str| Data::Chat(ChatMessageCollection::new().with_assistant(body.to_string()));
+        let exec = TestExecutor::scripted(vec![
+            answer("label: [positive"),
+            answer("label: positive\nconfidence: 0.9"),
+        ]);
+        let template = Data::text(StringTemplate::tera("Rate {{ text }}"));
+        let typed: TypedOutput<Sentiment> = template.run_typed(&"it".into(), &exec).await.unwrap();
+        assert_eq!(typed.value.label, "positive");
+        assert_eq!(typed.raw, "label: positive\nconfidence: 0.9");
+
+        let prompts = exec.prompts();
+        let retry = prompts[1].to_chat();
+        assert_eq!(retry.iter().nth(1).unwrap().body(), "label: [positive");
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_option_values_fail_before_executing() {
+        let exec = TestExecutor::default().with_capabilities(Capabilities::new().supports_between(
+            OptDiscriminants::Temperature,
+            0.0,
+            2.0,
+        ));
+        let step = Step::for_prompt_and_options(
+            Data::text(StringTemplate::tera("Rate {{ text }}")),
+            options!(Temperature: 3.0),
+        );
+        let res = step.run_typed::<Sentiment, _>(&"it".into(), &exec).await;
+        assert!(matches!(
+            res,
+            Err(StructuredOutputError::Run(FormatAndExecuteError::InvalidOption(issue)))
+                if issue.option == OptDiscriminants::Temperature
+        ));
+        assert_eq!(exec.calls(), 0);
+    }
+}
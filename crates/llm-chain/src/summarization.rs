@@ -47,7 +47,7 @@ impl TextSummarizer {
     /// Summarizes the given text using the provided `Executor`.
     ///
     /// Returns the summarized text, or an error if the summarization process fails.
-    pub async fn summarize_text<E: traits::Executor>(
+    pub async fn summarize_text<E: traits::DynExecutor + ?Sized>(
         &self,
         exec: &E,
         text: &str,
@@ -72,7 +72,7 @@ impl TextSummarizer {
 /// A convenience function to summarize text using the provided `Executor`.
 ///
 /// Returns the summarized text, or an error if the summarization process fails.
-pub async fn summarize_text<E: traits::Executor>(
+pub async fn summarize_text<E: traits::DynExecutor + ?Sized>(
     exec: &E,
     text: &str,
 ) -> Result<String, TextSummarizerError> {
@@ -0,0 +1,197 @@
+//! Executors and tokenizers shared by the tests of the crate.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::options::{Capabilities, Options};
+use crate::output::Output;
+use crate::prompt::{Data, Prompt};
+use crate::tokens::{PromptTokensError, TokenCollection, TokenCount, Tokenizer, TokenizerError};
+use crate::traits::{Executor, ExecutorCreationError, ExecutorError};
+
+/// A tokenizer for tests: `Null` finds no tokens, `Chars` one per character and `Words` one per
+/// word.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TestTokenizer {
+    #[default]
+    Null,
+    Chars,
+    Words,
+}
+
+impl Tokenizer for TestTokenizer {
+    fn tokenize_str(&self, doc: &str) -> Result<TokenCollection, TokenizerError> {
+        let tokens: Vec<i32> = match self {
+            TestTokenizer::Null => Vec::new(),
+            TestTokenizer::Chars => doc.chars().map(|c| c as i32).collect(),
+            TestTokenizer::Words => doc.split_whitespace().map(|_| 0).collect(),
+        };
+        Ok(tokens.into())
+    }
+
+    fn to_string(&self, tokens: TokenCollection) -> Result<String, TokenizerError> {
+        match self {
+            TestTokenizer::Null => Ok(String::new()),
+            TestTokenizer::Chars => Ok(tokens
+                .as_i32()?
+                .into_iter()
+                .filter_map(|t| char::from_u32(t as u32))
+                .collect()),
+            TestTokenizer::Words => Err(TokenizerError::ToStringError),
+        }
+    }
+}
+
+/// An executor that fails with the queued errors, in order, before answering with the scripted
+/// replies, in order, and then with its reply, or the prompt when echoing. It records the prompts
+/// it receives and counts their tokens with its tokenizer.
+pub struct TestExecutor {
+    failures: Mutex<VecDeque<ExecutorError>>,
+    replies: Mutex<VecDeque<Prompt>>,
+    reply: String,
+    echo: bool,
+    fail_on: Option<String>,
+    max_tokens: i32,
+    tokenizer: TestTokenizer,
+    capabilities: Capabilities,
+    answer_prefix: Option<String>,
+    calls: AtomicUsize,
+    prompts: Mutex<Vec<Prompt>>,
+}
+
+impl Default for TestExecutor {
+    fn default() -> Self {
+        Self {
+            failures: Mutex::new(VecDeque::new()),
+            replies: Mutex::new(VecDeque::new()),
+            reply: "ok".to_string(),
+            echo: false,
+            fail_on: None,
+            max_tokens: 100,
+            tokenizer: TestTokenizer::Null,
+            capabilities: Capabilities::any(),
+            answer_prefix: None,
+            calls: AtomicUsize::new(0),
+            prompts: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl TestExecutor {
+    pub fn failing_with(failures: Vec<ExecutorError>) -> Self {
+        Self {
+            failures: Mutex::new(failures.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn replying(reply: &str, max_tokens: i32) -> Self {
+        Self {
+            reply: reply.to_string(),
+            max_tokens,
+            ..Default::default()
+        }
+    }
+
+    pub fn scripted(replies: Vec<Prompt>) -> Self {
+        Self {
+            replies: Mutex::new(replies.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Answers every prompt with its text.
+    pub fn echoing() -> Self {
+        Self {
+            echo: true,
+            ..Default::default()
+        }
+    }
+
+    /// Fails with `ExecutorError::InvalidOptions` for the prompts containing `text`.
+    pub fn failing_on(mut self, text: &str) -> Self {
+        self.fail_on = Some(text.to_string());
+        self
+    }
+
+    pub fn with_tokenizer(mut self, tokenizer: TestTokenizer) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_answer_prefix(mut self, prefix: &str) -> Self {
+        self.answer_prefix = Some(prefix.to_string());
+        self
+    }
+
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    pub fn prompts(&self) -> Vec<Prompt> {
+        self.prompts.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Executor for TestExecutor {
+    type StepTokenizer<'a> = TestTokenizer;
+
+    fn new_with_options(_: Options) -> Result<Self, ExecutorCreationError> {
+        Ok(Self::default())
+    }
+
+    async fn execute(&self, _: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        self.prompts.lock().unwrap().push(prompt.clone());
+        if let Some(err) = self.failures.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+        let text = prompt.to_text();
+        if matches!(&self.fail_on, Some(fail_on) if text.contains(fail_on.as_str())) {
+            return Err(ExecutorError::InvalidOptions);
+        }
+        if self.echo {
+            return Ok(Output::new_immediate(Data::text(text)));
+        }
+        let reply = self
+            .replies
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Data::text(self.reply.clone()));
+        Ok(Output::new_immediate(reply))
+    }
+
+    fn tokens_used(&self, _: &Options, prompt: &Prompt) -> Result<TokenCount, PromptTokensError> {
+        let tokens = self
+            .tokenizer
+            .tokenize_str(&prompt.to_text())
+            .map_err(|_| PromptTokensError::UnableToCompute)?;
+        Ok(TokenCount::new(self.max_tokens, tokens.len() as i32))
+    }
+
+    fn max_tokens_allowed(&self, _: &Options) -> i32 {
+        self.max_tokens
+    }
+
+    fn answer_prefix(&self, _: &Prompt) -> Option<String> {
+        self.answer_prefix.clone()
+    }
+
+    fn get_tokenizer(&self, _: &Options) -> Result<TestTokenizer, TokenizerError> {
+        Ok(self.tokenizer)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities.clone()
+    }
+}
@@ -26,9 +26,9 @@ pub enum PromptTokensError {
     TokenizerError(#[from] crate::tokens::TokenizerError),
 }
 
-/// An extension trait for the `Executor` trait that provides additional methods for working
-/// with token counts.
-pub trait ExecutorTokenCountExt: traits::Executor {
+/// An extension trait for executors that provides additional methods for working with token
+/// counts. It is implemented for every `Executor` and for `dyn DynExecutor`.
+pub trait ExecutorTokenCountExt: traits::DynExecutor {
     /// Splits a `Parameters` object into multiple smaller `Parameters` objects that fit within
     /// the context window size supported by the given model.
     ///
@@ -72,7 +72,7 @@ pub trait ExecutorTokenCountExt: traits::Executor {
 }
 
 /// Blanket implementation of ExecutorTokenCountExt for all Executors
-impl<E: traits::Executor> ExecutorTokenCountExt for E {}
+impl<E: traits::DynExecutor + ?Sized> ExecutorTokenCountExt for E {}
 
 /// Struct representing token count information, including the maximum tokens allowed and the
 /// total number of tokens used.
@@ -96,6 +96,11 @@ impl TokenCount {
         }
     }
 
+    /// Returns the number of tokens used.
+    pub fn tokens_used(&self) -> i32 {
+        self.tokens_used
+    }
+
     /// Returns the number of tokens that could be added to the context window.
     pub fn tokens_remaining(&self) -> i32 {
         self.max_tokens - self.tokens_used
@@ -182,6 +187,20 @@ pub trait Tokenizer {
             .collect()
     }
 }
+
+/// A type-erased tokenizer, as returned by `DynExecutor::get_tokenizer`.
+pub type DynTokenizer<'a> = Box<dyn Tokenizer + 'a>;
+
+impl<T: Tokenizer + ?Sized> Tokenizer for Box<T> {
+    fn tokenize_str(&self, doc: &str) -> Result<TokenCollection, TokenizerError> {
+        (**self).tokenize_str(doc)
+    }
+
+    fn to_string(&self, tokens: TokenCollection) -> Result<String, TokenizerError> {
+        (**self).to_string(tokens)
+    }
+}
+
 /// Represents a single token.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(transparent)]
@@ -260,6 +279,14 @@ impl TokenCollection {
         }
     }
 
+    /// Converts the `TokenCollection` into a vector of `Token`, whatever the type of its values.
+    pub fn into_tokens(self) -> Vec<Token> {
+        match self.0 {
+            TokenCollectionImpl::I32(v) => v.into_iter().map(Token::from).collect(),
+            TokenCollectionImpl::Usize(v) => v.into_iter().map(Token::from).collect(),
+        }
+    }
+
     /// Returns the number of tokens in the token collection
     pub fn len(&self) -> usize {
         match &self.0 {
@@ -1,6 +1,8 @@
+use super::description::ToolDescription;
 use super::tool::{Tool, ToolError};
+use crate::options::Opt;
 use crate::parsing::{find_yaml, ExtractionError};
-use crate::prompt::StringTemplate;
+use crate::prompt::{ChatMessage, StringTemplate, ToolCall};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -86,6 +88,52 @@ where
         serde_yaml::to_string(&output).map_err(|e| e.into())
     }
 
+    /// Invokes the tool requested by a native tool call.
+    ///
+    /// The arguments of the call are parsed as the tool input, and the output is returned as a
+    /// tool message answering the call, ready to be appended to the conversation.
+    pub async fn invoke_tool_call(
+        &self,
+        call: &ToolCall,
+    ) -> Result<ChatMessage<String>, ToolUseError<<T as Tool>::Error>> {
+        // The arguments are JSON, which is a subset of YAML.
+        let input: serde_yaml::Value = serde_yaml::from_str(&call.arguments)?;
+        let output = self.invoke(&call.name, &input).await?;
+        Ok(ChatMessage::tool_result(
+            call.id.clone(),
+            serde_yaml::to_string(&output)?,
+        ))
+    }
+
+    /// Invokes every tool call in a message returned by the model.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ToolUseError::NoToolInvocation` if the message doesn't contain any tool calls.
+    pub async fn process_tool_calls(
+        &self,
+        message: &ChatMessage<String>,
+    ) -> Result<Vec<ChatMessage<String>>, ToolUseError<<T as Tool>::Error>> {
+        if message.tool_calls().is_empty() {
+            return Err(ToolUseError::NoToolInvocation);
+        }
+        let mut results = Vec::with_capacity(message.tool_calls().len());
+        for call in message.tool_calls() {
+            results.push(self.invoke_tool_call(call).await?);
+        }
+        Ok(results)
+    }
+
+    /// Returns the descriptions of the tools in the collection.
+    pub fn descriptions(&self) -> Vec<ToolDescription> {
+        self.tools.iter().map(|t| t.description()).collect()
+    }
+
+    /// Returns an option offering the tools to executors with native tool calling.
+    pub fn to_option(&self) -> Opt {
+        Opt::Tools(self.descriptions())
+    }
+
     /// Generate a YAML-formatted string describing the available tools.
     pub fn describe(&self) -> Result<String, ToolUseError<<T as Tool>::Error>> {
         let des: Vec<_> = self.tools.iter().map(|t| t.description()).collect();
@@ -107,3 +155,130 @@ pub struct ToolInvocationInput {
     pub command: String,
     pub input: serde_yaml::Value,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::ChatRole;
+    use crate::tools::description::{Describe, Format};
+    use async_trait::async_trait;
+
+    #[derive(Deserialize)]
+    struct AddInput {
+        a: i64,
+        b: i64,
+    }
+
+    impl Describe for AddInput {
+        fn describe() -> Format {
+            vec![("a", "<integer>").into(), ("b", "<integer>").into()].into()
+        }
+    }
+
+    #[derive(Serialize)]
+    struct AddOutput {
+        sum: i64,
+    }
+
+    #[derive(Debug, Error)]
+    #[error(transparent)]
+    struct AddError(#[from] serde_yaml::Error);
+
+    impl ToolError for AddError {}
+
+    struct AddTool;
+
+    #[async_trait]
+    impl Tool for AddTool {
+        type Input = AddInput;
+        type Output = AddOutput;
+        type Error = AddError;
+
+        async fn invoke_typed(&self, input: &AddInput) -> Result<AddOutput, AddError> {
+            Ok(AddOutput {
+                sum: input.a + input.b,
+            })
+        }
+
+        fn description(&self) -> ToolDescription {
+            ToolDescription::new(
+                "add",
+                "Adds two integers",
+                "",
+                AddInput::describe(),
+                vec![].into(),
+            )
+        }
+    }
+
+    fn collection() -> ToolCollection<AddTool> {
+        let mut tools = ToolCollection::new();
+        tools.add_tool(AddTool);
+        tools
+    }
+
+    fn call(id: &str, name: &str, arguments: &str) -> ToolCall {
+        ToolCall::new(id.to_string(), name.to_string(), arguments.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_invokes_tool_calls() {
+        let tools = collection();
+        let result = tools
+            .invoke_tool_call(&call("call-1", "add", r#"{"a": 2, "b": 3}"#))
+            .await
+            .unwrap();
+        assert_eq!(result.role(), &ChatRole::Tool);
+        assert_eq!(result.tool_call_id(), Some("call-1"));
+        assert_eq!(result.body(), "sum: 5\n");
+
+        let message = ChatMessage::new(ChatRole::Assistant, String::new()).with_tool_calls(vec![
+            call("call-1", "add", r#"{"a": 1, "b": 1}"#),
+            call("call-2", "add", r#"{"a": 4, "b": -1}"#),
+        ]);
+        let results = tools.process_tool_calls(&message).await.unwrap();
+        let ids: Vec<_> = results.iter().map(|r| r.tool_call_id()).collect();
+        assert_eq!(ids, vec![Some("call-1"), Some("call-2")]);
+        assert_eq!(results[1].body(), "sum: 3\n");
+
+        let message = ChatMessage::new(ChatRole::Assistant, "no calls".to_string());
+        assert!(matches!(
+            tools.process_tool_calls(&message).await,
+            Err(ToolUseError::NoToolInvocation)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reports_bad_tool_calls() {
+        let tools = collection();
+        assert!(matches!(
+            tools
+                .invoke_tool_call(&call("call-1", "subtract", r#"{"a": 2, "b": 3}"#))
+                .await,
+            Err(ToolUseError::ToolNotFound)
+        ));
+        assert!(matches!(
+            tools
+                .invoke_tool_call(&call("call-1", "add", r#"{"a": 2, "b":"#))
+                .await,
+            Err(ToolUseError::InvalidFormat(_))
+        ));
+        // A failing call stops the processing of the message.
+        let message = ChatMessage::new(ChatRole::Assistant, String::new()).with_tool_calls(vec![
+            call("call-1", "add", r#"{"a": 1, "b": 1}"#),
+            call("call-2", "add", "{"),
+        ]);
+        assert!(tools.process_tool_calls(&message).await.is_err());
+    }
+
+    #[test]
+    fn test_to_option_describes_the_tools() {
+        match collection().to_option() {
+            Opt::Tools(descriptions) => {
+                assert_eq!(descriptions.len(), 1);
+                assert_eq!(descriptions[0].name, "add");
+            }
+            other => panic!("unexpected option: {:?}", other),
+        }
+    }
+}
@@ -1,4 +1,10 @@
-use serde::{ser::SerializeMap, Serialize, Serializer};
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::json;
+use std::fmt;
 
 /// Represents a single parameter for a tool.
 #[derive(Clone, Debug)]
@@ -24,7 +30,7 @@ impl<K: Into<String>, P: Into<String>> From<(K, P)> for FormatPart {
 }
 
 /// Represents the format for a tool's input or output.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Format {
     pub parts: Vec<FormatPart>,
 }
@@ -56,13 +62,43 @@ impl Serialize for Format {
     }
 }
 
+impl<'de> Deserialize<'de> for Format {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FormatVisitor;
+
+        impl<'de> Visitor<'de> for FormatVisitor {
+            type Value = Format;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map of keys to their purpose")
+            }
+
+            fn visit_map<A>(self, mut access: A) -> Result<Format, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut parts = Vec::new();
+                while let Some((key, purpose)) = access.next_entry::<String, String>()? {
+                    parts.push(FormatPart { key, purpose });
+                }
+                Ok(Format::new(parts))
+            }
+        }
+
+        deserializer.deserialize_map(FormatVisitor)
+    }
+}
+
 /// A trait to provide a description format for a tool.
 pub trait Describe {
     fn describe() -> Format;
 }
 
 /// Represents the description of a tool, including its name, usage, and input/output formats.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ToolDescription {
     pub name: String,
     pub description: String,
@@ -91,4 +127,27 @@ impl ToolDescription {
             output_format,
         }
     }
+
+    /// Returns the input format as a JSON Schema object.
+    ///
+    /// Backends with native tool calling declare the parameters of a tool this way.
+    pub fn input_json_schema(&self) -> serde_json::Value {
+        let properties: serde_json::Map<String, serde_json::Value> = self
+            .input_format
+            .parts
+            .iter()
+            .map(|part| (part.key.clone(), json!({ "description": part.purpose })))
+            .collect();
+        let required: Vec<&str> = self
+            .input_format
+            .parts
+            .iter()
+            .map(|part| part.key.as_str())
+            .collect();
+        json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        })
+    }
 }
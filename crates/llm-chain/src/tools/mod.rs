@@ -8,6 +8,8 @@
 //! - `ToolCollection`: A collection of `Tool` instances.
 //! - `create_tool_prompt_segment`: A function to create a prompt that indicates the model should use the provided tools.
 //!
+//! Tools can be offered to the model in two ways. The prompt returned by `ToolCollection::to_prompt_template` asks the model to answer with a YAML invocation, which `ToolCollection::process_chat_input` parses. Executors with native tool calling instead accept the option returned by `ToolCollection::to_option` and report the calls on the returned `ChatMessage`, which `ToolCollection::process_tool_calls` dispatches.
+//!
 //! ## Example
 //!
 //! ```rust
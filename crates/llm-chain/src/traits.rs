@@ -12,13 +12,14 @@
 use std::{error::Error, fmt::Debug};
 
 use crate::{
-    options::Options,
+    options::{Capabilities, Options},
     output::Output,
     prompt::Prompt,
     schema::{Document, EmptyMetadata},
-    tokens::{PromptTokensError, TokenCount, Tokenizer, TokenizerError},
+    tokens::{DynTokenizer, PromptTokensError, TokenCount, Tokenizer, TokenizerError},
 };
 use async_trait::async_trait;
+use futures::StreamExt;
 
 #[derive(thiserror::Error, Debug)]
 #[error("unable to create executor")]
@@ -43,6 +44,37 @@ pub enum ExecutorError {
     PromptTokens(PromptTokensError),
     #[error("the context was to small to fit your input")]
     ContextTooSmall,
+    #[error("Rate limited by the model provider: {0}")]
+    /// The model provider rejected the call because a rate limit was hit. The call may be retried.
+    RateLimited(Box<dyn Error + Send + Sync>),
+    #[error("Network error: {0}")]
+    /// A transient network failure, such as a refused or dropped connection. The call may be retried.
+    Network(Box<dyn Error + Send + Sync>),
+    #[error("The model provider failed: {0}")]
+    /// The model provider failed on its side, e.g. with a 5xx status. The call may be retried.
+    Server(Box<dyn Error + Send + Sync>),
+    #[error("The model did not respond in time")]
+    /// The call did not complete within the allotted time. The call may be retried.
+    Timeout,
+    #[error("The output stream was cancelled")]
+    /// The output stream was cancelled before it was read to the end.
+    Cancelled,
+}
+
+impl ExecutorError {
+    /// Returns true if the error is transient, meaning the same call may succeed if retried.
+    ///
+    /// Rate limits, network failures, server failures and timeouts are retryable; everything else
+    /// would fail the same way again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ExecutorError::RateLimited(_)
+                | ExecutorError::Network(_)
+                | ExecutorError::Server(_)
+                | ExecutorError::Timeout
+        )
+    }
 }
 
 #[async_trait]
@@ -64,6 +96,35 @@ pub trait Executor: Sized {
 
     async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError>;
 
+    /// Executes several prompts with the same options, running at most `concurrency` calls at
+    /// once.
+    ///
+    /// Returns one result per prompt, in the order of `prompts`, so a failed call doesn't affect
+    /// the others. The default implementation calls `execute` for every prompt. Backends with a
+    /// native batch API can override it with something more efficient.
+    ///
+    /// # Parameters
+    ///
+    /// * `options`: The per-invocation options, shared by all prompts.
+    /// * `prompts`: The prompts to execute.
+    /// * `concurrency`: The maximum number of calls in flight, at least one is always allowed.
+    async fn execute_batch(
+        &self,
+        options: &Options,
+        prompts: &[Prompt],
+        concurrency: usize,
+    ) -> Vec<Result<Output, ExecutorError>> {
+        // Futures do nothing until polled, so only `concurrency` calls are started at a time.
+        let calls: Vec<_> = prompts
+            .iter()
+            .map(|prompt| self.execute(options, prompt))
+            .collect();
+        futures::stream::iter(calls)
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
     /// Calculates the number of tokens used by the step given a set of parameters.
     ///
     /// The step and the parameters together are used to form full prompt, which is then tokenized
@@ -114,6 +175,108 @@ pub trait Executor: Sized {
     ///
     /// A `Result` containing a tokenizer, or an error if there was a problem.
     fn get_tokenizer(&self, options: &Options) -> Result<Self::StepTokenizer<'_>, TokenizerError>;
+
+    /// Describes the options the executor uses and the values it accepts for them, so options
+    /// can be checked with `Capabilities::validate` before execution.
+    ///
+    /// The default implementation accepts every option.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::any()
+    }
+}
+
+#[async_trait]
+/// An object-safe companion to the `Executor` trait.
+///
+/// `Executor` cannot be used as a trait object because it has a constructor and a generic
+/// tokenizer type. `DynExecutor` exposes the same runtime behavior with the tokenizer boxed, so
+/// executors can be stored as `Box<dyn DynExecutor>` and chosen at runtime, e.g. from
+/// configuration. Every `Executor` implements `DynExecutor` through a blanket implementation, and
+/// the steps and chains accept either form.
+///
+/// # Example
+///
+/// ```ignore
+/// let executor: Box<dyn DynExecutor> = if use_llama {
+///     Box::new(llm_chain_llama::Executor::new()?)
+/// } else {
+///     Box::new(llm_chain_openai::chatgpt::Executor::new()?)
+/// };
+/// let res = step.run(&parameters, executor.as_ref()).await?;
+/// ```
+pub trait DynExecutor: Send + Sync {
+    /// Executes the prompt with the given options. See `Executor::execute`.
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError>;
+
+    /// Executes several prompts with bounded concurrency. See `Executor::execute_batch`.
+    async fn execute_batch(
+        &self,
+        options: &Options,
+        prompts: &[Prompt],
+        concurrency: usize,
+    ) -> Vec<Result<Output, ExecutorError>>;
+
+    /// Calculates the number of tokens used by the prompt. See `Executor::tokens_used`.
+    fn tokens_used(
+        &self,
+        options: &Options,
+        prompt: &Prompt,
+    ) -> Result<TokenCount, PromptTokensError>;
+
+    /// Returns the maximum number of input tokens allowed. See `Executor::max_tokens_allowed`.
+    fn max_tokens_allowed(&self, options: &Options) -> i32;
+
+    /// Returns a possible answer prefix inserted by the model. See `Executor::answer_prefix`.
+    fn answer_prefix(&self, prompt: &Prompt) -> Option<String>;
+
+    /// Creates a type-erased tokenizer. See `Executor::get_tokenizer`.
+    fn get_tokenizer(&self, options: &Options) -> Result<DynTokenizer<'_>, TokenizerError>;
+
+    /// Describes the supported options. See `Executor::capabilities`.
+    fn capabilities(&self) -> Capabilities;
+}
+
+#[async_trait]
+impl<E> DynExecutor for E
+where
+    E: Executor + Send + Sync,
+{
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        Executor::execute(self, options, prompt).await
+    }
+
+    async fn execute_batch(
+        &self,
+        options: &Options,
+        prompts: &[Prompt],
+        concurrency: usize,
+    ) -> Vec<Result<Output, ExecutorError>> {
+        Executor::execute_batch(self, options, prompts, concurrency).await
+    }
+
+    fn tokens_used(
+        &self,
+        options: &Options,
+        prompt: &Prompt,
+    ) -> Result<TokenCount, PromptTokensError> {
+        Executor::tokens_used(self, options, prompt)
+    }
+
+    fn max_tokens_allowed(&self, options: &Options) -> i32 {
+        Executor::max_tokens_allowed(self, options)
+    }
+
+    fn answer_prefix(&self, prompt: &Prompt) -> Option<String> {
+        Executor::answer_prefix(self, prompt)
+    }
+
+    fn get_tokenizer(&self, options: &Options) -> Result<DynTokenizer<'_>, TokenizerError> {
+        Ok(Box::new(Executor::get_tokenizer(self, options)?))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Executor::capabilities(self)
+    }
 }
 
 /// This marker trait is needed so the concrete VectorStore::Error can have a derived From<Embeddings::Error>
@@ -144,3 +307,55 @@ where
         limit: u32,
     ) -> Result<Vec<Document<M>>, Self::Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::step::Step;
+    use crate::test_util::{TestExecutor, TestTokenizer};
+    use crate::{prompt, Parameters};
+
+    fn echo_executor() -> TestExecutor {
+        TestExecutor::echoing()
+            .failing_on("fail")
+            .with_tokenizer(TestTokenizer::Chars)
+    }
+
+    #[tokio::test]
+    async fn test_step_runs_with_boxed_executor() {
+        let executor: Box<dyn DynExecutor> = Box::new(echo_executor());
+        let step = Step::for_prompt_template(prompt!("Hello {{text}}"));
+        let res = step
+            .run(&Parameters::new_with_text("world"), executor.as_ref())
+            .await
+            .unwrap()
+            .to_immediate()
+            .await
+            .unwrap();
+        assert_eq!(
+            res.primary_textual_output(),
+            Some("Hello world".to_string())
+        );
+
+        let tokenizer = executor.get_tokenizer(Options::empty()).unwrap();
+        assert_eq!(tokenizer.tokenize_str("one two three").unwrap().len(), 13);
+    }
+
+    #[tokio::test]
+    async fn test_execute_batch_returns_results_in_order() {
+        let prompts: Vec<_> = ["one", "fail", "three"]
+            .iter()
+            .map(|text| Prompt::text(text.to_string()))
+            .collect();
+        let results =
+            Executor::execute_batch(&echo_executor(), Options::empty(), &prompts, 2).await;
+        let texts: Vec<_> = results
+            .into_iter()
+            .map(|res| res.ok().map(|output| output.to_string()))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![Some("one".to_string()), None, Some("three".to_string())]
+        );
+    }
+}